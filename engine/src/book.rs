@@ -0,0 +1,207 @@
+use std::{
+    fs,
+    io,
+};
+use rand::Rng;
+use types::{
+    position::Position,
+    variant::Variant,
+    Castling,
+    Color,
+    Piece,
+};
+use crate::{
+    movegen,
+    polyglot_keys::POLYGLOT_RANDOM,
+};
+
+/* Support for opening books in the Polyglot format. A book is a sorted list of 16 byte entries: the Polyglot key of
+/ a position, a move, a weight and 4 bytes of learning data, all big endian.
+/ Moves store the target square in bits 0-5, the origin square in bits 6-11 and the promotion piece in bits 12-14,
+/ castling is stored as the king capturing its own rook. */
+
+pub const ENTRY_SIZE: usize = 16;
+
+const CASTLING_OFFSET: usize = 768;
+const EN_PASSANT_OFFSET: usize = 772;
+const TURN_OFFSET: usize = 780;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BookEntry {
+    pub key: u64,
+    pub book_move: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl BookEntry {
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.book_move.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    entries: Vec<BookEntry>,
+}
+
+impl Book {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Polyglot book size is not a multiple of 16 bytes"));
+        }
+
+        let mut entries: Vec<BookEntry> = bytes.chunks_exact(ENTRY_SIZE).map(|chunk| BookEntry {
+            key: u64::from_be_bytes(chunk[0..8].try_into().unwrap()),
+            book_move: u16::from_be_bytes(chunk[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(chunk[10..12].try_into().unwrap()),
+            learn: u32::from_be_bytes(chunk[12..16].try_into().unwrap()),
+        }).collect();
+        // Books should already be sorted by key, sorting anyway keeps unsorted books searchable
+        entries.sort_by_key(|entry| entry.key);
+
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Returns all entries for the position
+    pub fn entries(&self, pos: &Position) -> &[BookEntry] {
+        let key = polyglot_key(pos);
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let end = self.entries.partition_point(|entry| entry.key <= key);
+        &self.entries[start..end]
+    }
+
+    // Returns all legal book moves for the position with their weights
+    pub fn moves(&self, pos: &mut Position) -> Vec<((u8, u8), u16)> {
+        let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+        self.entries(pos).iter()
+            .filter_map(|entry| decode_move(pos, entry.book_move).map(|book_move| (book_move, entry.weight)))
+            .filter(|(book_move, _)| legal_moves.contains(book_move))
+            .collect()
+    }
+
+    /* Returns a move for a game that hasn't left the book depth yet, which is the number of plies played from the start.
+    / Only moves that are legal in the position are returned. Books only contain standard chess. */
+    pub fn book_move(&self, pos: &mut Position, depth: u16, best_move: bool) -> Option<(u8, u8)> {
+        if pos.state.variant != Variant::Standard || pos.move_history.len() >= depth as usize {
+            return None;
+        }
+        self.pick_move(pos, best_move)
+    }
+
+    // Picks the move with the highest weight, or a random move with a probability proportional to its weight
+    pub fn pick_move(&self, pos: &mut Position, best_move: bool) -> Option<(u8, u8)> {
+        let moves = self.moves(pos);
+        if best_move {
+            return moves.iter().max_by_key(|(_, weight)| *weight).map(|&(book_move, _)| book_move);
+        }
+
+        let total_weight: u32 = moves.iter().map(|&(_, weight)| weight as u32).sum();
+        if total_weight == 0 {
+            return moves.first().map(|&(book_move, _)| book_move);
+        }
+        let mut choice = rand::thread_rng().gen_range(0..total_weight);
+        for (book_move, weight) in moves {
+            if choice < weight as u32 {
+                return Some(book_move);
+            }
+            choice -= weight as u32;
+        }
+        None
+    }
+}
+
+/* Converts a Polyglot move to the move representation of this engine. Castling moves are converted from the king
+/ capturing its rook to the engine's castling move, see Position::castling_move(). Underpromotions are skipped since pawns always promote to queens. */
+pub fn decode_move(pos: &Position, book_move: u16) -> Option<(u8, u8)> {
+    let to = (book_move & 0x3F) as u8;
+    let from = ((book_move >> 6) & 0x3F) as u8;
+    let promotion = (book_move >> 12) & 0x7;
+    if promotion != 0 && promotion != 4 {
+        return None;
+    }
+
+    match pos.castling_right_by_rook(from, to) {
+        Some(right) => Some(pos.castling_move(right)),
+        None => Some((from, to)),
+    }
+}
+
+// Converts a move to the Polyglot move format, the inverse of decode_move()
+pub fn encode_move(pos: &Position, from: u8, to: u8) -> u16 {
+    let to = match pos.castling_right_of(from, to) {
+        Some(right) => pos.castling_squares(right).rook_from,
+        None => to,
+    };
+    let is_promotion = pos.piece_bitboards[Piece::PAWN as usize].contains(from) && (to / 8 == 0 || to / 8 == 7);
+    let promotion = if is_promotion { 4 } else { 0 };
+    promotion << 12 | (from as u16) << 6 | to as u16
+}
+
+/* Computes the Polyglot key of a position. The en passant file is only part of the key if a pawn of the side to
+/ move can actually capture en passant. */
+pub fn polyglot_key(pos: &Position) -> u64 {
+    let mut key = 0;
+
+    let mut pieces = pos.all_pieces();
+    while !pieces.is_empty() {
+        let square = pieces.trailing_zeros();
+        let (piece, color) = pos.piece_at(square).unwrap();
+        let kind = match piece {
+            Piece::PAWN => 0,
+            Piece::KNIGHT => 1,
+            Piece::BISHOP => 2,
+            Piece::ROOK => 3,
+            Piece::QUEEN => 4,
+            _ => 5,
+        };
+        let color_offset = if color == Color::White { 1 } else { 0 };
+        key ^= POLYGLOT_RANDOM[64 * (2 * kind + color_offset) + square as usize];
+        pieces.clear_lsb();
+    }
+
+    let castling_rights = [Castling::WHITE_KING_SIDE, Castling::WHITE_QUEEN_SIDE, Castling::BLACK_KING_SIDE, Castling::BLACK_QUEEN_SIDE];
+    for (i, right) in castling_rights.iter().enumerate() {
+        if pos.state.castling_rights.0 & right != 0 {
+            key ^= POLYGLOT_RANDOM[CASTLING_OFFSET + i];
+        }
+    }
+
+    if let Some(ep_square) = pos.en_passant_square {
+        let file = ep_square % 8;
+        let (pawn_rank, color) = match pos.state.active_player {
+            Color::White => (4, 0),
+            Color::Black => (3, 1),
+        };
+        let own_pawns = pos.piece_bitboards[Piece::PAWN as usize] & pos.color_bitboards[color];
+        let capturing_pawn = [-1, 1].iter().any(|&dx| {
+            let capture_file = file as i8 + dx;
+            (0..8).contains(&capture_file) && own_pawns.contains(pawn_rank * 8 + capture_file as u8)
+        });
+        if capturing_pawn {
+            key ^= POLYGLOT_RANDOM[EN_PASSANT_OFFSET + file as usize];
+        }
+    }
+
+    if pos.state.active_player == Color::White {
+        key ^= POLYGLOT_RANDOM[TURN_OFFSET];
+    }
+    key
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+};
+use types::{variant::Variant, Color};
+use crate::{
+    book::{self, BookEntry},
+    game,
+    pgn::{self, PgnGame},
+    san,
+};
+
+/* Builds Polyglot opening books from PGN games. Every move of a game is counted as a win, draw or loss for the side
+/ that played it, and the weight of a book move is 2 * wins + draws like in the original Polyglot book maker.
+/ Moves that never scored anything are left out. */
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct MoveStats {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MoveStats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn weight(&self) -> u32 {
+        2 * self.wins + self.draws
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BookBuilder {
+    // Moves played in fewer games are left out of the book
+    pub min_games: u32,
+    // Only the first max_ply plies of every game are added
+    pub max_ply: usize,
+    // Only games of this player are added, and only the moves this player made
+    pub player: Option<String>,
+    // Only games in which both players have at least this rating are added
+    pub min_elo: Option<u16>,
+    stats: HashMap<(u64, u16), MoveStats>,
+    games: usize,
+}
+
+impl Default for BookBuilder {
+    fn default() -> Self {
+        Self {
+            min_games: 1,
+            max_ply: usize::MAX,
+            player: None,
+            min_elo: None,
+            stats: HashMap::new(),
+            games: 0,
+        }
+    }
+}
+
+impl BookBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the number of games that were added to the book
+    pub fn games(&self) -> usize {
+        self.games
+    }
+
+    // Adds all games of a PGN file and returns how many of them passed the filters
+    pub fn add_file(&mut self, path: &str) -> io::Result<usize> {
+        let games = pgn::read_file(path)?;
+        Ok(games.iter().filter(|game| self.add_game(game)).count())
+    }
+
+    /* Replays a game and counts its moves. Returns false if the game was filtered out.
+    / Games are replayed until the first move that can't be played, since the rest of the game is unreachable.
+    / Games with an invalid FEN tag or of another variant than standard chess are skipped. */
+    pub fn add_game(&mut self, game: &PgnGame) -> bool {
+        let white_score = match game.result.as_str() {
+            "1-0" => 2,
+            "1/2-1/2" => 1,
+            "0-1" => 0,
+            _ => return false,
+        };
+        if let Some(min_elo) = self.min_elo {
+            let elo = |tag| game.header(tag).and_then(|elo| elo.parse::<u16>().ok()).unwrap_or(0);
+            if elo("WhiteElo") < min_elo || elo("BlackElo") < min_elo {
+                return false;
+            }
+        }
+        let player_color = match &self.player {
+            Some(player) if game.header("White") == Some(player.as_str()) => Some(Color::White),
+            Some(player) if game.header("Black") == Some(player.as_str()) => Some(Color::Black),
+            Some(_) => return false,
+            None => None,
+        };
+
+        let Ok(mut pos) = game.start_position() else {
+            return false;
+        };
+        if pos.state.variant != Variant::Standard {
+            return false;
+        }
+        for pgn_move in game.moves.iter().take(self.max_ply) {
+            let Some((from, to)) = san::parse_san(&mut pos, &pgn_move.san) else {
+                break;
+            };
+
+            let mover = pos.state.active_player;
+            if player_color.is_none_or(|color| color == mover) {
+                let key = (book::polyglot_key(&pos), book::encode_move(&pos, from, to));
+                let stats = self.stats.entry(key).or_default();
+                match if mover == Color::White { white_score } else { 2 - white_score } {
+                    2 => stats.wins += 1,
+                    1 => stats.draws += 1,
+                    _ => stats.losses += 1,
+                }
+            }
+            game::apply_move(&mut pos, from, to);
+        }
+
+        self.games += 1;
+        true
+    }
+
+    /* Returns the book entries sorted by key and by descending weight. Weights that don't fit into 16 bits are
+    / scaled down for all moves of the book to keep their proportions. */
+    pub fn entries(&self) -> Vec<BookEntry> {
+        let selected: Vec<(&(u64, u16), &MoveStats)> = self.stats.iter()
+            .filter(|(_, stats)| stats.games() >= self.min_games && stats.weight() > 0)
+            .collect();
+        let max_weight = selected.iter().map(|(_, stats)| stats.weight()).max().unwrap_or(0);
+
+        let mut entries: Vec<BookEntry> = selected.into_iter().map(|(&(key, book_move), stats)| {
+            let weight = if max_weight > u16::MAX as u32 {
+                std::cmp::max(stats.weight() as u64 * u16::MAX as u64 / max_weight as u64, 1) as u16
+            } else {
+                stats.weight() as u16
+            };
+            BookEntry { key, book_move, weight, learn: 0 }
+        }).collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)).then(a.book_move.cmp(&b.book_move)));
+        entries
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.entries().iter().flat_map(|entry| entry.to_bytes()).collect()
+    }
+
+    // Writes the book to a Polyglot .bin file and returns its number of entries
+    pub fn write(&self, path: &str) -> io::Result<usize> {
+        let bytes = self.to_bytes();
+        fs::write(path, &bytes)?;
+        Ok(bytes.len() / book::ENTRY_SIZE)
+    }
+}
@@ -0,0 +1,64 @@
+use rand::Rng;
+use types::{
+    position::Position,
+    types_utils::{fen_from_pos, shredder_fen_from_pos},
+};
+
+/* Chess960 (Fischer Random Chess) start positions and the UCI_Chess960 option. The rules of play are the same as in
+/ standard chess apart from castling, which the position handles for any king and rook files. In Chess960 games,
+/ marked in the state of their positions, castling moves are written as the king capturing its own rook and positions
+/ are written in Shredder-FEN. */
+
+pub const START_POSITIONS: u16 = 960;
+
+// Knight placements on the five squares left after placing the bishops and the queen, used for the numbering
+const KNIGHT_PLACEMENTS: [(usize, usize); 10] = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+
+/* Returns the back rank of a start position by its number in the standard (Scharnagl) numbering from 0 to 959,
+/ number 518 is the start position of standard chess. */
+pub fn back_rank(number: u16) -> [char; 8] {
+    assert!(number < START_POSITIONS, "Chess960 start positions are numbered from 0 to 959");
+    let mut rank = [' '; 8];
+    let number = number as usize;
+
+    // Bishops on a light and a dark square
+    rank[number % 4 * 2 + 1] = 'B';
+    rank[number / 4 % 4 * 2] = 'B';
+
+    let mut empty: Vec<usize> = (0..8).filter(|&file| rank[file] == ' ').collect();
+    rank[empty.remove(number / 16 % 6)] = 'Q';
+
+    let (first_knight, second_knight) = KNIGHT_PLACEMENTS[number / 96];
+    rank[empty[first_knight]] = 'N';
+    rank[empty[second_knight]] = 'N';
+
+    // The king stands between the rooks on the remaining squares
+    let empty: Vec<usize> = (0..8).filter(|&file| rank[file] == ' ').collect();
+    rank[empty[0]] = 'R';
+    rank[empty[1]] = 'K';
+    rank[empty[2]] = 'R';
+    rank
+}
+
+pub fn start_position(number: u16) -> Position {
+    let white: String = back_rank(number).iter().collect();
+    let fen = format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1", white.to_ascii_lowercase(), white);
+    let mut pos = Position::from_fen(fen);
+    pos.state.chess960 = true;
+    pos
+}
+
+// Returns a random start position and its number
+pub fn random_start_position() -> (u16, Position) {
+    let number = rand::thread_rng().gen_range(0..START_POSITIONS);
+    (number, start_position(number))
+}
+
+// Returns the FEN of a position, in Shredder-FEN in Chess960 games
+pub fn fen(pos: &Position) -> String {
+    if pos.state.chess960 {
+        shredder_fen_from_pos(pos)
+    } else {
+        fen_from_pos(pos)
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use types::{position::Position, variant::Variant, Color, Piece};
+use crate::{
+    negamax::SearchLimits,
+    uci,
+};
+
+/* Chess clocks for the game loop. The time control is written like the TimeControl tag of PGN, in seconds:
+/ "300" is sudden death, "300+2" adds an increment after every move (Fischer), "300d2" gives back the time used for a
+/ move up to the delay (Bronstein) and "40/5400" adds the time for every period of 40 moves. A side whose time runs out
+/ loses, unless the opponent has no material left to checkmate with. */
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TimeControl {
+    SuddenDeath { base: Duration },
+    Fischer { base: Duration, increment: Duration },
+    Bronstein { base: Duration, delay: Duration },
+    // The period is added to the clock at the start and after every moves moves
+    MovesPerPeriod { moves: u32, period: Duration },
+}
+
+impl TimeControl {
+    fn base(&self) -> Duration {
+        match *self {
+            TimeControl::SuddenDeath { base }
+            | TimeControl::Fischer { base, .. }
+            | TimeControl::Bronstein { base, .. } => base,
+            TimeControl::MovesPerPeriod { period, .. } => period,
+        }
+    }
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse::<f64>().ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or(format!("Invalid number of seconds: {}", text))
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let control = if let Some((moves, period)) = text.split_once('/') {
+            let moves = moves.parse::<u32>().ok().filter(|&moves| moves > 0).ok_or(format!("Invalid number of moves: {}", moves))?;
+            TimeControl::MovesPerPeriod { moves, period: parse_seconds(period)? }
+        } else if let Some((base, increment)) = text.split_once('+') {
+            TimeControl::Fischer { base: parse_seconds(base)?, increment: parse_seconds(increment)? }
+        } else if let Some((base, delay)) = text.split_once('d') {
+            TimeControl::Bronstein { base: parse_seconds(base)?, delay: parse_seconds(delay)? }
+        } else {
+            TimeControl::SuddenDeath { base: parse_seconds(text)? }
+        };
+        if control.base().is_zero() {
+            return Err(format!("Invalid time control {}, the base time must be more than zero", text));
+        }
+        Ok(control)
+    }
+}
+
+// Formats the time control like it is parsed, which is the format of the PGN TimeControl tag
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TimeControl::SuddenDeath { base } => write!(f, "{}", base.as_secs_f64()),
+            TimeControl::Fischer { base, increment } => write!(f, "{}+{}", base.as_secs_f64(), increment.as_secs_f64()),
+            TimeControl::Bronstein { base, delay } => write!(f, "{}d{}", base.as_secs_f64(), delay.as_secs_f64()),
+            TimeControl::MovesPerPeriod { moves, period } => write!(f, "{}/{}", moves, period.as_secs_f64()),
+        }
+    }
+}
+
+// The clocks of white and black, only the clock of the side to move runs
+#[derive(Debug, Clone)]
+pub struct Clock {
+    control: TimeControl,
+    remaining: [Duration; 2],
+    // Moves made by white and black
+    moves: [u32; 2],
+    // The side whose clock runs and when it was started
+    running: Option<(Color, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Clock {
+        Clock {
+            control,
+            remaining: [control.base(); 2],
+            moves: [0; 2],
+            running: None,
+        }
+    }
+
+    pub fn control(&self) -> TimeControl {
+        self.control
+    }
+
+    // Remaining time of a side, the time of the running clock is counted down
+    pub fn remaining(&self, color: Color) -> Duration {
+        match self.running {
+            Some((running, started)) if running == color => self.remaining[color as usize].saturating_sub(started.elapsed()),
+            _ => self.remaining[color as usize],
+        }
+    }
+
+    // Stops the running clock without taking the time from it, like when a move is taken back
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+
+    /* Runs the clock of the side to move. When the other clock was running, its side has made a move and the time of
+    / the move is taken from its clock. Returns the side whose flag has fallen, if any. */
+    pub fn update(&mut self, active_player: Color) -> Option<Color> {
+        let now = Instant::now();
+        match self.running {
+            Some((running, started)) if running != active_player => {
+                self.running = Some((active_player, now));
+                (!self.complete_move(running, now - started)).then_some(running)
+            },
+            Some((running, started)) => (now - started > self.remaining[running as usize]).then_some(running),
+            None => {
+                self.running = Some((active_player, now));
+                None
+            },
+        }
+    }
+
+    /* Takes the time used for a move from the clock of the side and adds the increment, the delay or the time of the
+    / next period. Returns false if the flag fell during the move, the clock then stays at zero. */
+    pub fn complete_move(&mut self, color: Color, elapsed: Duration) -> bool {
+        let side = color as usize;
+        if elapsed > self.remaining[side] {
+            self.remaining[side] = Duration::ZERO;
+            return false;
+        }
+        self.remaining[side] -= elapsed;
+        self.moves[side] += 1;
+        match self.control {
+            TimeControl::SuddenDeath { .. } => {},
+            TimeControl::Fischer { increment, .. } => self.remaining[side] += increment,
+            TimeControl::Bronstein { delay, .. } => self.remaining[side] += elapsed.min(delay),
+            TimeControl::MovesPerPeriod { moves, period } => {
+                if self.moves[side].is_multiple_of(moves) {
+                    self.remaining[side] += period;
+                }
+            },
+        }
+        true
+    }
+
+    /* The clocks as parameters of a UCI go command: the remaining times, the increments and the moves to the next
+    / time control. The delay of Bronstein clocks is given as increment, as at most that much time is given back. */
+    pub fn go_parameters(&self, active_player: Color) -> String {
+        let millis = |color| self.remaining(color).as_millis();
+        let mut parameters = format!("wtime {} btime {}", millis(Color::White), millis(Color::Black));
+        match self.control {
+            TimeControl::SuddenDeath { .. } => {},
+            TimeControl::Fischer { increment: extra, .. } | TimeControl::Bronstein { delay: extra, .. } => {
+                parameters.push_str(&format!(" winc {} binc {}", extra.as_millis(), extra.as_millis()));
+            },
+            TimeControl::MovesPerPeriod { moves, .. } => {
+                let moves_to_go = moves - self.moves[active_player as usize] % moves;
+                parameters.push_str(&format!(" movestogo {}", moves_to_go));
+            },
+        }
+        parameters
+    }
+
+    // Limits a search of the side to move to the move time the time manager of the engine gives it on this clock
+    pub fn limit(&self, active_player: Color, limits: SearchLimits) -> SearchLimits {
+        let move_time = uci::parse_go(&self.go_parameters(active_player), active_player).move_time;
+        SearchLimits {
+            depth: limits.depth,
+            move_time: match (limits.move_time, move_time) {
+                (Some(limit), Some(move_time)) => Some(limit.min(move_time)),
+                (limit, move_time) => limit.or(move_time),
+            },
+        }
+    }
+}
+
+// Formats a time as minutes and seconds with tenths, like 4:59.3
+pub fn format_time(time: Duration) -> String {
+    let tenths = time.as_millis() / 100;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+/* Whether a side still has the material to checkmate, a side that ran out of time only loses if the opponent has.
+/ A lone king or a king with a single minor piece can't force mate, in variants the material is always enough. */
+pub fn can_checkmate(pos: &Position, color: Color) -> bool {
+    if pos.state.variant != Variant::Standard {
+        return true;
+    }
+    let own = pos.color_bitboards[color as usize];
+    let heavy_or_pawns = pos.piece_bitboards[Piece::PAWN as usize] | pos.piece_bitboards[Piece::ROOK as usize] | pos.piece_bitboards[Piece::QUEEN as usize];
+    let minors = (pos.piece_bitboards[Piece::KNIGHT as usize] | pos.piece_bitboards[Piece::BISHOP as usize]) & own;
+    !(heavy_or_pawns & own).is_empty() || minors.count_ones() > 1
+}
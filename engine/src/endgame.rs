@@ -0,0 +1,293 @@
+use std::{
+    cmp,
+    collections::HashMap,
+};
+use lazy_static::lazy_static;
+use types::{
+    position::Position,
+    bitboard::BitBoard,
+    Color,
+    Piece,
+};
+use precompute::kpk::kpk_index;
+use crate::{
+    evaluation::{self, MATERIAL_VALUES_ENDGAME, MATERIAL_VALUES_MIDGAME},
+    kpk_bitbase::KPK_BITBASE,
+    movegen,
+};
+
+/* Specialised evaluation functions for endgames with very little material left.
+/ The general evaluation doesn't know how to make progress in positions like KBN vs K, so for known material
+/ signatures the evaluation is replaced by one of the functions below. They return a score from the view of the
+/ stronger side, which probe() converts to white's view. */
+
+// Score offset for positions that are known to be won, but where mate is not yet in sight of the search
+pub const KNOWN_WIN: i32 = 10000;
+
+type EndgameFunction = fn(&mut Position, Color) -> i32;
+
+lazy_static! {
+    static ref ENDGAMES: HashMap<u64, (EndgameFunction, Color)> = initialize_endgames();
+}
+
+fn initialize_endgames() -> HashMap<u64, (EndgameFunction, Color)> {
+    let endgames: [(&str, EndgameFunction); 11] = [
+        ("KPK", kpk),
+        ("KBNK", kbnk),
+        ("KRKP", krkp),
+        ("KRKB", krkb),
+        ("KRKN", krkn),
+        ("KQKP", kqkp),
+        ("KQKR", kqkr),
+        // Known draws
+        ("KNNK", draw),
+        ("KBKB", draw),
+        ("KNKN", draw),
+        ("KBKN", draw),
+    ];
+    let mut table = HashMap::new();
+    for (code, function) in endgames {
+        for strong_side in [Color::White, Color::Black] {
+            table.insert(key_from_code(code, strong_side), (function, strong_side));
+        }
+    }
+    table
+}
+
+/* Converts an endgame code like "KBNK" into the matching material key of evaluation::material_key,
+/ with the pieces before the second king belonging to the given strong side. */
+fn key_from_code(code: &str, strong_side: Color) -> u64 {
+    let weak_start = code[1..].find('K').unwrap() + 1;
+    let sides = [(&code[1..weak_start], strong_side), (&code[weak_start + 1..], !strong_side)];
+    let mut key = 0;
+    for (pieces, color) in sides {
+        for piece in pieces.chars() {
+            let piece_index = match piece {
+                'R' => 0,
+                'N' => 1,
+                'B' => 2,
+                'Q' => 3,
+                'P' => 5,
+                _ => panic!("Invalid piece {} in endgame code {}", piece, code),
+            };
+            key += 1 << (4 * (color as u64 * 6 + piece_index));
+        }
+    }
+    key
+}
+
+/* Returns the score of a specialised endgame evaluation from white's point of view
+/ or None if no specialised evaluation exists for the position's material. */
+pub fn probe(pos: &mut Position) -> Option<i32> {
+    let key = evaluation::material_key(pos);
+    let (function, strong_side): (EndgameFunction, Color) = match ENDGAMES.get(&key) {
+        Some(&entry) => entry,
+        None => {
+            // Bare kings and a lone minor piece can never win
+            if pos.all_pieces().count_ones() == 2
+                || pos.all_pieces().count_ones() == 3 && (pos.piece_bitboards[1] | pos.piece_bitboards[2]).count_ones() == 1 {
+                return Some(0);
+            }
+            // Any other material against a bare king is handled by the generic mating function
+            match [Color::White, Color::Black].into_iter().find(|&color| is_kxk(pos, color)) {
+                Some(strong_side) => (kxk, strong_side),
+                None => return None,
+            }
+        }
+    };
+    let score = function(pos, strong_side);
+    Some(if strong_side == Color::White { score } else { -score })
+}
+
+// Returns true if the weak side has a bare king and the strong side has at least the material of a rook
+fn is_kxk(pos: &Position, strong_side: Color) -> bool {
+    pos.color_bitboards[!strong_side as usize].count_ones() == 1
+        && non_pawn_material(pos, strong_side) >= MATERIAL_VALUES_MIDGAME[0]
+}
+
+fn non_pawn_material(pos: &Position, color: Color) -> u32 {
+    let mut npm = 0;
+    for (piece, value) in MATERIAL_VALUES_MIDGAME.iter().enumerate().take(5) {
+        npm += (pos.piece_bitboards[piece] & pos.color_bitboards[color as usize]).count_ones() * value;
+    }
+    npm
+}
+
+fn piece_square(pos: &Position, piece: u8, color: Color) -> u8 {
+    (pos.piece_bitboards[piece as usize] & pos.color_bitboards[color as usize]).trailing_zeros()
+}
+
+// Mirrors the square vertically for black so that the strong side can always be treated as white
+fn relative_square(square: u8, color: Color) -> u8 {
+    match color {
+        Color::White => square,
+        Color::Black => square ^ 56,
+    }
+}
+
+fn distance(a: u8, b: u8) -> i32 {
+    cmp::max((a % 8).abs_diff(b % 8), (a / 8).abs_diff(b / 8)) as i32
+}
+
+// Bonus for driving the king towards the edge of the board
+fn push_to_edge(square: u8) -> i32 {
+    let rank_distance = cmp::min(square / 8, 7 - square / 8) as i32;
+    let file_distance = cmp::min(square % 8, 7 - square % 8) as i32;
+    90 - (7 * file_distance * file_distance / 2 + 7 * rank_distance * rank_distance / 2)
+}
+
+// Bonus for driving the king towards the a1 or h8 corner
+fn push_to_corner(square: u8) -> i32 {
+    (7 - (square / 8) as i32 - (square % 8) as i32).abs()
+}
+
+// Bonus for bringing two pieces close to each other
+fn push_close(a: u8, b: u8) -> i32 {
+    140 - 20 * distance(a, b)
+}
+
+// Bonus for keeping two pieces away from each other
+fn push_away(a: u8, b: u8) -> i32 {
+    120 - push_close(a, b)
+}
+
+fn draw(_pos: &mut Position, _strong_side: Color) -> i32 {
+    0
+}
+
+/* King and enough material against a bare king: drive the enemy king to the edge of the board
+/ and bring our own king closer to help with the mate. */
+fn kxk(pos: &mut Position, strong_side: Color) -> i32 {
+    // Avoid stalemating the lonely king
+    if pos.state.active_player != strong_side && !pos.check
+        && movegen::get_all_legal_moves_for_color(!strong_side, pos).is_empty() {
+        return 0;
+    }
+    let strong_king = piece_square(pos, Piece::KING, strong_side);
+    let weak_king = piece_square(pos, Piece::KING, !strong_side);
+    let strong_pieces = pos.color_bitboards[strong_side as usize];
+    let pawn_count = (strong_pieces & pos.piece_bitboards[5]).count_ones() as i32;
+
+    let mut score = non_pawn_material(pos, strong_side) as i32
+        + pawn_count * MATERIAL_VALUES_ENDGAME[5] as i32
+        + push_to_edge(weak_king)
+        + push_close(strong_king, weak_king);
+
+    let bishops = strong_pieces & pos.piece_bitboards[2];
+    let dark_squares = BitBoard(0xAA55_AA55_AA55_AA55);
+    let has_bishop_pair = !(bishops & dark_squares).is_empty() && !(bishops & !dark_squares).is_empty();
+    if !(strong_pieces & (pos.piece_bitboards[0] | pos.piece_bitboards[3])).is_empty()
+        || has_bishop_pair
+        || !bishops.is_empty() && !(strong_pieces & pos.piece_bitboards[1]).is_empty() {
+        score += KNOWN_WIN;
+    }
+    score
+}
+
+/* King, bishop and knight against a bare king: the mate can only be forced in a corner of the bishop's color,
+/ so the weak king is driven towards one of those. */
+fn kbnk(pos: &mut Position, strong_side: Color) -> i32 {
+    let strong_king = piece_square(pos, Piece::KING, strong_side);
+    let weak_king = piece_square(pos, Piece::KING, !strong_side);
+    let bishop = piece_square(pos, Piece::BISHOP, strong_side);
+
+    // push_to_corner() drives towards a1 and h8, so mirror the board horizontally for a light-squared bishop
+    let light_squared_bishop = (bishop / 8 + bishop % 8) % 2 == 1;
+    let corner_king = if light_squared_bishop { weak_king ^ 7 } else { weak_king };
+
+    KNOWN_WIN + MATERIAL_VALUES_ENDGAME[1] as i32 + MATERIAL_VALUES_ENDGAME[2] as i32
+        + push_close(strong_king, weak_king)
+        + 420 * push_to_corner(corner_king)
+}
+
+/* King and pawn against king. The result is looked up in the KPK bitbase generated by the precompute crate,
+/ which stores for every position whether the side with the pawn wins. Positions are normalised to white having the
+/ pawn on the files a to d. */
+fn kpk(pos: &mut Position, strong_side: Color) -> i32 {
+    let mut strong_king = relative_square(piece_square(pos, Piece::KING, strong_side), strong_side);
+    let mut weak_king = relative_square(piece_square(pos, Piece::KING, !strong_side), strong_side);
+    let mut pawn = relative_square(piece_square(pos, Piece::PAWN, strong_side), strong_side);
+    let side_to_move = if pos.state.active_player == strong_side { 0 } else { 1 };
+
+    if pawn % 8 >= 4 {
+        strong_king ^= 7;
+        weak_king ^= 7;
+        pawn ^= 7;
+    }
+
+    if !kpk_probe(side_to_move, weak_king, strong_king, pawn) {
+        return 0;
+    }
+
+    KNOWN_WIN + MATERIAL_VALUES_ENDGAME[5] as i32 + (pawn / 8) as i32
+}
+
+// Returns whether white wins, for a normalised position with the pawn on the files a to d
+fn kpk_probe(side_to_move: usize, black_king: u8, white_king: u8, pawn: u8) -> bool {
+    let index = kpk_index(side_to_move, black_king, white_king, pawn);
+    KPK_BITBASE[index / 32] & (1 << (index % 32)) != 0
+}
+
+/* King and rook against king and pawn. Winning unless the pawn is far advanced and supported by its king,
+/ with the distances of both kings to the pawn deciding how drawish the position is. */
+fn krkp(pos: &mut Position, strong_side: Color) -> i32 {
+    let strong_king = relative_square(piece_square(pos, Piece::KING, strong_side), strong_side);
+    let weak_king = relative_square(piece_square(pos, Piece::KING, !strong_side), strong_side);
+    let rook = relative_square(piece_square(pos, Piece::ROOK, strong_side), strong_side);
+    let pawn = relative_square(piece_square(pos, Piece::PAWN, !strong_side), strong_side);
+    let queening_square = pawn % 8;
+    let strong_to_move = (pos.state.active_player == strong_side) as i32;
+    let rook_value = MATERIAL_VALUES_ENDGAME[0] as i32;
+
+    // If the stronger side's king is in front of the pawn, it's a win
+    if strong_king % 8 == pawn % 8 && strong_king < pawn {
+        return rook_value - distance(strong_king, pawn);
+    }
+    // If the weaker side's king is too far from the pawn and the rook, it's a win
+    if distance(weak_king, pawn) >= 4 - strong_to_move && distance(weak_king, rook) >= 3 {
+        return rook_value - distance(strong_king, pawn);
+    }
+    // If the pawn is far advanced and supported by the defending king, the position is drawish
+    if weak_king / 8 <= 2 && distance(weak_king, pawn) == 1 && strong_king / 8 >= 3
+        && distance(strong_king, pawn) > 2 + strong_to_move {
+        return 80 - 8 * distance(strong_king, pawn);
+    }
+    let in_front_of_pawn = pawn.saturating_sub(8);
+    200 - 8 * (distance(strong_king, in_front_of_pawn) - distance(weak_king, in_front_of_pawn) - distance(pawn, queening_square))
+}
+
+// King and rook against king and bishop is usually a draw, but a king on the edge gives some winning chances
+fn krkb(pos: &mut Position, strong_side: Color) -> i32 {
+    push_to_edge(piece_square(pos, Piece::KING, !strong_side))
+}
+
+// King and rook against king and knight: also drawish, but a knight far away from its king can get lost
+fn krkn(pos: &mut Position, strong_side: Color) -> i32 {
+    let weak_king = piece_square(pos, Piece::KING, !strong_side);
+    let knight = piece_square(pos, Piece::KNIGHT, !strong_side);
+    push_to_edge(weak_king) + push_away(weak_king, knight)
+}
+
+/* King and queen against king and pawn. A win unless the pawn is on the seventh rank on a bishop or rook file
+/ with its king next to it, where stalemate tricks save the defending side. */
+fn kqkp(pos: &mut Position, strong_side: Color) -> i32 {
+    let strong_king = piece_square(pos, Piece::KING, strong_side);
+    let weak_king = piece_square(pos, Piece::KING, !strong_side);
+    let pawn = piece_square(pos, Piece::PAWN, !strong_side);
+    let relative_pawn_rank = relative_square(pawn, !strong_side) / 8;
+
+    let mut score = push_close(strong_king, weak_king);
+    if relative_pawn_rank != 6 || distance(weak_king, pawn) != 1 || ![0, 2, 5, 7].contains(&(pawn % 8)) {
+        score += MATERIAL_VALUES_ENDGAME[3] as i32 - MATERIAL_VALUES_ENDGAME[5] as i32;
+    }
+    score
+}
+
+// King and queen against king and rook: a win, but the defending king has to be driven to the edge first
+fn kqkr(pos: &mut Position, strong_side: Color) -> i32 {
+    let strong_king = piece_square(pos, Piece::KING, strong_side);
+    let weak_king = piece_square(pos, Piece::KING, !strong_side);
+    MATERIAL_VALUES_ENDGAME[3] as i32 - MATERIAL_VALUES_ENDGAME[0] as i32
+        + push_to_edge(weak_king)
+        + push_close(strong_king, weak_king)
+}
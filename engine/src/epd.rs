@@ -0,0 +1,153 @@
+use std::{
+    fs,
+    io,
+    str::FromStr,
+};
+use types::position::Position;
+use crate::san;
+
+/* Extended Position Description, the format of test suites like WAC, ECM and STS. A record is the first four fields
+/ of a FEN followed by operations, each an opcode with its operands and terminated by a semicolon:
+/ 2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";
+/ Operands in double quotes may contain spaces and semicolons. The best moves (bm) and moves to avoid (am) are
+/ resolved against the position when the record is read, so records with illegal moves are rejected. */
+
+#[derive(Debug, Clone)]
+pub struct EpdRecord {
+    pub position: Position,
+    // All operations in the order of the record, quoted operands without their quotes
+    pub operations: Vec<(String, Vec<String>)>,
+    pub best_moves: Vec<(u8, u8)>,
+    pub avoid_moves: Vec<(u8, u8)>,
+}
+
+impl EpdRecord {
+    pub fn operation(&self, opcode: &str) -> Option<&[String]> {
+        self.operations.iter()
+            .find(|(name, _)| name == opcode)
+            .map(|(_, operands)| operands.as_slice())
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.operation("id")?.first().map(String::as_str)
+    }
+
+    // The primary comment, c0
+    pub fn comment(&self) -> Option<&str> {
+        self.operation("c0")?.first().map(String::as_str)
+    }
+
+    // A move solves the record if it's one of the best moves and none of the moves to avoid
+    pub fn is_solved_by(&self, found: (u8, u8)) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(&found)) && !self.avoid_moves.contains(&found)
+    }
+
+    // Records without bm or am can't be used to test the engine
+    pub fn is_test(&self) -> bool {
+        !self.best_moves.is_empty() || !self.avoid_moves.is_empty()
+    }
+}
+
+impl FromStr for EpdRecord {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut rest = line;
+        let mut fields = Vec::new();
+        for _ in 0..4 {
+            rest = rest.trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("Expected 4 FEN fields: {}", line));
+            }
+            fields.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        let mut position: Position = fields.join(" ").parse()
+            .map_err(|e| format!("Invalid position {}: {}", fields.join(" "), e))?;
+
+        let operations = parse_operations(rest)?;
+        let mut record = EpdRecord {
+            position: position.clone(),
+            operations,
+            best_moves: Vec::new(),
+            avoid_moves: Vec::new(),
+        };
+        // The move counters aren't part of the four FEN fields but may be given as operations
+        if let Some(half_moves) = record.operation("hmvc").and_then(|operands| operands.first()?.parse().ok()) {
+            record.position.state.half_move_counter = half_moves;
+        }
+        if let Some(full_moves) = record.operation("fmvn").and_then(|operands| operands.first()?.parse().ok()) {
+            record.position.state.full_move_counter = full_moves;
+        }
+
+        for (opcode, moves) in [("bm", &mut record.best_moves), ("am", &mut record.avoid_moves)] {
+            let Some((_, operands)) = record.operations.iter().find(|(name, _)| name == opcode) else {
+                continue;
+            };
+            for operand in operands {
+                moves.push(san::parse_move(&mut position, operand)?);
+            }
+        }
+        Ok(record)
+    }
+}
+
+// Splits the operations into opcodes and operands, every operation has to end with a semicolon
+fn parse_operations(text: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut operations = Vec::new();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                if tokens.is_empty() {
+                    return Err(format!("Empty operation in {}", text.trim()));
+                }
+                let opcode = tokens.remove(0);
+                operations.push((opcode, std::mem::take(&mut tokens)));
+            },
+            '"' => {
+                let mut operand = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => operand.push(c),
+                        None => return Err(format!("Unterminated string in {}", text.trim())),
+                    }
+                }
+                tokens.push(operand);
+            },
+            c if c.is_whitespace() => {},
+            c => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            },
+        }
+    }
+    if !tokens.is_empty() {
+        return Err(format!("Operation without semicolon in {}", text.trim()));
+    }
+    Ok(operations)
+}
+
+// Reads all records of an EPD text, blank lines and lines starting with # are skipped
+pub fn read_records(text: &str) -> Vec<Result<EpdRecord, String>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+pub fn read_file(path: &str) -> io::Result<Vec<Result<EpdRecord, String>>> {
+    Ok(read_records(&fs::read_to_string(path)?))
+}
@@ -1,12 +1,21 @@
-use std::cmp;
+use std::{
+    cmp,
+    collections::HashMap,
+    str::FromStr,
+};
 use types::{
     position::Position,
     bitboard::BitBoard,
     types_utils::*,
+    variant::Variant,
+    Castling,
+    Piece,
 };
 use crate::{
+    endgame,
     movegen,
     game,
+    variants,
 };
 
 const PIECE_SQUARE_TABLES_MIDGAME: [[[i32; 8]; 4]; 5] = [
@@ -156,41 +165,137 @@ const QUEEN_MOBILITY_BONUS_TABLE_ENDGAME: [i32; 28] = [
     -48, -30, -7, 19, 40, 55, 59, 75, 78, 96, 96, 100, 121, 127, 131, 133, 136, 141, 147, 150, 151, 168, 168, 171, 182, 182, 192, 219
 ];
 
+// Outpost bonus indexed by outpost_total():
+// none, reachable outpost, knight outpost on the edge without targets, bishop outpost, knight outpost
+const OUTPOST_BONUS_MIDGAME: [i32; 5] = [0, 31, -7, 30, 56];
+const OUTPOST_BONUS_ENDGAME: [i32; 5] = [0, 22, 36, 23, 36];
+
+// Rook file bonus indexed by rook_on_file(): closed, semi-open, open
+const ROOK_ON_FILE_BONUS_MIDGAME: [i32; 3] = [0, 19, 48];
+const ROOK_ON_FILE_BONUS_ENDGAME: [i32; 3] = [0, 7, 29];
+
+const FILE_A: BitBoard = BitBoard(0x0101_0101_0101_0101);
+const QUEEN_SIDE: BitBoard = BitBoard(0x0F0F_0F0F_0F0F_0F0F);
+const CENTER_FILES: BitBoard = BitBoard(0x3C3C_3C3C_3C3C_3C3C);
+const DARK_SQUARES: BitBoard = BitBoard(0xAA55_AA55_AA55_AA55);
+
+/* Quadratic material imbalance coefficients, taken from Stockfish as well.
+/ Rows and columns are indexed by bishop pair, pawn, knight, bishop, rook and queen. Each of our pieces of the row type
+/ receives the coefficient of every piece of the column type (up to its own type) on either side of the board. */
+const IMBALANCE_OURS: [[i32; 6]; 6] = [
+    [0, 0, 0, 0, 0, 0],
+    [40, 38, 0, 0, 0, 0],
+    [32, 255, -62, 0, 0, 0],
+    [0, 104, 4, 0, 0, 0],
+    [-26, -2, 47, 105, -208, 0],
+    [-189, 24, 117, 133, -134, -6],
+];
+const IMBALANCE_THEIRS: [[i32; 6]; 6] = [
+    [0, 0, 0, 0, 0, 0],
+    [36, 0, 0, 0, 0, 0],
+    [9, 63, 0, 0, 0, 0],
+    [59, 65, 42, 0, 0, 0],
+    [46, 39, 24, -24, 0, 0],
+    [97, 100, -42, 137, 268, 0],
+];
+const BISHOP_PAIR_BONUS: i32 = 1438;
+
 // Phase values for calculation of phase value for tapered evaluation
 // For now, these values are taken from Stockfish
 const MIDGAME_LIMIT: u32 = 15258;
 const ENDGAME_LIMIT: u32 = 3915;
 
 // Material values weighed in centipawns
-const MATERIAL_VALUES_MIDGAME: [u32; 6] = [1276, 781, 825, 2538, 0, 124];
-const MATERIAL_VALUES_ENDGAME: [u32; 6] = [1380, 854, 915, 2682, 0, 206];
+pub(crate) const MATERIAL_VALUES_MIDGAME: [u32; 6] = [1276, 781, 825, 2538, 0, 124];
+pub(crate) const MATERIAL_VALUES_ENDGAME: [u32; 6] = [1380, 854, 915, 2682, 0, 206];
+
+/* Weights of the evaluation terms in percent, engines with different profiles play in different styles. Material
+/ includes the imbalance, pieces the placement terms like outposts and rooks on open files. The default profile
+/ weighs every term with 100 and evaluates like main_evaluation. */
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct EvalProfile {
+    pub material: i32,
+    pub psqt: i32,
+    pub mobility: i32,
+    pub pieces: i32,
+}
+
+impl Default for EvalProfile {
+    fn default() -> Self {
+        EvalProfile {
+            material: 100,
+            psqt: 100,
+            mobility: 100,
+            pieces: 100,
+        }
+    }
+}
+
+impl EvalProfile {
+    pub const NAMES: [&'static str; 4] = ["default", "aggressive", "positional", "materialistic"];
+}
+
+// Profiles are selected by their name
+impl FromStr for EvalProfile {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let (material, psqt, mobility, pieces) = match name.to_ascii_lowercase().as_str() {
+            "default" => (100, 100, 100, 100),
+            "aggressive" => (90, 100, 150, 120),
+            "positional" => (100, 130, 110, 130),
+            "materialistic" => (120, 70, 60, 80),
+            _ => return Err(format!("Unknown evaluation profile {}, expected one of {}", name, EvalProfile::NAMES.join(", "))),
+        };
+        Ok(EvalProfile { material, psqt, mobility, pieces })
+    }
+}
 
 pub fn main_evaluation(pos: &mut Position) -> i32 {
+    evaluate(pos, &EvalProfile::default(), &mut ImbalanceCache::default())
+}
+
+/* Evaluates the position for the side to move with the terms weighed by the profile. The material imbalance is taken
+/ from the cache, every search thread of an engine keeps its own cache between searches. */
+pub fn evaluate(pos: &mut Position, profile: &EvalProfile, cache: &mut ImbalanceCache) -> i32 {
     // Instantly return the lower bound of the evaluation if the position is in checkmate
     // (seen from the side to move - if you are to move and in checkmate, eval is -infinity)
     if game::is_in_checkmate(pos) {
         return i32::MIN + 2;
     }
+    // Antichess turns the material balance around, none of the terms below apply
+    if pos.state.variant == Variant::Antichess {
+        return variants::antichess_evaluation(pos);
+    }
     let player_to_move = match pos.state.active_player {
         types::Color::White => 1,
         types::Color::Black => -1
     };
 
-    let midgame_evaluation = get_midgame_evaluation(pos);
-    let mut endgame_evaluation = get_endgame_evaluation(pos);
+    // Endgames with a known specialised evaluation replace the general evaluation completely, in standard chess only
+    let specialised_evaluation = if pos.state.variant == Variant::Standard { endgame::probe(pos) } else { None };
+    if let Some(endgame_evaluation) = specialised_evaluation {
+        return endgame_evaluation * player_to_move;
+    }
+
+    let imbalance = cache.imbalance(pos);
+    let midgame_evaluation = get_midgame_evaluation(pos, profile, imbalance);
+    let mut endgame_evaluation = get_endgame_evaluation(pos, profile, imbalance);
+    endgame_evaluation += initiative(pos, endgame_evaluation);
     let phase = get_phase_value(pos) as i32;
     let scale_factor = scale_factor(pos, endgame_evaluation);
     endgame_evaluation = endgame_evaluation * scale_factor as i32 / 64;
     /* println!("Phase: {}", phase);
     println!("Scale factor: {}", scale_factor);
     println!("Midgame evaluation: {}, Endgame evaluation: {}", midgame_evaluation, endgame_evaluation); */
-    let mut evaluation = (midgame_evaluation * phase + ((endgame_evaluation * (128 - phase)))) / 128;
+    let mut evaluation = (midgame_evaluation * phase + (endgame_evaluation * (128 - phase))) / 128;
     evaluation += tempo(pos);
+    evaluation += variants::evaluation(pos);
     evaluation * player_to_move
 }
 
 // The scale factor scales down the weight of the endgame evaluation value in the main evaluation
-fn scale_factor(pos: &mut Position, endgame_evaluation: i32) -> u32 {
+pub fn scale_factor(pos: &mut Position, endgame_evaluation: i32) -> u32 {
     let pos_flipped = pos.colorflip();
     let (pos_white, pos_black) = if endgame_evaluation > 0 {
         (pos.clone(), pos_flipped.clone())
@@ -203,6 +308,8 @@ fn scale_factor(pos: &mut Position, endgame_evaluation: i32) -> u32 {
     let pawn_count_black = (pos_black.piece_bitboards[5] & pos_black.color_bitboards[0]).count_ones();
     let queen_count_white = (pos_white.piece_bitboards[3] & pos_white.color_bitboards[0]).count_ones();
     let queen_count_black = (pos_black.piece_bitboards[3] & pos_black.color_bitboards[0]).count_ones();
+    let rook_count_white = (pos_white.piece_bitboards[0] & pos_white.color_bitboards[0]).count_ones();
+    let rook_count_black = (pos_black.piece_bitboards[0] & pos_black.color_bitboards[0]).count_ones();
     let bishop_count_white = (pos_white.piece_bitboards[2] & pos_white.color_bitboards[0]).count_ones();
     let bishop_count_black = (pos_black.piece_bitboards[2] & pos_black.color_bitboards[0]).count_ones();
     let knight_count_white = (pos_white.piece_bitboards[1] & pos_white.color_bitboards[0]).count_ones();
@@ -211,7 +318,7 @@ fn scale_factor(pos: &mut Position, endgame_evaluation: i32) -> u32 {
     let non_pawn_material_black = get_npm(&pos_black);
 
     // If white has no more pawns and the material difference is less than the midgame value of a bishop, scale down the endgame evaluation
-    if pawn_count_white == 0 && (non_pawn_material_white - non_pawn_material_black) <= MATERIAL_VALUES_MIDGAME[2] {
+    if pawn_count_white == 0 && non_pawn_material_white <= non_pawn_material_black + MATERIAL_VALUES_MIDGAME[2] {
         // If the material diffence is also less than the midgame value of a rook, scale down to 0
         if non_pawn_material_white < MATERIAL_VALUES_MIDGAME[0] {
             scale_factor = 0;
@@ -231,56 +338,38 @@ fn scale_factor(pos: &mut Position, endgame_evaluation: i32) -> u32 {
     if scale_factor == 64 {
         let opposite_bishops = opposite_bishops(pos);
         if opposite_bishops && non_pawn_material_white == MATERIAL_VALUES_MIDGAME[2] && non_pawn_material_black == MATERIAL_VALUES_MIDGAME[2] {
-            scale_factor = 22 /* + 4 * candidate_passed(pos_white) */
+            scale_factor = 22 + 4 * passed_pawn_count(&pos_white);
         } else if opposite_bishops {
+            // Opposite colored bishops with other pieces on the board are still drawish, but much less so
+            // if the additional material is unbalanced
             scale_factor = 22 + 3 * piece_count(&pos_white);
+            if non_pawn_material_white == non_pawn_material_black {
+                scale_factor = cmp::min(scale_factor, 46);
+            }
         } else {
-            // If both white's and black's non pawn material are worth as much as a bishop and if both players control the same amount of pawns
-            if non_pawn_material_white == MATERIAL_VALUES_MIDGAME[0] && non_pawn_material_black == MATERIAL_VALUES_MIDGAME[0] && pawn_count_white - pawn_count_black <= 1 {
-                let mut pawn_king_black = false;
-                let mut pcw_flank = [0, 0];
-                let mut pos_iterator = pos_white.color_bitboards[0] | pos_black.color_bitboards[0];
-                // Iterate over all occupied squares in the position
-                while pos_iterator != BitBoard::empty() {
-                    let index = pos_iterator.trailing_zeros() as usize;
-                    // If the square is occupied by a white pawn
-                    if pos_white.color_bitboards[0] & pos_white.piece_bitboards[5] & BitBoard::from_index(index) != BitBoard::empty() {
-                        let array_index = if index / 8 < 4 {1} else {0};
-                        // Note the pawn flank
-                        pcw_flank[array_index] = 1;
-                    }
-                    // If the square is occupied by a black king
-                    if pos_black.color_bitboards[0] & pos_black.piece_bitboards[4] & BitBoard::from_index(index) != BitBoard::empty() {
-                        // For each direction
-                        for delta in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
-                            let new_index = index as i32 + delta.0 * 8 + delta.1;
-                            if new_index < 0 || new_index > 63 {
-                                continue;
-                            }
-                            // If the square is occupied by a black pawn
-                            if pos_black.color_bitboards[0] & pos_black.piece_bitboards[5] & BitBoard::from_index(new_index as usize) != BitBoard::empty() {
-                                // Note that a black pawn is next to the black king
-                                pawn_king_black = true;
-                                break;
-                            }
-                        }
-                    }
-                    pos_iterator.clear_lsb();
-                }
-                // If the pawn flank is different and there is an opposite color pawn next to the opposite color king, scale down to 36
-                if pcw_flank[0] != pcw_flank[1] && pawn_king_black {
+            // Rook endings
+            if non_pawn_material_white == MATERIAL_VALUES_MIDGAME[0] && non_pawn_material_black == MATERIAL_VALUES_MIDGAME[0] && pawn_count_white <= pawn_count_black + 1 {
+                let pawns_white = pos_white.piece_bitboards[5] & pos_white.color_bitboards[0];
+                let king_black = (pos_black.piece_bitboards[4] & pos_black.color_bitboards[0]).trailing_zeros();
+                let pawns_black = pos_black.piece_bitboards[5] & pos_black.color_bitboards[0];
+                let pawns_on_one_flank = (pawns_white & QUEEN_SIDE).is_empty() != (pawns_white & !QUEEN_SIDE).is_empty();
+                // If the pawns are on one flank and there is an opposite color pawn next to the opposite color king, scale down to 36
+                if pawns_on_one_flank && !(king_ring(king_black) & pawns_black).is_empty() {
                     return 36;
                 }
+                // If the defending king stands in front of all pawns of the stronger side, scale down to 24
+                // (the king square is mirrored back from the flipped position first)
+                if pawn_count_white > 0 && king_in_front_of_pawns(&pos_white, king_black ^ 56) {
+                    return 24;
+                }
             }
             // If there is only one queen on the board
             if queen_count_white + queen_count_black == 1 {
-                // And it belongs to white
+                // Set the scale factor to 37 + 3 * amount of the queenless side's minor pieces and 5 * amount of its rooks
                 if queen_count_white == 1 {
-                    // Set the scale factor to 37 + 3 * amount of black's minor pieces
-                    scale_factor = 37 + 3 * (bishop_count_black + knight_count_black);
+                    scale_factor = 37 + 3 * (bishop_count_black + knight_count_black) + 5 * rook_count_black;
                 } else {
-                    // If the queen belongs to black, do the same for white
-                    scale_factor = 37 + 3 * (bishop_count_white + knight_count_white);
+                    scale_factor = 37 + 3 * (bishop_count_white + knight_count_white) + 5 * rook_count_white;
                 }
             } else {
                 // If the total queen amount is different from 2, choose the smaller value between the current scale factor
@@ -291,14 +380,89 @@ fn scale_factor(pos: &mut Position, endgame_evaluation: i32) -> u32 {
     }
 
     scale_factor    
-}    
+}
+
+// Returns all squares adjacent to the given square
+fn king_ring(square: u8) -> BitBoard {
+    let mut ring = BitBoard::empty();
+    for &(dx, dy) in &[(1, 1), (1, 0), (1, -1), (0, 1), (0, -1), (-1, 1), (-1, 0), (-1, -1)] {
+        if let Some(offset_square) = try_square_offset(square, dx, dy) {
+            ring |= BitBoard::from_square(offset_square);
+        }
+    }
+    ring
+}
+
+// Returns true if the enemy king on the given square blocks the path of all our pawns
+fn king_in_front_of_pawns(pos: &Position, king_square: u8) -> bool {
+    let mut pawns = pos.piece_bitboards[5] & pos.color_bitboards[0];
+    while !pawns.is_empty() {
+        let pawn = pawns.trailing_zeros();
+        if (pawn % 8).abs_diff(king_square % 8) > 1 || king_square / 8 <= pawn / 8 {
+            return false;
+        }
+        pawns.clear_lsb();
+    }
+    true
+}
+
+// Counts our pawns that have no enemy pawns in front of them on their own or the adjacent files
+fn passed_pawn_count(pos: &Position) -> u32 {
+    let enemy_pawns = pos.piece_bitboards[5] & pos.color_bitboards[1];
+    let mut pawns = pos.piece_bitboards[5] & pos.color_bitboards[0];
+    let mut passed = 0;
+    while !pawns.is_empty() {
+        let pawn = pawns.trailing_zeros();
+        let file = FILE_A << (pawn % 8) as usize;
+        let files = file | file.shift_east() | file.shift_west();
+        let in_front = if pawn / 8 == 7 { BitBoard::empty() } else { BitBoard(u64::MAX << (8 * (pawn / 8 + 1))) };
+        if (files & in_front & enemy_pawns).is_empty() {
+            passed += 1;
+        }
+        pawns.clear_lsb();
+    }
+    passed
+}
+
+/* The initiative (or "winnable") term adjusts the endgame evaluation by how likely it is that the stronger side can
+/ actually convert its advantage: passed pawns, pawns on both flanks and active kings make a win more likely, while
+/ symmetrical pawns on a single flank with the kings facing each other are hard to win. The adjustment never
+/ changes the sign of the evaluation. */
+pub fn initiative(pos: &mut Position, endgame_evaluation: i32) -> i32 {
+    let pos_flipped = pos.colorflip();
+    let pawns = pos.piece_bitboards[5];
+    let king_white = (pos.piece_bitboards[4] & pos.color_bitboards[0]).trailing_zeros();
+    let king_black = (pos.piece_bitboards[4] & pos.color_bitboards[1]).trailing_zeros();
+    if king_white > 63 || king_black > 63 {
+        return 0;
+    }
 
-// The phase value is a value that indicates how much the game is still in midgame vs endgame
-fn get_phase_value(pos: &mut Position) -> u32 {
+    let passed_count = (passed_pawn_count(pos) + passed_pawn_count(&pos_flipped)) as i32;
+    let both_flanks = !(pawns & QUEEN_SIDE).is_empty() && !(pawns & !QUEEN_SIDE).is_empty();
+    let outflanking = (king_white % 8).abs_diff(king_black % 8) as i32 - (king_white / 8).abs_diff(king_black / 8) as i32;
+    let pure_pawn = get_npm(pos) + get_npm(&pos_flipped) == 0;
+    let almost_unwinnable = outflanking < 0 && !both_flanks;
+    // A king that has crossed into the opponent's half of the board
+    let infiltration = king_white / 8 > 3 || king_black / 8 < 4;
+
+    let complexity = 9 * passed_count
+        + 12 * pawns.count_ones() as i32
+        + 9 * outflanking
+        + 21 * both_flanks as i32
+        + 24 * infiltration as i32
+        + 51 * pure_pawn as i32
+        - 43 * almost_unwinnable as i32
+        - 110;
+
+    endgame_evaluation.signum() * cmp::max(complexity, -endgame_evaluation.abs())
+}
+
+// The phase value indicates how much the game is still in the midgame, from 128 in the midgame down to 0 in the endgame
+pub fn get_phase_value(pos: &mut Position) -> u32 {
     let pos_flipped = pos.colorflip();
     let mut non_pawn_material = get_npm(pos) + get_npm(&pos_flipped);
     // println!("npm before ceiling: {}", non_pawn_material);
-    non_pawn_material = cmp::max(ENDGAME_LIMIT, cmp::min(non_pawn_material, MIDGAME_LIMIT));
+    non_pawn_material = non_pawn_material.clamp(ENDGAME_LIMIT, MIDGAME_LIMIT);
     // println!("Non pawn material: {}", non_pawn_material);
     ((non_pawn_material - ENDGAME_LIMIT) * 128) / (MIDGAME_LIMIT - ENDGAME_LIMIT)
 }
@@ -306,21 +470,23 @@ fn get_phase_value(pos: &mut Position) -> u32 {
 // Gets value of non-pawn material
 fn get_npm(pos: &Position) -> u32 {
     let mut npm = 0;
-    for piece in 0..6 {
-        if piece == 5 {
-            continue;
-        }
-        npm += (pos.piece_bitboards[piece] & pos.color_bitboards[0]).count_ones() * MATERIAL_VALUES_MIDGAME[piece];
+    for (piece, value) in MATERIAL_VALUES_MIDGAME.iter().enumerate().take(5) {
+        npm += (pos.piece_bitboards[piece] & pos.color_bitboards[0]).count_ones() * value;
     }
     npm
 }
 
-fn get_midgame_evaluation(pos: &mut Position) -> i32 {
+fn get_midgame_evaluation(pos: &mut Position, profile: &EvalProfile, imbalance: i32) -> i32 {
     let mut evaluation_score = 0;
     let pos_flipped = pos.colorflip();
-    evaluation_score += get_piece_value_midgame(pos) as i32 - get_piece_value_midgame(&pos_flipped) as i32;
-    evaluation_score += get_piece_square_table_value(pos, true) - get_piece_square_table_value(&pos_flipped, true);
-    evaluation_score += get_mobility_score(pos, true) as i32 - get_mobility_score(&pos_flipped, true) as i32;
+    let material = get_piece_value_midgame(pos) as i32 - get_piece_value_midgame(&pos_flipped) as i32 + imbalance;
+    evaluation_score += material * profile.material / 100;
+    let psqt = get_piece_square_table_value(pos, true) - get_piece_square_table_value(&pos_flipped, true);
+    evaluation_score += psqt * profile.psqt / 100;
+    let mobility = get_mobility_score(pos, true) - get_mobility_score(&pos_flipped, true);
+    evaluation_score += mobility * profile.mobility / 100;
+    let pieces = get_pieces_score(pos, true) - get_pieces_score(&pos_flipped, true);
+    evaluation_score += pieces * profile.pieces / 100;
     // TODO: pawn structure: isolated, backward, doubled, connected, chained, etc.
     // TODO: piece safety
     // TODO: passed pawns
@@ -329,10 +495,13 @@ fn get_midgame_evaluation(pos: &mut Position) -> i32 {
     evaluation_score
 }
 
-fn get_endgame_evaluation(pos: &mut Position) -> i32 {
+fn get_endgame_evaluation(pos: &mut Position, profile: &EvalProfile, imbalance: i32) -> i32 {
     let mut evaluation_score: i32 = 0;
     let pos_flipped = pos.colorflip();
-    evaluation_score += get_piece_value_endgame(pos) as i32 - get_piece_value_endgame(&pos_flipped) as i32;
+    let material = get_piece_value_endgame(pos) as i32 - get_piece_value_endgame(&pos_flipped) as i32 + imbalance;
+    evaluation_score += material * profile.material / 100;
+    let pieces = get_pieces_score(pos, false) - get_pieces_score(&pos_flipped, false);
+    evaluation_score += pieces * profile.pieces / 100;
     evaluation_score
 }
 
@@ -365,7 +534,7 @@ fn get_piece_square_table_value(pos: &Position, midgame: bool) -> i32 {
             let square = bb.trailing_zeros() as usize;
             let rank = cmp::min(7 - square / 8, square / 8);
             let file = square % 8;
-            psqt_score += piece_square_table.get_value(piece, rank as usize, file as usize);
+            psqt_score += piece_square_table.get_value(piece, rank as usize, file);
             bb.clear_lsb(); // Clear the least significant set bit
         }
     }
@@ -377,7 +546,7 @@ fn get_mobility_score(pos: &Position, midgame: bool) -> i32 {
     let mut mobility_score = 0;
     let mut iterator = pos.color_bitboards[0];
     while !iterator.is_empty() {
-        let index = iterator.trailing_zeros() as u8;
+        let index = iterator.trailing_zeros();
         let mobility = get_mobility(pos, index, mobility_range);
         let piece = pos.piece_at(index).unwrap().0;
         match piece {
@@ -473,7 +642,7 @@ fn get_mobility_range(pos: &Position) -> BitBoard {
     mobility_range ^= queen ^ king;
     let mut mobility_range_iterator = mobility_range;
     while !mobility_range_iterator.is_empty() {
-        let index = mobility_range_iterator.trailing_zeros() as u8;
+        let index = mobility_range_iterator.trailing_zeros();
         if !is_in_mobility_area(pos, index) {
             mobility_range &= !BitBoard::from_square(index);
         }
@@ -482,6 +651,271 @@ fn get_mobility_range(pos: &Position) -> BitBoard {
     mobility_range
 }
 
+/* Positional terms for knights, bishops, rooks and queens that go beyond their piece-square table and mobility:
+/ outposts, pawn structure around minor pieces, open files, trapped rooks and pins against the queen. */
+fn get_pieces_score(pos: &Position, midgame: bool) -> i32 {
+    let mobility_range = get_mobility_range(pos);
+    // A trapped rook hurts more if the king has lost the option to castle out of its way
+    let castling_multiplier = if pos.state.castling_rights.0 & Castling::WHITE_CASTLING != 0 {1} else {2};
+    let mut pieces_score = 0;
+    let mut iterator = pos.color_bitboards[0] & !(pos.piece_bitboards[4] | pos.piece_bitboards[5]);
+    while !iterator.is_empty() {
+        let square = iterator.trailing_zeros();
+        let piece = pos.piece_type_at(square).unwrap();
+        if midgame {
+            pieces_score += OUTPOST_BONUS_MIDGAME[outpost_total(pos, square, piece)];
+            pieces_score += 18 * minor_behind_pawn(pos, square, piece);
+            pieces_score -= 3 * bishop_pawns(pos, square, piece);
+            pieces_score -= 4 * bishop_xray_pawns(pos, square, piece);
+            pieces_score += 45 * long_diagonal_bishop(pos, square, piece);
+            pieces_score += 6 * rook_on_queen_file(pos, square, piece);
+            pieces_score += ROOK_ON_FILE_BONUS_MIDGAME[rook_on_file(pos, square, piece)];
+            pieces_score += 20 * rook_on_seventh(pos, square, piece);
+            pieces_score -= 55 * castling_multiplier * trapped_rook(pos, square, piece, mobility_range);
+            pieces_score -= 56 * weak_queen(pos, square, piece);
+            pieces_score -= (if piece == Piece::KNIGHT {8} else {6}) * king_protector(pos, square, piece);
+        } else {
+            pieces_score += OUTPOST_BONUS_ENDGAME[outpost_total(pos, square, piece)];
+            pieces_score += 3 * minor_behind_pawn(pos, square, piece);
+            pieces_score -= 7 * bishop_pawns(pos, square, piece);
+            pieces_score -= 5 * bishop_xray_pawns(pos, square, piece);
+            pieces_score += 11 * rook_on_queen_file(pos, square, piece);
+            pieces_score += ROOK_ON_FILE_BONUS_ENDGAME[rook_on_file(pos, square, piece)];
+            pieces_score += 40 * rook_on_seventh(pos, square, piece);
+            pieces_score -= 13 * castling_multiplier * trapped_rook(pos, square, piece, mobility_range);
+            pieces_score -= 15 * weak_queen(pos, square, piece);
+            pieces_score -= 9 * king_protector(pos, square, piece);
+        }
+        iterator.clear_lsb();
+    }
+    pieces_score
+}
+
+// Squares attacked by our pawns
+fn pawn_attacks_white(pos: &Position) -> BitBoard {
+    let pawns = pos.piece_bitboards[5] & pos.color_bitboards[0];
+    pawns.diagonal_north_east() | pawns.diagonal_north_west()
+}
+
+// Squares that could ever be attacked by one of the opponent's pawns while it advances
+fn pawn_attacks_span_black(pos: &Position) -> BitBoard {
+    let pawns = pos.piece_bitboards[5] & pos.color_bitboards[1];
+    let mut span = pawns.diagonal_south_east() | pawns.diagonal_south_west();
+    for _ in 0..5 {
+        span |= span >> 8;
+    }
+    span
+}
+
+// An outpost is a square on the 4th to 6th rank that is protected by our pawns and can't be attacked by enemy pawns
+fn is_outpost_square(pos: &Position, square: u8) -> bool {
+    if !(3..=5).contains(&(square / 8)) {
+        return false;
+    }
+    pawn_attacks_white(pos).contains(square) && !pawn_attacks_span_black(pos).contains(square)
+}
+
+/* Returns the index into the outpost bonus tables: 4 for a knight on an outpost, 3 for a bishop on an outpost,
+/ 2 for a knight outpost on the edge with nothing to attack and 1 for a minor piece that can reach an outpost. */
+fn outpost_total(pos: &Position, square: u8, piece: u8) -> usize {
+    if piece != Piece::KNIGHT && piece != Piece::BISHOP {
+        return 0;
+    }
+    if !is_outpost_square(pos, square) {
+        return reachable_outpost(pos, square, piece);
+    }
+    if piece == Piece::BISHOP {
+        return 3;
+    }
+    let file = square % 8;
+    if !(2..=5).contains(&file) {
+        let enemy_pieces = pos.color_bitboards[1] & !pos.piece_bitboards[5];
+        let has_targets = !(movegen::get_pseudolegal_knight_moves(square) & enemy_pieces).is_empty();
+        let flank = if file < 4 { QUEEN_SIDE } else { !QUEEN_SIDE };
+        let enemy_minors_on_flank = pos.color_bitboards[1] & (pos.piece_bitboards[1] | pos.piece_bitboards[2]) & flank;
+        if !has_targets && enemy_minors_on_flank.count_ones() <= 1 {
+            return 2;
+        }
+    }
+    4
+}
+
+// Returns 1 if a knight or bishop can move to an unoccupied outpost square
+fn reachable_outpost(pos: &Position, square: u8, piece: u8) -> usize {
+    let mut targets = match piece {
+        1 => movegen::get_knight_moves(square, pos),
+        2 => movegen::slider_moves(piece, square, pos),
+        _ => return 0,
+    };
+    targets &= !pos.color_bitboards[1];
+    while !targets.is_empty() {
+        if is_outpost_square(pos, targets.trailing_zeros()) {
+            return 1;
+        }
+        targets.clear_lsb();
+    }
+    0
+}
+
+// Returns 1 if a knight or bishop is shielded by a pawn directly in front of it
+fn minor_behind_pawn(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::KNIGHT && piece != Piece::BISHOP || square / 8 == 7 {
+        return 0;
+    }
+    pos.piece_bitboards[5].contains(square + 8) as i32
+}
+
+/* Penalty factor for a bishop that shares its square color with our own pawns.
+/ The penalty grows with the number of blocked central pawns and if the bishop itself is not protected by a pawn. */
+fn bishop_pawns(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::BISHOP {
+        return 0;
+    }
+    let pawns = pos.piece_bitboards[5] & pos.color_bitboards[0];
+    let same_colored_squares = if DARK_SQUARES.contains(square) { DARK_SQUARES } else { !DARK_SQUARES };
+    let pawns_on_same_color = (pawns & same_colored_squares).count_ones() as i32;
+    let blocked_central_pawns = ((pawns & CENTER_FILES) << 8 & pos.all_pieces()).count_ones() as i32;
+    let unprotected = !pawn_attacks_white(pos).contains(square) as i32;
+    pawns_on_same_color * (blocked_central_pawns + unprotected)
+}
+
+// Number of enemy pawns on the bishop's diagonals, regardless of any pieces in between
+fn bishop_xray_pawns(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::BISHOP {
+        return 0;
+    }
+    let mut diagonals = BitBoard::empty();
+    for &(dx, dy) in &[(1, 1), (1, -1), (-1, 1), (-1, -1)] {
+        let mut ray = square;
+        while let Some(next) = try_square_offset(ray, dx, dy) {
+            diagonals |= BitBoard::from_square(next);
+            ray = next;
+        }
+    }
+    (diagonals & pos.piece_bitboards[5] & pos.color_bitboards[1]).count_ones() as i32
+}
+
+// Returns 1 if a bishop on one of the long diagonals has an unobstructed view of the center
+fn long_diagonal_bishop(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::BISHOP {
+        return 0;
+    }
+    let (file, rank) = (square % 8, square / 8);
+    if file != rank && file != 7 - rank {
+        return 0;
+    }
+    let distance_to_edge = cmp::min(file, 7 - file);
+    if distance_to_edge > 2 {
+        return 0;
+    }
+    let dx = if file < 4 {1} else {-1};
+    let dy = if rank < 4 {1} else {-1};
+    let mut ray = square;
+    for _ in distance_to_edge..4 {
+        if pos.piece_bitboards[5].contains(ray) {
+            return 0;
+        }
+        ray = try_square_offset(ray, dx, dy).unwrap();
+    }
+    1
+}
+
+// Returns 1 if a rook shares its file with a queen of either color
+fn rook_on_queen_file(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::ROOK {
+        return 0;
+    }
+    let file = FILE_A << (square % 8) as usize;
+    !(file & pos.piece_bitboards[3]).is_empty() as i32
+}
+
+// Returns 2 for a rook on an open file, 1 for a rook on a semi-open file and 0 otherwise
+fn rook_on_file(pos: &Position, square: u8, piece: u8) -> usize {
+    if piece != Piece::ROOK {
+        return 0;
+    }
+    let file = FILE_A << (square % 8) as usize;
+    if !(file & pos.piece_bitboards[5] & pos.color_bitboards[0]).is_empty() {
+        return 0;
+    }
+    if !(file & pos.piece_bitboards[5] & pos.color_bitboards[1]).is_empty() {
+        return 1;
+    }
+    2
+}
+
+// Returns 1 for a rook on the seventh rank that either attacks pawns there or cuts off the enemy king on the eighth
+fn rook_on_seventh(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::ROOK || square / 8 != 6 {
+        return 0;
+    }
+    let seventh_rank = BitBoard(0x00FF_0000_0000_0000);
+    let eighth_rank = BitBoard(0xFF00_0000_0000_0000);
+    let pawns_on_seventh = seventh_rank & pos.piece_bitboards[5] & pos.color_bitboards[1];
+    let king_on_eighth = eighth_rank & pos.piece_bitboards[4] & pos.color_bitboards[1];
+    (!pawns_on_seventh.is_empty() || !king_on_eighth.is_empty()) as i32
+}
+
+// Returns 1 for a rook with little mobility that is stuck on a closed file between the king and the edge of the board
+fn trapped_rook(pos: &Position, square: u8, piece: u8, mobility_range: BitBoard) -> i32 {
+    if piece != Piece::ROOK || rook_on_file(pos, square, piece) > 0 || get_mobility(pos, square, mobility_range) > 3 {
+        return 0;
+    }
+    let king = pos.piece_bitboards[4] & pos.color_bitboards[0];
+    if king.is_empty() {
+        return 0;
+    }
+    let king_file = king.trailing_zeros() % 8;
+    let rook_file = square % 8;
+    ((king_file < 4) == (rook_file < king_file)) as i32
+}
+
+// Returns 1 if an enemy rook or bishop could pin our queen or attack it once a single piece in between moves away
+fn weak_queen(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::QUEEN {
+        return 0;
+    }
+    let enemy_rooks = pos.piece_bitboards[0] & pos.color_bitboards[1];
+    let enemy_bishops = pos.piece_bitboards[2] & pos.color_bitboards[1];
+    for &(dx, dy) in &[(1, 1), (1, 0), (1, -1), (0, 1), (0, -1), (-1, 1), (-1, 0), (-1, -1)] {
+        let diagonal = dx != 0 && dy != 0;
+        let mut pieces_in_between = 0;
+        let mut ray = square;
+        while let Some(next) = try_square_offset(ray, dx, dy) {
+            ray = next;
+            if !pos.all_pieces().contains(ray) {
+                continue;
+            }
+            if pieces_in_between == 1 {
+                let sliders = if diagonal { enemy_bishops } else { enemy_rooks };
+                if sliders.contains(ray) {
+                    return 1;
+                }
+            }
+            pieces_in_between += 1;
+            if pieces_in_between > 1 {
+                break;
+            }
+        }
+    }
+    0
+}
+
+// Distance of a knight or bishop from our own king
+fn king_protector(pos: &Position, square: u8, piece: u8) -> i32 {
+    if piece != Piece::KNIGHT && piece != Piece::BISHOP {
+        return 0;
+    }
+    let king = pos.piece_bitboards[4] & pos.color_bitboards[0];
+    if king.is_empty() {
+        return 0;
+    }
+    let king_square = king.trailing_zeros();
+    let file_distance = (king_square % 8).abs_diff(square % 8);
+    let rank_distance = (king_square / 8).abs_diff(square / 8);
+    cmp::max(file_distance, rank_distance) as i32
+}
+
 fn get_piece_value_midgame(pos: &Position) -> u32 {
     get_material_value(pos, true)
 }
@@ -495,34 +929,90 @@ fn get_material_value(pos: &Position, midgame: bool) -> u32 {
     let mut total_piece_value: u32 = 0;
     match midgame {
         true => {
-            for piece in 0..6 {
-                total_piece_value += (pos.piece_bitboards[piece] & pos.color_bitboards[0]).count_ones() * MATERIAL_VALUES_MIDGAME[piece];
+            for (piece, value) in MATERIAL_VALUES_MIDGAME.iter().enumerate() {
+                total_piece_value += (pos.piece_bitboards[piece] & pos.color_bitboards[0]).count_ones() * value;
             }
         },
         false => {
-            for piece in 0..6 {
-                total_piece_value += (pos.piece_bitboards[piece] & pos.color_bitboards[0]).count_ones() * MATERIAL_VALUES_ENDGAME[piece];
+            for (piece, value) in MATERIAL_VALUES_ENDGAME.iter().enumerate() {
+                total_piece_value += (pos.piece_bitboards[piece] & pos.color_bitboards[0]).count_ones() * value;
             }
         }
     }
     total_piece_value
 }
 
+/* Returns a key that uniquely identifies the amount of each piece type for both colors.
+/ Every count gets 4 bits, ordered by color first and piece index second. Kings are left out. */
+pub fn material_key(pos: &Position) -> u64 {
+    let mut key = 0;
+    for color in 0..2 {
+        for piece in [0, 1, 2, 3, 5] {
+            let count = (pos.piece_bitboards[piece] & pos.color_bitboards[color]).count_ones() as u64;
+            key |= count << (4 * (color * 6 + piece));
+        }
+    }
+    key
+}
+
 /* Material imbalance describes the concept of pieces not only having a static value assignment (like 1-3-3-5-9), but
-considering what other pieces are on the board as well */
-/* fn material_imbalance(pos: &Position) {
-    let mut imbalance = 0;
-    v += 
+/ considering what other pieces are on the board as well, e.g. knights gaining value with more pawns on the board. */
+pub fn material_imbalance(pos: &Position) -> i32 {
+    let white_counts = imbalance_piece_counts(pos, 0);
+    let black_counts = imbalance_piece_counts(pos, 1);
+    let total = imbalance(&white_counts, &black_counts) - imbalance(&black_counts, &white_counts)
+        + bishop_pair(&white_counts) - bishop_pair(&black_counts);
+    total / 16
+}
 
-} */
+// Material imbalance only depends on the piece counts, so it is cached per material signature
+#[derive(Debug, Default)]
+pub struct ImbalanceCache {
+    imbalances: HashMap<u64, i32>,
+}
 
-// Returns true if the position contains a bishop pair from the view of the white player
-/* fn bishop_pair(pos: &Position) -> u32 {
-    if (pos.piece_bitboards[2] & pos.color_bitboards[0]).count_ones() < 2 {
-        return 0;
+impl ImbalanceCache {
+    pub fn imbalance(&mut self, pos: &Position) -> i32 {
+        *self.imbalances.entry(material_key(pos)).or_insert_with(|| material_imbalance(pos))
     }
-    1438
-} */
+
+    // Number of material signatures in the cache
+    pub fn len(&self) -> usize {
+        self.imbalances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.imbalances.is_empty()
+    }
+}
+
+// Piece counts of one color in the order of the imbalance tables, with a flag for the bishop pair at index 0
+fn imbalance_piece_counts(pos: &Position, color: usize) -> [i32; 6] {
+    let count = |piece: usize| (pos.piece_bitboards[piece] & pos.color_bitboards[color]).count_ones() as i32;
+    let bishops = count(2);
+    [(bishops > 1) as i32, count(5), count(1), bishops, count(0), count(3)]
+}
+
+fn imbalance(ours: &[i32; 6], theirs: &[i32; 6]) -> i32 {
+    let mut imbalance = 0;
+    for piece in 1..6 {
+        if ours[piece] == 0 {
+            continue;
+        }
+        let mut piece_value = 0;
+        for other_piece in 0..=piece {
+            piece_value += IMBALANCE_OURS[piece][other_piece] * ours[other_piece]
+                + IMBALANCE_THEIRS[piece][other_piece] * theirs[other_piece];
+        }
+        imbalance += ours[piece] * piece_value;
+    }
+    imbalance
+}
+
+// Returns the bishop pair bonus if the given piece counts contain a bishop pair
+fn bishop_pair(counts: &[i32; 6]) -> i32 {
+    counts[0] * BISHOP_PAIR_BONUS
+}
 
 // Returns true if the position contains opposite bishops (white vs dark squares)
 fn opposite_bishops(pos: &Position) -> bool {
@@ -531,9 +1021,10 @@ fn opposite_bishops(pos: &Position) -> bool {
     }
     let white_bishops = pos.piece_bitboards[2] & pos.color_bitboards[0];
     let black_bishops = pos.piece_bitboards[2] & pos.color_bitboards[1];
-    let white_bishop_square = white_bishops.trailing_zeros() % 8;
-    let black_bishop_square = black_bishops.trailing_zeros() % 8;
-    white_bishop_square % 2 != black_bishop_square % 2
+    if white_bishops.count_ones() != 1 || black_bishops.count_ones() != 1 {
+        return false;
+    }
+    (white_bishops & DARK_SQUARES).is_empty() != (black_bishops & DARK_SQUARES).is_empty()
 }
 
 // Being the side whose turn it is confers a small bonus
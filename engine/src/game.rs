@@ -1,157 +1,503 @@
-use std::panic;
+use std::{
+    io::{self, BufRead},
+    panic,
+};
 
+use indicatif::{ProgressBar, ProgressStyle};
 use types::{
     position::Position,
     bitboard::BitBoard,
     state::GameResult,
-    types_utils::*,
+    variant::Variant,
     Color,
+    DropMove,
+    Piece,
     Results,
 };
 use crate::{
-    negamax,
+    chess960,
+    clock::{self, Clock, TimeControl},
+    negamax::{Engine, SearchInfo, SearchLimits},
     movegen,
     evaluation,
-    parse_input,
+    parse_input::{self, UserInput},
+    pgn::{self, PgnGame},
+    player::{MoveError, Player},
+    san,
+    variants,
 };
 
-pub fn main_game_loop(humans: u8, depth: u8, fen: Option<String>) -> Vec<(u8, u8)> {
-    let mut pos = if fen.is_some() {
-        let mut position = Position::from_fen(fen.unwrap());
-        update_attackers(&mut position, !BitBoard::empty());
-        position
-    } else {
-        Position::new()
-    };
-    match humans {
-        0 => {
-            println!("AI vs AI game.");
-            while pos.state.game_result.is_ongoing() {
-                pos.print_position();
-                
-                let eval = match pos.state.active_player {
-                    Color::White => evaluation::main_evaluation(&mut pos),
-                    Color::Black => -evaluation::main_evaluation(&mut pos),
-                };
-                if i32::MIN + 1 < eval && eval < i32::MAX {
-                    println!("Current evaluation: {}", eval);   
-                }
+const HELP: &str = "Moves can be entered in SAN (Nf3), LAN (g1-f3) or UCI (g1f3). Commands:
+  <enter>          let the engine play the move
+  go [depth N]     let the engine play the move, searched N plies deep
+  hint             show the move the engine would play
+  legal            list the legal moves
+  undo             take back the last move, against an engine its reply as well
+  redo             play the moves taken back again
+  resign           resign the game
+  draw             offer a draw
+  fen              show the FEN of the position
+  setfen <FEN>     start from the position of the FEN
+  new              start a new game
+  flip             turn the board around
+  eval             show the static evaluation from white's point of view
+  save <file.pgn>  save the game as PGN
+  load <file.pgn>  continue the first game of a PGN file
+  help             show this help
+  quit             stop the game";
+
+// A move played in the game loop with what is needed to take it back: the position and the clocks before the move
+#[derive(Clone)]
+struct UndoRecord {
+    before: Position,
+    played: (u8, u8),
+    clock: Option<Clock>,
+}
+
+/* The moves of a game in the game loop with their undo records and the moves that were taken back, the evaluations
+/ printed before engine moves and the scores of the engine searches. */
+pub struct Game {
+    start: Position,
+    pos: Position,
+    history: Vec<UndoRecord>,
+    redo: Vec<UndoRecord>,
+    // Evaluations by ply
+    evals: Vec<(usize, i32)>,
+    // Search scores of engine moves by ply, from the view of the engine
+    scores: Vec<(usize, i32)>,
+    // How the game ended if not by the moves on the board, for the Termination tag of the PGN
+    termination: Option<&'static str>,
+}
+
+impl Game {
+    pub fn new(start: Position) -> Game {
+        Game {
+            pos: start.clone(),
+            start,
+            history: Vec::new(),
+            redo: Vec::new(),
+            evals: Vec::new(),
+            scores: Vec::new(),
+            termination: None,
+        }
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.pos
+    }
+
+    // The moves that were taken back, in the order redo plays them again
+    pub fn redo_moves(&self) -> Vec<(u8, u8)> {
+        self.redo.iter().rev().map(|record| record.played).collect()
+    }
+
+    // Plays a new move, the moves taken back can't be played again afterwards
+    pub fn play(&mut self, from: u8, to: u8, clock: Option<&Clock>) {
+        self.redo.clear();
+        self.push(from, to, clock);
+    }
+
+    fn push(&mut self, from: u8, to: u8, clock: Option<&Clock>) {
+        self.history.push(UndoRecord { before: self.pos.clone(), played: (from, to), clock: clock.cloned() });
+        play_move(&mut self.pos, from, to);
+    }
+
+    // Takes back the last move and restores the position and the clocks from before it
+    fn pop(&mut self, clock: &mut Option<Clock>) -> Option<Color> {
+        let record = self.history.pop()?;
+        self.pos = record.before.clone();
+        if let Some(mut saved) = record.clock.clone() {
+            saved.stop();
+            *clock = Some(saved);
+        }
+        let plies = self.history.len();
+        self.evals.retain(|&(ply, _)| ply < plies);
+        self.scores.retain(|&(ply, _)| ply < plies);
+        self.redo.push(record);
+        Some(self.pos.state.active_player)
+    }
+
+    // Takes back moves until a human is to move, returns false if there was nothing to take back
+    pub fn undo(&mut self, players: &[Player; 2], clock: &mut Option<Clock>) -> bool {
+        if self.history.is_empty() {
+            return false;
+        }
+        // The side that played the move taken back is to move again
+        while let Some(mover) = self.pop(clock) {
+            if self.history.is_empty() || players[mover as usize].is_human() {
+                break;
+            }
+        }
+        true
+    }
+
+    // Plays the moves taken back again until a human is to move, returns false if there was nothing to play
+    pub fn redo(&mut self, players: &[Player; 2], clock: Option<&Clock>) -> bool {
+        let Some(record) = self.redo.pop() else {
+            return false;
+        };
+        self.push(record.played.0, record.played.1, clock);
+        while !players[self.pos.state.active_player as usize].is_human() {
+            let Some(record) = self.redo.pop() else {
+                break;
+            };
+            self.push(record.played.0, record.played.1, clock);
+        }
+        true
+    }
+
+    // The search scores of the moves of a side, the latest score last
+    fn side_scores(&self, color: Color) -> Vec<i32> {
+        let mover = |ply: usize| if ply.is_multiple_of(2) { self.start.state.active_player } else { !self.start.state.active_player };
+        self.scores.iter().filter(|&&(ply, _)| mover(ply) == color).map(|&(_, score)| score).collect()
+    }
+}
+
+/* Plays a game between the given players of white and black, every side is a human or an engine with its own
+/ settings. Humans enter their moves or commands to take moves back, set up positions, save and load the game and more,
+/ see HELP. Their hints and the moves they let the engine play are searched by the given engine to the given depth.
+/ With a time control, the clocks run and engines plan their time by them. A side whose flag falls loses the game.
+/ Humans can resign and offer draws, engines resign, offer and accept draws by their thresholds. */
+pub fn main_game_loop(engine: &mut Engine, players: &mut [Player; 2], depth: u8, start: Option<Position>, time_control: Option<TimeControl>) -> PgnGame {
+    game_loop(engine, players, depth, start, time_control, &mut io::stdin().lock())
+}
 
-                if is_in_checkmate(&mut pos) {
-                    pos.state.game_result = match pos.state.active_player {
-                        types::Color::White => GameResult(Results::BLACK_VICTORY),
-                        types::Color::Black => GameResult(Results::WHITE_VICTORY),
-                    };
-                    println!("{:?} wins by checkmate!", !pos.state.active_player);
-                    println!("FEN: {}", fen_from_pos(&pos));
-                    return pos.move_history;
+// The game loop of main_game_loop, reading the moves, commands and answers of humans from the input one line at a time
+pub fn game_loop(engine: &mut Engine, players: &mut [Player; 2], depth: u8, start: Option<Position>, time_control: Option<TimeControl>, input: &mut impl BufRead) -> PgnGame {
+    let initial = start.unwrap_or_default();
+    let mut game = Game::new(initial.clone());
+    let mut clock = time_control.map(Clock::new);
+    // Whether the board is shown from black's side, which it is for a human playing black against an engine
+    let mut flipped = !players[0].is_human() && players[1].is_human();
+    // The board is only printed again after it changed
+    let mut show_board = true;
+    println!("{} (white) vs {} (black).", players[0].name(), players[1].name());
+    if players.iter().any(Player::is_human) {
+        println!("Type 'help' to see the commands.");
+    }
+
+    while game.pos.state.game_result.is_ongoing() {
+        let pos = &mut game.pos;
+        if let Some(flagged) = clock.as_mut().and_then(|clock| clock.update(pos.state.active_player)) {
+            lose_on_time(&mut game, flagged);
+            break;
+        }
+        let eval = match pos.state.active_player {
+            Color::White => evaluation::main_evaluation(pos),
+            Color::Black => -evaluation::main_evaluation(pos),
+        };
+        if show_board {
+            pos.print_board(flipped);
+            if i32::MIN + 1 < eval && eval < i32::MAX {
+                println!("Current evaluation: {}", eval);
+            }
+            if let Some(clock) = &clock {
+                println!("Clock: white {}, black {}", clock::format_time(clock.remaining(Color::White)), clock::format_time(clock.remaining(Color::Black)));
+            }
+        }
+        show_board = true;
+
+        if is_in_checkmate(pos) {
+            pos.state.game_result = victory(!pos.state.active_player);
+            println!("{:?} wins by checkmate!", !pos.state.active_player);
+            break;
+        }
+        if is_won_by_variant(pos) {
+            return game_record(&game, players, clock.as_ref());
+        }
+        if movegen::get_all_legal_moves_for_color(pos.state.active_player, pos).is_empty() {
+            pos.state.game_result = GameResult(Results::STALEMATE);
+            println!("Draw by stalemate!");
+            break;
+        }
+
+        let mover = pos.state.active_player;
+        let computer_move = match &mut players[mover as usize] {
+            Player::Human => None,
+            Player::Engine { engine, limits, .. } => {
+                let limits = clock.as_ref().map_or(*limits, |clock| clock.limit(mover, *limits));
+                Some(engine_move(engine, pos, limits))
+            },
+            Player::Uci(uci_engine) => match uci_engine.best_move(&game.start, pos, clock.as_ref()) {
+                Ok(computer_move) => Some(computer_move),
+                Err(MoveError::Timeout(e)) => {
+                    println!("{}.", e);
+                    lose_on_time(&mut game, mover);
+                    break;
+                },
+                Err(MoveError::Unsupported(e)) => {
+                    game.termination = Some("unterminated");
+                    println!("Error: {}, stopping the game.", e);
+                    break;
+                },
+                Err(MoveError::Failed(e)) => {
+                    println!("Error: {}, stopping the game.", e);
+                    break;
+                },
+            },
+        };
+        if let Some(((from, to), score)) = computer_move {
+            let thresholds = players[mover as usize].thresholds().expect("Only engines make computer moves");
+            let ply = pos.move_history.len();
+            if let Some(score) = score {
+                game.scores.push((ply, score));
+            }
+            let scores = game.side_scores(mover);
+            if thresholds.should_resign(&scores) {
+                game.pos.state.game_result = victory(!mover);
+                println!("{} resigns, {:?} wins!", players[mover as usize].name(), !mover);
+                break;
+            }
+            println!("{} move: {}", players[mover as usize].name(), san::move_to_san(&mut game.pos, from, to));
+            game.play(from, to, clock.as_ref());
+            game.evals.push((ply, eval));
+            if thresholds.should_offer_draw(&scores) {
+                println!("{} offers a draw.", players[mover as usize].name());
+                if accepts_draw(&players[!mover as usize], &game, !mover, input) {
+                    game.pos.state.game_result = GameResult(Results::DRAW);
+                    println!("Draw agreed!");
+                    break;
                 }
-                make_engine_move(&mut pos, depth);
-                if pos.state.active_player == Color::Black { pos.state.full_move_counter += 1; }
             }
-            println!("FEN: {}", fen_from_pos(&pos));
-            return pos.move_history;
-        },
-        1 => {
-            println!("Human vs AI game.");
-            while pos.state.game_result.is_ongoing() {
-                pos.print_position();
-
-                let eval = match pos.state.active_player {
-                    Color::White => evaluation::main_evaluation(&mut pos),
-                    Color::Black => -evaluation::main_evaluation(&mut pos),
-                };
-                if i32::MIN + 1 < eval && eval < i32::MAX {
-                    println!("Current evaluation: {}", eval);   
+            continue;
+        }
+
+        // Get user input, moves can be entered in SAN, LAN or UCI
+        let mut line = String::new();
+        println!("{:?} to move, enter a move or a command:", pos.state.active_player);
+        if input.read_line(&mut line).unwrap_or(0) == 0 {
+            println!("No more input, stopping the game.");
+            break;
+        }
+        let command = match parse_input::parse_user_input(&line, pos) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("Error: {}", e);
+                show_board = false;
+                continue;
+            }
+        };
+        match command {
+            UserInput::Move(from, to) => match check_player_move(pos, from, to) {
+                Ok(()) => game.play(from, to, clock.as_ref()),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    show_board = false;
+                },
+            },
+            UserInput::EngineMove | UserInput::Go(_) => {
+                let go_depth = if let UserInput::Go(Some(go_depth)) = command { go_depth } else { depth };
+                let ((from, to), _) = engine_move(engine, pos, SearchLimits { depth: go_depth, move_time: None });
+                println!("AI move: {}", san::move_to_san(pos, from, to));
+                let ply = pos.move_history.len();
+                game.play(from, to, clock.as_ref());
+                game.evals.push((ply, eval));
+            },
+            UserInput::Resign => {
+                pos.state.game_result = victory(!mover);
+                println!("{:?} resigns, {:?} wins!", mover, !mover);
+            },
+            UserInput::OfferDraw => {
+                if accepts_draw(&players[!mover as usize], &game, !mover, input) {
+                    game.pos.state.game_result = GameResult(Results::DRAW);
+                    println!("Draw agreed!");
+                } else {
+                    println!("{} declines the draw offer.", players[!mover as usize].name());
+                    show_board = false;
                 }
-                if is_in_checkmate(&mut pos) {
-                    pos.state.game_result = match pos.state.active_player {
-                        types::Color::White => GameResult(Results::BLACK_VICTORY),
-                        types::Color::Black => GameResult(Results::WHITE_VICTORY),
-                    };
-                    println!("{:?} wins by checkmate!", !pos.state.active_player);
-                    println!("FEN: {}", fen_from_pos(&pos));
-                    return pos.move_history;
+            },
+            UserInput::Hint => {
+                let ((from, to), _) = search_with_progress(engine, pos, SearchLimits { depth, move_time: None });
+                println!("Hint: {}", san::move_to_san(pos, from, to));
+                show_board = false;
+            },
+            UserInput::LegalMoves => {
+                let moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+                let moves: Vec<String> = moves.iter().map(|&(from, to)| san::move_to_san(pos, from, to)).collect();
+                println!("Legal moves: {}", moves.join(", "));
+                show_board = false;
+            },
+            UserInput::Fen => {
+                println!("FEN: {}", chess960::fen(pos));
+                show_board = false;
+            },
+            UserInput::SetFen(fen) => match Position::from_variant_fen(&fen, pos.state.variant) {
+                Ok(pos) => {
+                    game = Game::new(pos);
+                    clock = time_control.map(Clock::new);
+                },
+                Err(e) => {
+                    println!("Error: Invalid FEN: {}", e);
+                    show_board = false;
+                },
+            },
+            UserInput::Undo => {
+                if !game.undo(players, &mut clock) {
+                    println!("Error: There are no moves to take back.");
+                    show_board = false;
                 }
-                // Get user input in the format of "a1 a2"
-                let mut input = String::new();
-                println!("Enter a legal move, type 'legal' to get a list of legal moves or press enter to have the engine move.");
-                std::io::stdin().read_line(&mut input).unwrap();
-                let input = input.trim();let input_legality = parse_input::user_input_to_square_index(input);
-                match input_legality {
-                    Ok(o) => {
-                        if o == [99, 99] {
-                            continue;
-                        } else if o == [98, 98] {
-                            let moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, &mut pos);
-                            println!("Legal moves:");
-                            for legal_move in moves {
-                                let from_string = string_from_square(legal_move.0);
-                                let to_string = string_from_square(legal_move.1);
-                                print!("{} {}, ", from_string, to_string);
-                            }
-                            continue;
-                        }
-                        else if o == [97, 97] {
-                            make_engine_move(&mut pos, depth);
-                            if pos.state.active_player == Color::Black { pos.state.full_move_counter += 1; }
-                            continue;
-                        } else if o == [96, 96] {
-                            println!("FEN: {}", fen_from_pos(&pos));
-                            continue;
-                        }
-                    }
-                    Err(e) => {
-                        println!("Error: {}", e);
-                        continue;
-                    }
+            },
+            UserInput::Redo => {
+                if !game.redo(players, clock.as_ref()) {
+                    println!("Error: There are no moves to play again.");
+                    show_board = false;
                 }
-
-                let squares = input_legality.unwrap();
-                let square = squares[0];
-                let target_square = squares[1];
-                
-                match make_player_move(&mut pos, square, target_square) {
-                    Ok(_) => 
-                        if pos.state.active_player == Color::Black { pos.state.full_move_counter += 1; },
-                    Err(e) => {
-                        println!("Error: {}", e);
-                        continue;
-                    }
+            },
+            UserInput::New => {
+                game = Game::new(initial.clone());
+                clock = time_control.map(Clock::new);
+            },
+            UserInput::Flip => flipped = !flipped,
+            UserInput::Eval => {
+                println!("Static evaluation: {:+.2}", evaluation::main_evaluation(pos) as f64 / 100.0);
+                show_board = false;
+            },
+            UserInput::Save(path) => {
+                let record = game_record(&game, players, clock.as_ref());
+                match pgn::write_file(&path, &[record]) {
+                    Ok(()) => println!("Saved the game to {}.", path),
+                    Err(e) => println!("Error: Could not write {}: {}", path, e),
                 }
-            }
-            println!("FEN: {}", fen_from_pos(&pos));
-            return pos.move_history;
+                show_board = false;
+            },
+            UserInput::Load(path) => match load_game(&path) {
+                Ok(loaded) => {
+                    println!("Loaded a game with {} moves from {}.", loaded.pos.move_history.len(), path);
+                    game = loaded;
+                    clock = time_control.map(Clock::new);
+                },
+                Err(e) => {
+                    println!("Error: {}", e);
+                    show_board = false;
+                },
+            },
+            UserInput::Help => {
+                println!("{}", HELP);
+                show_board = false;
+            },
+            UserInput::Quit => break,
+        }
+    }
+    println!("FEN: {}", chess960::fen(&game.pos));
+    game_record(&game, players, clock.as_ref())
+}
+
+// Ends the game when the time of a side is over, it is drawn if the opponent can't checkmate
+fn lose_on_time(game: &mut Game, flagged: Color) {
+    let pos = &mut game.pos;
+    pos.state.game_result = if clock::can_checkmate(pos, !flagged) { victory(!flagged) } else { GameResult(Results::DRAW) };
+    game.termination = Some("time forfeit");
+    if pos.state.game_result == GameResult(Results::DRAW) {
+        println!("{:?} ran out of time, but {:?} can't checkmate: draw!", flagged, !flagged);
+    } else {
+        println!("{:?} wins on time!", !flagged);
+    }
+}
+
+// Reads the first game of a PGN file to continue it
+fn load_game(path: &str) -> Result<Game, String> {
+    let games = pgn::read_file(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let pgn_game = games.first().ok_or(format!("There are no games in {}", path))?;
+    let start = pgn_game.start_position().map_err(|e| format!("Invalid FEN tag: {}", e))?;
+    let moves = pgn_game.replay()?.move_history;
+    let mut game = Game::new(start);
+    for (from, to) in moves {
+        game.play(from, to, None);
+    }
+    Ok(game)
+}
+
+// Plays the moves from the start position like they are played in a game
+pub fn replay(start: &Position, moves: &[(u8, u8)]) -> Position {
+    let mut pos = start.clone();
+    for &(from, to) in moves {
+        play_move(&mut pos, from, to);
+    }
+    pos
+}
+
+// Plays a move of a game: applies it, adds it to the move history and counts the full moves
+pub fn play_move(pos: &mut Position, from: u8, to: u8) {
+    apply_move(pos, from, to);
+    pos.move_history.push((from, to));
+    if pos.state.active_player == Color::White {
+        pos.state.full_move_counter += 1;
+    }
+}
+
+pub fn victory(winner: Color) -> GameResult {
+    match winner {
+        Color::White => GameResult(Results::WHITE_VICTORY),
+        Color::Black => GameResult(Results::BLACK_VICTORY),
+    }
+}
+
+/* Asks a side whether it accepts a draw offer. Humans answer the question on the input, this engine decides by its
+/ evaluation and the material left, external engines by the last score they reported. */
+fn accepts_draw(player: &Player, game: &Game, color: Color, input: &mut impl BufRead) -> bool {
+    let Some(thresholds) = player.thresholds() else {
+        println!("{:?}, do you accept the draw offer? (yes/no)", color);
+        let mut answer = String::new();
+        return input.read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "yes");
+    };
+    let mut pos = game.pos.clone();
+    let score = match player {
+        Player::Engine { engine, .. } => {
+            let score = evaluation::evaluate(&mut pos, &engine.options().profile, &mut evaluation::ImbalanceCache::default());
+            if pos.state.active_player == color { score } else { -score }
         },
-        2 => {
-            println!("Running benchmark mode for 2 moves.");
-            for _i in 0..2 {
-                pos.print_position();
-                
-                let eval = match pos.state.active_player {
-                    Color::White => evaluation::main_evaluation(&mut pos),
-                    Color::Black => -evaluation::main_evaluation(&mut pos),
-                };
-                if i32::MIN + 1 < eval && eval < i32::MAX {
-                    println!("Current evaluation: {}", eval);   
-                }
+        _ => match game.side_scores(color).last() {
+            Some(&score) => score,
+            None => return false,
+        },
+    };
+    thresholds.accepts_draw(score, evaluation::get_phase_value(&mut pos))
+}
 
-                if is_in_checkmate(&mut pos) {
-                    pos.state.game_result = match pos.state.active_player {
-                        types::Color::White => GameResult(Results::BLACK_VICTORY),
-                        types::Color::Black => GameResult(Results::WHITE_VICTORY),
-                    };
-                    println!("{:?} wins by checkmate!", !pos.state.active_player);
-                    println!("FEN: {}", fen_from_pos(&pos));
-                    return pos.move_history;
-                }
-                make_engine_move(&mut pos, depth);
+// Ends the game if it has been won by a rule of the variant, like reaching the center in King of the Hill
+fn is_won_by_variant(pos: &mut Position) -> bool {
+    let Some(winner) = variants::winner(pos) else {
+        return false;
+    };
+    pos.state.game_result = match winner {
+        Color::White => GameResult(Results::WHITE_VICTORY),
+        Color::Black => GameResult(Results::BLACK_VICTORY),
+    };
+    println!("{:?} wins by the rules of {}!", winner, pos.state.variant);
+    println!("FEN: {}", chess960::fen(pos));
+    true
+}
+
+// Creates the PGN of a game, engine moves are commented with the evaluation printed before them
+fn game_record(record: &Game, players: &[Player; 2], clock: Option<&Clock>) -> PgnGame {
+    let pos = &record.pos;
+    let mut game = PgnGame::from_moves(&record.start, &pos.move_history);
+    game.set_header("Event", "AmselChess game");
+    game.set_header("Date", &pgn::today());
+    let name = |player: &Player| if player.is_human() { "?".to_string() } else { player.name().to_string() };
+    game.set_header("White", &name(&players[0]));
+    game.set_header("Black", &name(&players[1]));
+    if pos.state.chess960 {
+        game.set_header("Variant", "Chess960");
+    } else if pos.state.variant != Variant::Standard {
+        game.set_header("Variant", pos.state.variant.pgn_name());
+    }
+    if let Some(clock) = clock {
+        game.set_header("TimeControl", &clock.control().to_string());
+    }
+    if let Some(termination) = record.termination {
+        game.set_header("Termination", termination);
+    }
+    game.set_result(pos.state.game_result);
+
+    for &(ply, eval) in &record.evals {
+        if i32::MIN + 1 < eval && eval < i32::MAX {
+            if let Some(pgn_move) = game.moves.get_mut(ply) {
+                pgn_move.comment = Some(format!("{:+.2}", eval as f64 / 100.0));
             }
-            println!("FEN: {}", fen_from_pos(&pos));
-            return pos.move_history;
         }
-        _ => panic!("Invalid number of human players."),
     }
+    game
 }
 
 /* Find all sliders that are attacking the given square by using a fictitious queen that can move in all directions,
@@ -167,7 +513,7 @@ pub fn update_attackers(pos: &mut Position, attackers: BitBoard) {
     let mut attacker_board = attackers & (pos.color_bitboards[0] | pos.color_bitboards[1]);
 
     while attacker_board != BitBoard::empty() {
-        let index = attacker_board.trailing_zeros() as u8;
+        let index = attacker_board.trailing_zeros();
         if let Some(piece) = pos.piece_type_at(index) {
             let attacks = match piece {
                 0 | 2 | 3 => {
@@ -207,17 +553,22 @@ pub fn is_in_checkmate(pos: &mut Position) -> bool {
             return true;
         }
     }
-    return false;
+    false
 }
 
 pub fn make_player_move(pos: &mut Position, from: u8, to: u8) -> Result<(), &'static str> {
-    // Check if the targetted piece contains a piece of the active player's color
-    if let Some(piece) = pos.piece_at(from) {
-        if piece.1 != pos.state.active_player {
-            return Err("Piece does not belong to active player.");
-        }
-    } else {
-        return Err("Illegal move: no piece on origin square.");
+    check_player_move(pos, from, to)?;
+    play_move(pos, from, to);
+    Ok(())
+}
+
+// Checks that a move entered by a player is a legal move of the side to move
+pub fn check_player_move(pos: &mut Position, from: u8, to: u8) -> Result<(), &'static str> {
+    // Check if the targetted piece contains a piece of the active player's color, drops come from the hand
+    match pos.piece_at(from) {
+        Some(piece) if piece.1 != pos.state.active_player => return Err("Piece does not belong to active player."),
+        None if DropMove::piece(from).is_none() => return Err("Illegal move: no piece on origin square."),
+        _ => {},
     }
 
     let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
@@ -227,48 +578,53 @@ pub fn make_player_move(pos: &mut Position, from: u8, to: u8) -> Result<(), &'st
         return Err("Not a legal move.");
     }
 
-    apply_move(pos, from, to);
-    pos.move_history.push((from, to));
-
     Ok(())
 }
 
-pub fn make_engine_move(pos: &mut Position, depth: u8) {  
-    let best_move = negamax::find_best_move(pos, depth);
-    let (from, to) = best_move;
-
-    println!("AI move: {} {}", string_from_square(from), string_from_square(to));
-
-    apply_move(pos, from, to);
-    pos.move_history.push((from, to));
+// Chooses the move of the engine with the score of its search, book moves are played without a score
+pub fn engine_move(engine: &mut Engine, pos: &mut Position, limits: SearchLimits) -> ((u8, u8), Option<i32>) {
+    // Play from the opening book if it has a move for this position, otherwise search
+    match engine.book_move(pos) {
+        Some(book_move) => {
+            println!("Book move found");
+            (book_move, None)
+        },
+        None => {
+            let (best_move, score) = search_with_progress(engine, pos, limits);
+            (best_move, Some(score))
+        },
+    }
 }
 
 pub fn apply_move(pos: &mut Position, from: u8, to: u8) {
     let mut attackers_to_update = BitBoard::empty();
 
-    // Add sliders that are no longer blocked by the moved piece to the list of pieces to update
-    attackers_to_update |= get_attacking_sliders(pos, from);
+    // Add sliders that are no longer blocked by the moved piece to the list of pieces to update, drops free no square
+    let is_drop = DropMove::piece(from).is_some();
+    if !is_drop {
+        attackers_to_update |= get_attacking_sliders(pos, from);
+    }
 
-    let is_pawn = pos.piece_bitboards[5].contains(from);
-    let is_king = pos.piece_bitboards[4].contains(from);
+    let is_pawn = !is_drop && pos.piece_bitboards[5].contains(from);
+    let is_king = !is_drop && pos.piece_bitboards[4].contains(from);
 
     let ep_square: Option<u8> = pos.en_passant_square;
 
+    // Castling moves the rook as well, which frees the sliders it blocked
+    let castling = if is_king { pos.castling_right_of(from, to).map(|right| pos.castling_squares(right)) } else { None };
+    if let Some(castling) = castling {
+        attackers_to_update |= get_attacking_sliders(pos, castling.rook_from);
+    }
+
     pos.make_move(&from, &to);
 
     // Add sliders that now have their path blocked by the moved piece
     attackers_to_update |= get_attacking_sliders(pos, to);
 
-    // If the move is a castling move, move the rook as well
-    if is_king && ((from as i8 % 8) - (to as i8 % 8)).abs() > 1 {
-        if from > to {
-            pos.make_castling_move(&(to - 2), &(from - 1));
-            attackers_to_update |= BitBoard::from_square(from - 1);
-            attackers_to_update ^= BitBoard::from_square(to - 1);
-        } else {
-            pos.make_castling_move(&(to + 1), &(from + 1));
-            attackers_to_update |= BitBoard::from_square(from + 1);
-            attackers_to_update ^= BitBoard::from_square(to + 1);
+    if let Some(castling) = castling {
+        for square in [castling.king_to, castling.rook_to] {
+            attackers_to_update |= BitBoard::from_square(square);
+            attackers_to_update |= get_attacking_sliders(pos, square);
         }
     }
 
@@ -287,25 +643,31 @@ pub fn apply_move(pos: &mut Position, from: u8, to: u8) {
             }
         }
         // Check if the move is en passant
-        match ep_square {
-            Some(ep_square) => {
-                if to == ep_square {
-                    let ep_target = match pos.state.active_player {
-                        Color::White => to + 8,
-                        Color::Black => to - 8,
-                    };
-                    attackers_to_update |= get_attacking_sliders(pos, ep_target);
-                    pos.color_bitboards[pos.state.active_player as usize] ^= BitBoard::from_square(ep_target);
-                    pos.piece_bitboards[5] ^= BitBoard::from_square(ep_target);
+        if let Some(ep_square) = ep_square {
+            if to == ep_square {
+                let ep_target = match pos.state.active_player {
+                    Color::White => to + 8,
+                    Color::Black => to - 8,
+                };
+                attackers_to_update |= get_attacking_sliders(pos, ep_target);
+                pos.color_bitboards[pos.state.active_player as usize] ^= BitBoard::from_square(ep_target);
+                pos.piece_bitboards[5] ^= BitBoard::from_square(ep_target);
+                if pos.state.variant == Variant::Crazyhouse {
+                    pos.put_in_hand(!pos.state.active_player, Piece::PAWN, ep_target);
                 }
-            },
-            None => (),
+            }
         }
     }
     
     attackers_to_update |= BitBoard::from_square(to);
     update_attackers(pos, attackers_to_update);
 
+    // The king is a normal piece in Antichess and may have been captured
+    if pos.state.variant == Variant::Antichess {
+        pos.check = false;
+        return;
+    }
+
     let king_square = match pos.state.active_player {
         Color::White => pos.piece_bitboards[4] & pos.color_bitboards[0],
         Color::Black => pos.piece_bitboards[4] & pos.color_bitboards[1],
@@ -318,10 +680,39 @@ pub fn apply_move(pos: &mut Position, from: u8, to: u8) {
         },
         _ => 
             // Check if the move puts the enemy king in check
-            pos.check = pos.is_square_attacked_by_color(king_square.trailing_zeros() as u8, !pos.state.active_player),
+            pos.check = pos.is_square_attacked_by_color(king_square.trailing_zeros(), !pos.state.active_player),
+    }
+    if pos.check && pos.state.variant == Variant::ThreeCheck {
+        pos.state.checks[!pos.state.active_player as usize] += 1;
     }
 }
 
+// Searches the position within the limits and shows the searched root moves in a progress bar
+fn search_with_progress(engine: &mut Engine, pos: &Position, limits: SearchLimits) -> ((u8, u8), i32) {
+    match limits.move_time {
+        Some(move_time) => println!("Running search for {} ms with {} threads", move_time.as_millis(), engine.threads()),
+        None => println!("Running search at depth {} with {} threads", limits.depth, engine.threads()),
+    }
+
+    let bar = ProgressBar::new(0);
+    bar.set_style(ProgressStyle::with_template("Move {pos}/{len} [{bar:40.cyan/blue}] {elapsed_precise}").
+        unwrap().
+        progress_chars("#>-"));
+    let progress = bar.clone();
+    engine.set_info_callback(move |info| {
+        if let SearchInfo::RootMove { searched, total, .. } = *info {
+            progress.set_length(total as u64);
+            progress.set_position(searched as u64);
+        }
+    });
+
+    let outcome = engine.search(pos, limits);
+    bar.finish();
+    println!("Search completed in {} seconds", outcome.time.as_secs_f32());
+    println!("Tablebase hits: {}", outcome.stats.tb_hits);
+    (outcome.best_move.expect("Engine move requested in a position without legal moves"), outcome.score)
+}
+
 pub fn is_quiet_position(pos: &mut Position) -> bool {
     let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
     for (_from, to) in legal_moves {
@@ -1,70 +1,90 @@
 pub mod movegen;
 pub mod magics;
+pub mod kpk_bitbase;
+pub mod polyglot_keys;
 pub mod game;
 pub mod evaluation;
+pub mod endgame;
+pub mod syzygy;
+pub mod book;
+pub mod book_builder;
+pub mod pgn;
+pub mod san;
+pub mod epd;
+pub mod chess960;
+pub mod variants;
 pub mod negamax;
+pub mod perft;
+pub mod bench;
+pub mod uci;
+pub mod player;
+pub mod clock;
+pub mod sprt;
+pub mod match_runner;
 
 pub mod parse_input {
-    pub fn user_input_to_square_index(input: &str) -> Result<[u8; 2], String> {
-        if input == "" {
-            return Ok([97, 97]);
-        }
-        else if input == "legal" {
-            return Ok([98, 98]);
-        }
-        if input.len() != 5 {
-            return Err("Invalid input length".to_string());
-        }
-        let start_file = input.chars().nth(0).unwrap();
-        let start_rank = input.chars().nth(1).unwrap();
-        let start_file_index = match start_file {
-            'a' => 0,
-            'b' => 1,
-            'c' => 2,
-            'd' => 3,
-            'e' => 4,
-            'f' => 5,
-            'g' => 6,
-            'h' => 7,
-            _ => return Err("Invalid file in first square".to_string()),
-        };
-        let rank_index = match start_rank {
-            '1' => 0,
-            '2' => 1,
-            '3' => 2,
-            '4' => 3,
-            '5' => 4,
-            '6' => 5,
-            '7' => 6,
-            '8' => 7,
-            _ => return Err("Invalid rank in first square".to_string()),
-        };
+    use types::position::Position;
+    use crate::san;
 
-        let target_file = input.chars().nth(3).unwrap();
-        let target_rank = input.chars().nth(4).unwrap();
-        let target_file_index = match target_file {
-            'a' => 0,
-            'b' => 1,
-            'c' => 2,
-            'd' => 3,
-            'e' => 4,
-            'f' => 5,
-            'g' => 6,
-            'h' => 7,
-            _ => return Err("Invalid file in second square".to_string()),
-        };
-        let target_rank_index = match target_rank {
-            '1' => 0,
-            '2' => 1,
-            '3' => 2,
-            '4' => 3,
-            '5' => 4,
-            '6' => 5,
-            '7' => 6,
-            '8' => 7,
-            _ => return Err("Invalid rank in second square".to_string()),
-        };
+    // Commands and moves that can be entered in the interactive game loop
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub enum UserInput {
+        EngineMove,
+        LegalMoves,
+        Fen,
+        SetFen(String),
+        Move(u8, u8),
+        Undo,
+        Redo,
+        Resign,
+        OfferDraw,
+        Flip,
+        Eval,
+        // The engine plays a move, searched to the given depth or the depth of the game
+        Go(Option<u8>),
+        Hint,
+        Save(String),
+        Load(String),
+        New,
+        Help,
+        Quit,
+    }
 
-        Ok([start_file_index + rank_index * 8, target_file_index + target_rank_index * 8])
+    /* Commands are lower case words, some followed by an argument like "setfen <FEN>" or "save <file>". Everything else
+    / is read as a move in SAN, LAN or UCI and must be legal in the position. */
+    pub fn parse_user_input(input: &str, pos: &mut Position) -> Result<UserInput, String> {
+        let input = input.trim();
+        let (command, argument) = input.split_once(char::is_whitespace)
+            .map_or((input, ""), |(command, argument)| (command, argument.trim()));
+        let required = |usage: &str| if argument.is_empty() { Err(format!("Missing argument, usage: {}", usage)) } else { Ok(argument.to_string()) };
+        match command {
+            "" => Ok(UserInput::EngineMove),
+            "legal" => Ok(UserInput::LegalMoves),
+            "fen" => Ok(UserInput::Fen),
+            "setfen" => required("setfen <FEN>").map(UserInput::SetFen),
+            "undo" => Ok(UserInput::Undo),
+            "redo" => Ok(UserInput::Redo),
+            "resign" => Ok(UserInput::Resign),
+            "draw" => Ok(UserInput::OfferDraw),
+            "flip" => Ok(UserInput::Flip),
+            "eval" => Ok(UserInput::Eval),
+            "go" => match argument.split_whitespace().collect::<Vec<&str>>()[..] {
+                [] => Ok(UserInput::Go(None)),
+                ["depth", depth] => match depth.parse::<u8>() {
+                    Ok(depth) if depth > 0 => Ok(UserInput::Go(Some(depth))),
+                    _ => Err(format!("Invalid depth: {}, the depth must be a number from 1 to 255", depth)),
+                },
+                _ => Err("Invalid go command, usage: go [depth N]".to_string()),
+            },
+            "hint" => Ok(UserInput::Hint),
+            "save" => required("save <file.pgn>").map(UserInput::Save),
+            "load" => required("load <file.pgn>").map(UserInput::Load),
+            "new" => Ok(UserInput::New),
+            "help" => Ok(UserInput::Help),
+            "quit" => Ok(UserInput::Quit),
+            _ => san::parse_move(pos, input)
+                .map(|(from, to)| UserInput::Move(from, to))
+                .map_err(|e| format!("{}, type 'help' to see the commands", e)),
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,101 +1,589 @@
-use std::env;
-use engine::game;
-use types::types_utils::string_from_square;
+use std::{
+    collections::HashMap,
+    env,
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use engine::{
+    bench,
+    book::Book,
+    book_builder::BookBuilder,
+    chess960,
+    clock::TimeControl,
+    epd,
+    game,
+    match_runner::{self, Adjudication, MatchSettings, Opening},
+    negamax::{Engine, EngineOptions, Resources, SearchInfo, SearchLimits},
+    perft,
+    pgn,
+    player::{EngineSettings, Player, PlayerConfig, Thresholds},
+    san,
+    sprt::{Sprt, SprtResult},
+    syzygy::Tablebases,
+    uci,
+    variants,
+};
+use types::{position::Position, variant::Variant};
 
-fn main() {
+const USAGE: &str = "Usage:
+  engine uci [--threads N] [--hash MB] [--deterministic]
+  engine play [--white PLAYER] [--black PLAYER] [--color white|black|random] [--depth N] [--movetime MS]
+              [--clock SECONDS[+INC|dDELAY] | --clock MOVES/SECONDS] [--fen FEN | --chess960 N|random] [--variant NAME] [--book FILE] [--syzygy PATH] [--threads N] [--hash MB] [--deterministic]
+  engine analyse [--fen FEN | --chess960 N|random] [--variant NAME] [--depth N] [--movetime MS] [--syzygy PATH]
+                 [--threads N] [--hash MB] [--deterministic] [--stats]
+  engine match [--engine1 PLAYER] [--engine2 PLAYER] [--games N] [--concurrency N] [--openings FILE.pgn|FILE.epd]
+               [--depth N] [--movetime MS] [--clock TC] [--sprt ELO0,ELO1] [--alpha A] [--beta B] [--pgn FILE]
+               [--maxplies N] [--resignscore CP] [--resignmoves N] [--drawscore CP] [--drawmoves N] [--variant NAME] [--threads N] [--hash MB] [--deterministic]
+  engine perft [--fen FEN | --chess960 N|random] [--variant NAME] [--depth N] [--divide]
+  engine bench [--depth N] [--threads N] [--hash MB] [--stats]
+  engine epd <suite.epd> [--depth N] [--time MS] [--threads N] [--hash MB] [--deterministic]
+  engine makebook <output.bin> <games.pgn>... [--min-games N] [--max-ply N] [--player NAME] [--min-elo N]
+Without a command the engine speaks UCI, variants are chess, kingofthehill, 3check, antichess and crazyhouse.
+Players are human, engine[:depth=N,movetime=MS,threads=N,hash=MB,profile=NAME,material|psqt|mobility|pieces=PERCENT]
+or uci:<path>[,depth=N,movetime=MS,<UCI option>=<value>], profiles are default, aggressive, positional and materialistic.
+Engines resign and offer draws by resignscore=CP,resignmoves=N,drawscore=CP,drawmoves=N, 0 moves turn it off.
+--deterministic searches with one thread and a fixed move order, so searches to a depth always give the same result.";
+
+const ENGINE_FLAGS: [&str; 2] = ["--threads", "--hash"];
+const ENGINE_SWITCHES: [&str; 1] = ["--deterministic"];
+const POSITION_FLAGS: [&str; 3] = ["--fen", "--chess960", "--variant"];
+
+// Depth of engine moves in played games
+const DEFAULT_PLAY_DEPTH: u8 = 4;
+// Time an analysis runs for without a depth or move time
+const DEFAULT_ANALYSIS_TIME: Duration = Duration::from_secs(5);
+const DEFAULT_PERFT_DEPTH: u8 = 4;
+const DEFAULT_MATCH_GAMES: usize = 100;
+// Games of a match are drawn after this many plies
+const DEFAULT_MAX_PLIES: usize = 400;
+// Accepted rate of false positives and of false negatives of an SPRT
+const DEFAULT_SPRT_ERROR: f64 = 0.05;
+
+// Errors of a command, usage errors exit with code 2 and other failures with code 1
+enum CliError {
+    Usage(String),
+    Failure(String),
+}
+
+// The arguments of a command: flags with a value like "--depth 5", switches like "--divide" and the other arguments
+struct Options {
+    values: HashMap<String, String>,
+    switches: Vec<String>,
+    arguments: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String], flags: &[&str], switches: &[&str]) -> Result<Options, CliError> {
+        let mut options = Options {
+            values: HashMap::new(),
+            switches: Vec::new(),
+            arguments: Vec::new(),
+        };
+        let mut args_iter = args.iter();
+        while let Some(arg) = args_iter.next() {
+            if flags.contains(&arg.as_str()) {
+                let value = args_iter.next().ok_or_else(|| CliError::Usage(format!("Missing value for {}", arg)))?;
+                options.values.insert(arg.clone(), value.clone());
+            } else if switches.contains(&arg.as_str()) {
+                options.switches.push(arg.clone());
+            } else if arg.starts_with("--") {
+                return Err(CliError::Usage(format!("Unknown option {}", arg)));
+            } else {
+                options.arguments.push(arg.clone());
+            }
+        }
+        Ok(options)
+    }
+
+    // Parses the value of a flag, None if the flag wasn't given
+    fn get<T: FromStr>(&self, flag: &str) -> Result<Option<T>, CliError> {
+        self.values.get(flag)
+            .map(|value| value.parse().map_err(|_| CliError::Usage(format!("Invalid value for {}: {}", flag, value))))
+            .transpose()
+    }
+
+    fn has(&self, switch: &str) -> bool {
+        self.switches.iter().any(|s| s == switch)
+    }
+
+    // Fails for commands that only take flags
+    fn no_arguments(&self) -> Result<(), CliError> {
+        match self.arguments.first() {
+            Some(argument) => Err(CliError::Usage(format!("Unexpected argument {}", argument))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn main() -> ExitCode {
     env::set_var("RUST_BACKTRACE", "1");
-    env::set_var("RAYON_NUM_THREADS", "12");
-
-    // Get game settings from user
-    let mut input_human_players = String::new();
-    println!("Enter the amount of human players in this game. Amount can be 0 or 1.");
-    std::io::stdin().read_line(&mut input_human_players).unwrap();
-    let input = input_human_players.trim();
-    let human_players = match input.parse::<u8>() {
-        Ok(n) => {
-            if n > 2 {
-                println!("Error: Maximum of 1 human player allowed.");
-                return;
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, args) = match args.split_first() {
+        Some((command, args)) if !command.starts_with("--") || command == "--help" => (command.as_str(), args),
+        _ => ("uci", &args[..]),
+    };
+    let result = match command {
+        "uci" => run_uci(args),
+        "play" => play(args),
+        "analyse" | "analyze" => analyse(args),
+        "perft" => run_perft(args),
+        "bench" => run_bench(args),
+        "match" => run_match(args),
+        "epd" => run_test_suite(args),
+        "makebook" => make_book(args),
+        "help" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => Err(CliError::Usage(format!("Unknown command {}", command))),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(message)) => {
+            eprintln!("Error: {}", message);
+            eprintln!("Run 'engine help' to see the commands and their options.");
+            ExitCode::from(2)
+        },
+        Err(CliError::Failure(message)) => {
+            eprintln!("Error: {}", message);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+// Engine options from --threads, --hash and --deterministic, engines play from the book given with --book
+fn engine_options(options: &Options) -> Result<EngineOptions, CliError> {
+    let defaults = EngineOptions::default();
+    Ok(EngineOptions {
+        threads: options.get("--threads")?.unwrap_or(defaults.threads),
+        hash: options.get("--hash")?.unwrap_or(defaults.hash),
+        deterministic: options.has("--deterministic"),
+        own_book: options.values.contains_key("--book"),
+        ..defaults
+    })
+}
+
+// Start position from --fen, --chess960 and --variant, Chess960 start positions are marked in their state
+fn start_position(options: &Options) -> Result<Position, CliError> {
+    let variant = options.get::<Variant>("--variant")?.unwrap_or_default();
+    match (options.values.get("--fen"), options.values.get("--chess960")) {
+        (Some(_), Some(_)) => Err(CliError::Usage("--fen and --chess960 can't be combined".to_string())),
+        (Some(fen), None) => Position::from_variant_fen(fen, variant).map_err(|e| CliError::Usage(format!("Invalid FEN: {}", e))),
+        (None, Some(number)) => {
+            if variant != Variant::Standard {
+                return Err(CliError::Usage("Chess960 start positions can only be played with standard rules".to_string()));
             }
-            println!("Setting human players to {}.", n);
-            n
+            let (number, pos) = match number.as_str() {
+                "random" => chess960::random_start_position(),
+                _ => match options.get::<u16>("--chess960")? {
+                    Some(number) if number < chess960::START_POSITIONS => (number, chess960::start_position(number)),
+                    _ => return Err(CliError::Usage("Chess960 start positions are numbered from 0 to 959".to_string())),
+                },
+            };
+            println!("Chess960 start position {}.", number);
+            Ok(pos)
         },
-        Err(_) => {
-            println!("Error: Invalid input. Please enter a number from 0 to 1.");
-            return;
-        }
+        (None, None) => Ok(variants::start_position(variant)),
+    }
+}
+
+// Loads the Syzygy tables and the opening book given with --syzygy and --book for the engines
+fn load_resources(options: &Options) -> Result<Resources, CliError> {
+    let mut resources = Resources::default();
+    if let Some(path) = options.values.get("--syzygy") {
+        let tablebases = Tablebases::open(path)
+            .map_err(|e| CliError::Failure(format!("Could not load Syzygy tables {}: {}", path, e)))?;
+        println!("Found {} Syzygy tables with up to {} pieces.", tablebases.len(), tablebases.max_pieces());
+        resources.tablebases = Some(Arc::new(tablebases)).filter(|tablebases| !tablebases.is_empty());
+    }
+    if let Some(path) = options.values.get("--book") {
+        let opening_book = Book::open(path)
+            .map_err(|e| CliError::Failure(format!("Could not load opening book {}: {}", path, e)))?;
+        println!("Loaded opening book with {} entries.", opening_book.len());
+        resources.book = Some(Arc::new(opening_book));
+    }
+    Ok(resources)
+}
+
+fn run_uci(args: &[String]) -> Result<(), CliError> {
+    let options = Options::parse(args, &ENGINE_FLAGS, &ENGINE_SWITCHES)?;
+    options.no_arguments()?;
+    uci::run(engine_options(&options)?);
+    Ok(())
+}
+
+/* Plays a game on the command line, a human plays white against the engine unless --white and --black say otherwise.
+/ --color lets the human choose a side against the engine. --depth and --movetime are the search limits of engines that
+/ don't set their own and of the hints for humans. With --clock, engines without a depth search as long as their
+/ time allows. */
+fn play(args: &[String]) -> Result<(), CliError> {
+    let flags = [&ENGINE_FLAGS[..], &POSITION_FLAGS, &["--white", "--black", "--color", "--depth", "--movetime", "--clock", "--book", "--syzygy"]].concat();
+    let options = Options::parse(args, &flags, &ENGINE_SWITCHES)?;
+    options.no_arguments()?;
+    let depth = options.get::<u8>("--depth")?.unwrap_or(DEFAULT_PLAY_DEPTH);
+    if depth == 0 {
+        return Err(CliError::Usage("The depth must be at least 1".to_string()));
+    }
+    let time_control = options.get::<String>("--clock")?
+        .map(|clock| clock.parse::<TimeControl>().map_err(|e| CliError::Usage(format!("--clock: {}", e))))
+        .transpose()?;
+    let limits = match options.get::<u64>("--movetime")? {
+        Some(move_time) => SearchLimits { depth: options.get("--depth")?.unwrap_or(uci::MAX_DEPTH), move_time: Some(Duration::from_millis(move_time)) },
+        None if time_control.is_some() => SearchLimits { depth: options.get("--depth")?.unwrap_or(uci::MAX_DEPTH), move_time: None },
+        None => SearchLimits { depth, move_time: None },
+    };
+    let defaults = EngineSettings { options: engine_options(&options)?, limits, thresholds: Thresholds::default() };
+    let player = |flag: &str, default: &str| {
+        let spec = options.values.get(flag).map_or(default, String::as_str);
+        PlayerConfig::parse(spec, defaults).map_err(|e| CliError::Usage(format!("{}: {}", flag, e)))
+    };
+    let configs = match options.values.get("--color").map(String::as_str) {
+        None => [player("--white", "human")?, player("--black", "engine")?],
+        Some(_) if options.values.contains_key("--white") || options.values.contains_key("--black") => {
+            return Err(CliError::Usage("--color can't be combined with --white and --black".to_string()));
+        },
+        Some("white") => [PlayerConfig::Human, PlayerConfig::Engine(defaults)],
+        Some("black") => [PlayerConfig::Engine(defaults), PlayerConfig::Human],
+        Some("random") if rand::random::<bool>() => [PlayerConfig::Human, PlayerConfig::Engine(defaults)],
+        Some("random") => [PlayerConfig::Engine(defaults), PlayerConfig::Human],
+        Some(value) => return Err(CliError::Usage(format!("Invalid value for --color: {}, expected white, black or random", value))),
     };
+    let start = start_position(&options)?;
+    let resources = load_resources(&options)?;
+
+    // External engines are set up for the variant of the start position
+    let mut players = [
+        Player::new(&configs[0], &resources, &start).map_err(CliError::Failure)?,
+        Player::new(&configs[1], &resources, &start).map_err(CliError::Failure)?,
+    ];
+    let mut engine = Engine::new(defaults.options);
+    engine.set_resources(resources);
+    let game = game::main_game_loop(&mut engine, &mut players, depth, Some(start), time_control);
+    println!();
+    print!("{}", game.to_pgn());
+    Ok(())
+}
 
-    let mut input_depth = String::new();
-    println!("Enter the depth of the search algorithm. Minimum 1, maximum 8.");
-    std::io::stdin().read_line(&mut input_depth).unwrap();
-    let input = input_depth.trim();
-    let depth = match input.parse::<u8>() {
-        Ok(n) => {
-            if n > 8 {
-                println!("Error: Maximum depth is 8.");
-                return;
+/* Plays a match between two engine configurations and prints the score and the Elo difference after every game.
+/ Every opening is played with both colours, without --openings all games start from the start position. With --sprt
+/ the match stops once the test accepts one of its hypotheses. Engines search with one thread unless told otherwise,
+/ so concurrent games don't compete for the cores. */
+fn run_match(args: &[String]) -> Result<(), CliError> {
+    let flags = [&ENGINE_FLAGS[..], &POSITION_FLAGS, &[
+        "--engine1", "--engine2", "--games", "--concurrency", "--openings", "--clock", "--depth", "--movetime", "--pgn",
+        "--sprt", "--alpha", "--beta", "--maxplies", "--resignscore", "--resignmoves", "--drawscore", "--drawmoves",
+    ]].concat();
+    let options = Options::parse(args, &flags, &ENGINE_SWITCHES)?;
+    options.no_arguments()?;
+    let time_control = options.get::<String>("--clock")?
+        .map(|clock| clock.parse::<TimeControl>().map_err(|e| CliError::Usage(format!("--clock: {}", e))))
+        .transpose()?;
+    let depth = options.get::<u8>("--depth")?;
+    if depth == Some(0) {
+        return Err(CliError::Usage("The depth must be at least 1".to_string()));
+    }
+    let limits = match options.get::<u64>("--movetime")? {
+        Some(move_time) => SearchLimits { depth: depth.unwrap_or(uci::MAX_DEPTH), move_time: Some(Duration::from_millis(move_time)) },
+        None if time_control.is_some() => SearchLimits { depth: depth.unwrap_or(uci::MAX_DEPTH), move_time: None },
+        None => SearchLimits { depth: depth.unwrap_or(DEFAULT_PLAY_DEPTH), move_time: None },
+    };
+    let engine_options = EngineOptions { threads: options.get("--threads")?.unwrap_or(1), ..engine_options(&options)? };
+    let defaults = EngineSettings { options: engine_options, limits, thresholds: Thresholds::default() };
+    let specs = [options.values.get("--engine1"), options.values.get("--engine2")].map(|spec| spec.map_or("engine", String::as_str));
+    let engines = [PlayerConfig::parse(specs[0], defaults), PlayerConfig::parse(specs[1], defaults)];
+    let engines = match engines {
+        [Err(e), _] => return Err(CliError::Usage(format!("--engine1: {}", e))),
+        [_, Err(e)] => return Err(CliError::Usage(format!("--engine2: {}", e))),
+        [_, Ok(PlayerConfig::Human)] | [Ok(PlayerConfig::Human), _] => return Err(CliError::Usage("Humans can't play in matches".to_string())),
+        [Ok(first), Ok(second)] => [first, second],
+    };
+
+    let defaults = Thresholds::default();
+    let thresholds = Thresholds {
+        resign_score: options.get("--resignscore")?.unwrap_or(defaults.resign_score),
+        resign_moves: options.get("--resignmoves")?.unwrap_or(defaults.resign_moves),
+        draw_score: options.get("--drawscore")?.unwrap_or(defaults.draw_score),
+        draw_moves: options.get("--drawmoves")?.unwrap_or(defaults.draw_moves),
+    };
+    let sprt = match options.values.get("--sprt") {
+        Some(bounds) => {
+            let elo = bounds.split_once(',').and_then(|(elo0, elo1)| Some((elo0.trim().parse().ok()?, elo1.trim().parse().ok()?)));
+            let Some((elo0, elo1)) = elo.filter(|(elo0, elo1): &(f64, f64)| elo0 < elo1) else {
+                return Err(CliError::Usage(format!("Invalid value for --sprt: {}, expected ELO0,ELO1 with ELO0 < ELO1", bounds)));
+            };
+            let alpha = options.get::<f64>("--alpha")?.unwrap_or(DEFAULT_SPRT_ERROR);
+            let beta = options.get::<f64>("--beta")?.unwrap_or(DEFAULT_SPRT_ERROR);
+            if [alpha, beta].iter().any(|&error| error <= 0.0 || error >= 0.5) {
+                return Err(CliError::Usage("--alpha and --beta must be between 0 and 0.5".to_string()));
             }
-            println!("Setting depth to {}.", n);
-            n
+            Some(Sprt { elo0, elo1, alpha, beta })
+        },
+        None => None,
+    };
+    let start = start_position(&options)?;
+    let openings = match options.values.get("--openings") {
+        Some(_) if options.values.contains_key("--fen") || options.values.contains_key("--chess960") => {
+            return Err(CliError::Usage("--openings can't be combined with --fen and --chess960".to_string()));
         },
-        Err(_) => {
-            println!("Error: Invalid input. Please enter a number from 1 to 8.");
-            return;
+        Some(path) => match_runner::read_openings(path).map_err(CliError::Failure)?,
+        None => vec![Opening { start, moves: Vec::new() }],
+    };
+    let settings = MatchSettings {
+        engines,
+        resources: Default::default(),
+        names: specs.map(str::to_string),
+        openings,
+        games: options.get("--games")?.unwrap_or(DEFAULT_MATCH_GAMES),
+        concurrency: options.get("--concurrency")?.unwrap_or(1),
+        time_control,
+        adjudication: Adjudication { thresholds, max_plies: options.get("--maxplies")?.unwrap_or(DEFAULT_MAX_PLIES) },
+        sprt,
+    };
+
+    println!("Playing {} games of {} against {} with {} openings.", settings.games, specs[0], specs[1], settings.openings.len());
+    let result = match_runner::run(&settings, |number, pgn_game, score| {
+        let (elo, margin) = score.elo();
+        let termination = pgn_game.header("Termination").map_or(String::new(), |termination| format!(" ({})", termination));
+        let mut line = format!("Game {:>4}: {} vs {} {}{:<15} Score {}-{}-{}  Elo {:.1} +/- {:.1}",
+            number + 1, pgn_game.header("White").unwrap_or("?"), pgn_game.header("Black").unwrap_or("?"), pgn_game.result,
+            termination, score.wins, score.losses, score.draws, elo, margin);
+        if let Some(sprt) = settings.sprt {
+            let (lower, upper) = sprt.bounds();
+            line.push_str(&format!("  LLR {:.2} ({:.2}, {:.2})", sprt.llr(score), lower, upper));
         }
+        println!("{}", line);
+    }).map_err(CliError::Failure)?;
+
+    let score = result.score;
+    let (elo, margin) = score.elo();
+    println!("Score of {} against {}: {} wins, {} losses, {} draws in {} games ({:.1}%)",
+        specs[0], specs[1], score.wins, score.losses, score.draws, score.games(), score.ratio() * 100.0);
+    println!("Elo difference: {:.1} +/- {:.1}", elo, margin);
+    match result.sprt {
+        Some(SprtResult::AcceptH0) => println!("SPRT: H0 accepted, the first engine gains at most {} Elo.", settings.sprt.unwrap().elo0),
+        Some(SprtResult::AcceptH1) => println!("SPRT: H1 accepted, the first engine gains at least {} Elo.", settings.sprt.unwrap().elo1),
+        Some(SprtResult::Continue) => println!("SPRT: no result after {} games.", score.games()),
+        None => {},
+    }
+    if let Some(path) = options.values.get("--pgn") {
+        pgn::write_file(path, &result.games).map_err(|e| CliError::Failure(format!("Could not write {}: {}", path, e)))?;
+        println!("Wrote {} games to {}.", result.games.len(), path);
+    }
+    Ok(())
+}
+
+/* Searches a position and prints every finished iteration. Without a depth or move time the search runs for five
+/ seconds, with only a depth it searches to that depth right away. --stats adds the statistics of every iteration and
+/ of the whole search. */
+fn analyse(args: &[String]) -> Result<(), CliError> {
+    let flags = [&ENGINE_FLAGS[..], &POSITION_FLAGS, &["--depth", "--movetime", "--syzygy"]].concat();
+    let options = Options::parse(args, &flags, &[ENGINE_SWITCHES[0], "--stats"])?;
+    options.no_arguments()?;
+    let limits = match (options.get::<u8>("--depth")?, options.get::<u64>("--movetime")?) {
+        (Some(0), _) => return Err(CliError::Usage("The depth must be at least 1".to_string())),
+        (Some(depth), None) => SearchLimits { depth, move_time: None },
+        (depth, Some(move_time)) => SearchLimits { depth: depth.unwrap_or(uci::MAX_DEPTH), move_time: Some(Duration::from_millis(move_time)) },
+        (None, None) => SearchLimits { depth: uci::MAX_DEPTH, move_time: Some(DEFAULT_ANALYSIS_TIME) },
     };
+    let mut engine = Engine::new(engine_options(&options)?);
+    let mut pos = start_position(&options)?;
+    engine.set_resources(load_resources(&options)?);
 
-    let mut input_fen = String::new();
-    println!("Enter a valid FEN string to import it or press enter to start a new game.");
-    std::io::stdin().read_line(&mut input_fen).unwrap();
-    let input = input_fen.trim();
-    let fen = if !input.is_empty() {
-        match input.parse::<String>() {
-            Ok(f) => {
-                Some(f)
-            },
-            Err(_) => {
-                println!("Error: Invalid input. Please enter a valid FEN string.");
-                None
+    let info_pos = pos.clone();
+    let stats = options.has("--stats");
+    engine.set_info_callback(move |info| {
+        if let SearchInfo::Iteration(outcome) = info {
+            if let Some((from, to)) = outcome.best_move {
+                println!("depth {:>2}  score {:<10}  {:>12} nodes {:>8.2}s  {}",
+                    outcome.depth, uci::score_to_uci(outcome.score), outcome.nodes, outcome.time.as_secs_f64(),
+                    san::move_to_san(&mut info_pos.clone(), from, to));
             }
-        } 
+            if stats {
+                let iteration = outcome.stats.last_iteration();
+                println!("          iteration {:>12} nodes {:>8.2}s  branching factor {:.2}  first move cutoffs {:.1}%",
+                    iteration.nodes, iteration.time.as_secs_f64(), iteration.branching_factor(), outcome.stats.first_move_cutoff_rate());
+            }
+        }
+    });
+    let outcome = engine.search(&pos, limits);
+    match outcome.best_move {
+        Some((from, to)) => println!("Best move {} ({}), score {}, depth {}, {} nodes in {:.2} seconds",
+            san::move_to_san(&mut pos, from, to), san::move_to_uci(&pos, from, to), uci::score_to_uci(outcome.score),
+            outcome.depth, outcome.nodes, outcome.time.as_secs_f64()),
+        None => println!("There are no legal moves in this position."),
+    }
+    if options.has("--stats") {
+        println!("{}", outcome.stats);
+    }
+    Ok(())
+}
+
+// Counts the leaf nodes of the move tree of a position, with --divide the count below every move is printed
+fn run_perft(args: &[String]) -> Result<(), CliError> {
+    let flags = [&POSITION_FLAGS[..], &["--depth"]].concat();
+    let options = Options::parse(args, &flags, &["--divide"])?;
+    options.no_arguments()?;
+    let depth = options.get::<u8>("--depth")?.unwrap_or(DEFAULT_PERFT_DEPTH);
+    let mut pos = start_position(&options)?;
+
+    let start_time = Instant::now();
+    let nodes = if options.has("--divide") {
+        let mut counts: Vec<(String, u64)> = perft::divide(&mut pos, depth).into_iter()
+            .map(|((from, to), count)| (san::move_to_uci(&pos, from, to), count))
+            .collect();
+        counts.sort();
+        for (uci_move, count) in &counts {
+            println!("{}: {}", uci_move, count);
+        }
+        counts.iter().map(|(_, count)| count).sum()
+    } else {
+        perft::perft(&mut pos, depth)
+    };
+    let time = start_time.elapsed();
+    println!("Depth {}: {} nodes in {:.2} seconds ({:.0} nodes per second)",
+        depth, nodes, time.as_secs_f64(), nodes as f64 / time.as_secs_f64().max(0.001));
+    Ok(())
+}
+
+/* Searches the bench positions to a fixed depth and prints the speed of the search. The bench searches
+/ deterministically with one thread unless --threads says otherwise, the node count of such a bench is its signature. */
+fn run_bench(args: &[String]) -> Result<(), CliError> {
+    let flags = [&ENGINE_FLAGS[..], &["--depth"]].concat();
+    let options = Options::parse(args, &flags, &["--stats"])?;
+    options.no_arguments()?;
+    let depth = options.get::<u8>("--depth")?.unwrap_or(bench::DEFAULT_DEPTH).max(1);
+    let threads = options.get("--threads")?.unwrap_or(1);
+    let mut engine = Engine::new(EngineOptions { threads, deterministic: threads == 1, ..engine_options(&options)? });
+
+    let result = bench::run(&mut engine, depth);
+    for (index, outcome) in result.outcomes.iter().enumerate() {
+        println!("Position {:>2}/{}: {:>12} nodes {:>8.2}s", index + 1, result.outcomes.len(), outcome.nodes, outcome.time.as_secs_f64());
+    }
+    println!("Searched {} positions to depth {} with {} threads in {:.2} seconds: {} nodes, {:.0} nodes per second",
+        result.outcomes.len(), depth, engine.threads(), result.time.as_secs_f64(), result.nodes, result.nodes_per_second());
+    if options.has("--stats") {
+        println!("{}", result.stats);
+    }
+    if engine.options().deterministic {
+        println!("Bench signature: {}", result.nodes);
     } else {
-        None
+        println!("Searches with several threads aren't deterministic, the bench signature needs --threads 1.");
+    }
+    Ok(())
+}
+
+fn make_book(args: &[String]) -> Result<(), CliError> {
+    let options = Options::parse(args, &["--min-games", "--max-ply", "--player", "--min-elo"], &[])?;
+    let mut builder = BookBuilder::new();
+    builder.min_games = options.get("--min-games")?.unwrap_or(builder.min_games);
+    builder.max_ply = options.get("--max-ply")?.unwrap_or(builder.max_ply);
+    builder.player = options.get("--player")?;
+    builder.min_elo = options.get("--min-elo")?;
+    let Some((output, files)) = options.arguments.split_first().filter(|(_, files)| !files.is_empty()) else {
+        return Err(CliError::Usage("makebook needs an output file and at least one PGN file".to_string()));
     };
 
+    for file in files {
+        let added = builder.add_file(file).map_err(|e| CliError::Failure(format!("Could not read {}: {}", file, e)))?;
+        println!("Added {} games from {}.", added, file);
+    }
+    let entries = builder.write(output).map_err(|e| CliError::Failure(format!("Could not write {}: {}", output, e)))?;
+    println!("Wrote {} entries from {} games to {}.", entries, builder.games(), output);
+    Ok(())
+}
+
+/* Searches every position of an EPD test suite and checks the found move against its bm and am operations.
+/ Without a time limit every position is searched to the given depth (4 by default). With a time limit, iterative
+/ deepening runs up to the given depth or as deep as the time allows. */
+fn run_test_suite(args: &[String]) -> Result<(), CliError> {
+    let flags = [&ENGINE_FLAGS[..], &["--depth", "--time"]].concat();
+    let options = Options::parse(args, &flags, &ENGINE_SWITCHES)?;
+    let [file] = &options.arguments[..] else {
+        return Err(CliError::Usage("epd needs exactly one EPD file".to_string()));
+    };
+    let move_time = options.get::<u64>("--time")?.map(Duration::from_millis);
+    let limits = SearchLimits {
+        depth: options.get("--depth")?.unwrap_or(if move_time.is_some() { uci::MAX_DEPTH } else { 4 }),
+        move_time,
+    };
 
-    // Main game loop
-    let move_history = game::main_game_loop(human_players, depth, fen);
+    let records = epd::read_file(file).map_err(|e| CliError::Failure(format!("Could not read {}: {}", file, e)))?;
+    let mut engine = Engine::new(engine_options(&options)?);
+    let mut tested = 0;
+    let mut solved = 0;
+    let mut total_time = Duration::ZERO;
+    let mut total_nodes = 0;
 
-    // Print the move history
-    let mut move_history_iter = move_history.iter().peekable();
-    loop {
-        match move_history_iter.next() {
-            Some((start, end)) => {
-                print!("{} ", string_from_square(*start));
-                print!("{} ", string_from_square(*end));
-                if move_history_iter.peek_mut().is_some() {
-                    print!("| ");
-                }
+    for (index, record) in records.into_iter().enumerate() {
+        let mut record = match record {
+            Ok(record) if record.is_test() => record,
+            Ok(_) => {
+                println!("Skipping position {}: no bm or am operation", index + 1);
+                continue;
             },
-            None => {
-                println!();
-                break;
-            }
+            Err(e) => {
+                println!("Skipping position {}: {}", index + 1, e);
+                continue;
+            },
+        };
+        let id = record.id().map(str::to_string).unwrap_or_else(|| format!("#{}", index + 1));
+
+        let outcome = engine.search(&record.position, limits);
+        let Some((from, to)) = outcome.best_move else {
+            println!("Skipping {}: no legal moves", id);
+            continue;
+        };
+        let is_solved = record.is_solved_by((from, to));
+        let found = san::move_to_san(&mut record.position, from, to);
+        let mut expected: Vec<String> = Vec::new();
+        for &(from, to) in &record.best_moves {
+            expected.push(san::move_to_san(&mut record.position, from, to));
+        }
+        for &(from, to) in &record.avoid_moves {
+            expected.push(format!("not {}", san::move_to_san(&mut record.position, from, to)));
         }
-    }
 
-    // Wait for the user to press enter before closing the program
-    let mut input = String::new();
-    println!("Press enter to close the game.");
-    std::io::stdin().read_line(&mut input).unwrap();
+        tested += 1;
+        if is_solved {
+            solved += 1;
+        }
+        total_time += outcome.time;
+        total_nodes += outcome.nodes;
+        println!("{:<16} {:<8} {:<7} expected {:<16} depth {:>2} {:>8.2}s {:>12} nodes",
+            id, found, if is_solved { "solved" } else { "failed" }, expected.join(" "),
+            outcome.depth, outcome.time.as_secs_f64(), outcome.nodes);
+    }
 
+    let nodes_per_second = total_nodes as f64 / total_time.as_secs_f64().max(0.001);
+    println!("Solved {} of {} positions in {:.2} seconds, {} nodes ({:.0} nodes per second)",
+        solved, tested, total_time.as_secs_f64(), total_nodes, nodes_per_second);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use types::position::Position;
-    use engine::movegen;
+    use types::{
+        fen::FenError,
+        position::Position,
+        state::GameResult,
+        types_utils::{fen_from_pos, shredder_fen_from_pos},
+        variant::Variant,
+        Color,
+        DropMove,
+        Piece,
+        Results,
+    };
+    use std::{sync::Arc, time::Duration};
+    use engine::{bench, book, book_builder, chess960, clock, endgame, epd, evaluation, game, movegen, perft, pgn, san, syzygy, uci, variants};
+    use engine::negamax::{Engine, EngineOptions, IterationStats, Resources, SearchInfo, SearchLimits, SearchStats};
+    use engine::parse_input::{parse_user_input, UserInput};
+    use engine::player::{EngineSettings, Player, PlayerConfig, Thresholds};
+    use engine::match_runner::{self, Adjudication, MatchSettings, Opening};
+    use engine::sprt::{self, Score, Sprt, SprtResult};
     use types::bitboard::BitBoard;
 
     #[test]
@@ -134,6 +622,13 @@ mod tests {
     }
 
 
+    #[test]
+    fn diagonal_shifts_do_not_wrap() {
+        let bb = BitBoard::from_square(0) | BitBoard::from_square(7);
+        assert_eq!(bb.diagonal_north_east(), BitBoard::from_square(9));
+        assert_eq!(bb.diagonal_north_west(), BitBoard::from_square(14));
+    }
+
     #[test]
     fn test_piece_at_for_empty() {
         let square = 16;
@@ -220,6 +715,1180 @@ mod tests {
         assert_eq!(moves, BitBoard::from_u64(22517998136852480));
     } */
 
+    #[test]
+    fn evaluation_is_color_symmetric() {
+        let fens = [
+            ("r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP1B1PPP/R2QKB1R w - - 0 1",
+             "r2qkb1r/pp1b1ppp/2n1pn2/2pp4/3P4/2N1PN2/PP2BPPP/R1BQ1RK1 b - - 0 1"),
+            ("6k1/1R3ppp/8/3N4/8/8/r4PPP/6K1 w - - 0 1",
+             "6k1/R4ppp/8/8/3n4/8/1r3PPP/6K1 b - - 0 1"),
+        ];
+        for (fen, mirrored_fen) in fens {
+            let mut pos = Position::from_fen(fen.to_string());
+            let mut mirrored_pos = Position::from_fen(mirrored_fen.to_string());
+            assert_eq!(evaluation::main_evaluation(&mut pos), evaluation::main_evaluation(&mut mirrored_pos));
+        }
+    }
+
+    #[test]
+    fn material_imbalance_matches_stockfish() {
+        // Values of imbalance_total from the Stockfish evaluation guide, a lone bishop pair is worth 1438 / 16
+        let fens = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 0),
+            ("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", 89),
+            ("2b1kb2/8/8/8/8/8/8/4K3 b - - 0 1", -89),
+            ("2b1kn2/pppppppp/8/8/8/8/PPPPPPPP/2B1KB2 w - - 0 1", 22),
+            ("3rkr2/pppppppp/8/8/8/8/PPPPPPPP/3QK3 w - - 0 1", 110),
+            ("r3k3/pppppppp/8/8/8/8/PPPPPPPP/1NB1K3 w - - 0 1", 234),
+        ];
+        for (fen, imbalance) in fens {
+            let pos = Position::from_fen(fen.to_string());
+            assert_eq!(evaluation::material_imbalance(&pos), imbalance, "{}", fen);
+        }
+
+        // Positions with the same material share a cache entry, a cache hit gives the value of a fresh computation
+        let mut cache = evaluation::ImbalanceCache::default();
+        let pos = Position::from_fen("r3k3/pppppppp/8/8/8/8/PPPPPPPP/1NB1K3 w - - 0 1".to_string());
+        let same_material = Position::from_fen("4k2r/pppppppp/8/8/8/8/PPPPPPPP/4KB1N b - - 0 1".to_string());
+        assert_eq!(cache.imbalance(&pos), 234);
+        assert_eq!(cache.imbalance(&same_material), evaluation::material_imbalance(&same_material));
+        assert_eq!(cache.imbalance(&pos), evaluation::material_imbalance(&pos));
+        assert_eq!(cache.len(), 1);
+        cache.imbalance(&Position::new());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn opposite_colored_bishops_are_drawish() {
+        let mut opposite_bishops = Position::from_fen("4k3/8/5b2/8/3P4/2PB4/8/4K3 w - - 0 1".to_string());
+        let mut same_colored_bishops = Position::from_fen("4k3/8/4b3/8/3P4/2PB4/8/4K3 w - - 0 1".to_string());
+        let opposite_eval = evaluation::main_evaluation(&mut opposite_bishops);
+        let same_eval = evaluation::main_evaluation(&mut same_colored_bishops);
+        assert!(opposite_eval > 0);
+        assert!(opposite_eval < same_eval);
+    }
+
+    #[test]
+    fn endgame_scale_factors_match_stockfish() {
+        let scale_factor = |fen: &str, endgame_evaluation| evaluation::scale_factor(&mut Position::from_fen(fen.to_string()), endgame_evaluation);
+        // Rook ending with the pawns of the stronger side on one flank and a defending pawn next to the defending king
+        assert_eq!(scale_factor("r5k1/5pp1/8/8/8/8/5PPP/3R2K1 w - - 0 1", 100), 36);
+        assert_eq!(scale_factor("3r2k1/5ppp/8/8/8/8/5PP1/R5K1 w - - 0 1", -100), 36);
+        // Not a Stockfish case: a defending king in front of all pawns of the stronger side holds most rook endings
+        assert_eq!(scale_factor("7r/8/8/4k3/8/4P3/8/R3K3 w - - 0 1", 100), 24);
+        // Without a pawn next to it and with the king aside of the pawn, 36 + 7 * the pawns of the stronger side
+        assert_eq!(scale_factor("7r/8/8/8/2k5/4P3/8/R3K3 w - - 0 1", 100), 43);
+        // Queen against minor pieces, 37 + 3 * the minor pieces of the side without the queen
+        assert_eq!(scale_factor("4k3/8/8/8/2nb4/8/8/3QK3 w - - 0 1", 100), 43);
+        assert_eq!(scale_factor("3qk3/8/8/2NB4/8/8/8/4K3 b - - 0 1", -100), 43);
+        assert_eq!(scale_factor("4k3/8/8/8/2n5/8/8/3QK3 w - - 0 1", 100), 40);
+    }
+
+    #[test]
+    fn initiative_matches_stockfish() {
+        let initiative = |fen: &str, endgame_evaluation| evaluation::initiative(&mut Position::from_fen(fen.to_string()), endgame_evaluation);
+        /* King and pawn against king with the kings facing each other: 9 * 1 passed pawn + 12 * 1 pawn + 9 * -7 outflanking
+        / + 51 for a pure pawn ending - 43 as almost unwinnable - 110 gives -144, but never more than the evaluation itself */
+        assert_eq!(initiative("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", 200), -144);
+        assert_eq!(initiative("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", 100), -100);
+        assert_eq!(initiative("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", 0), 0);
+        /* Pawns on both flanks and an infiltrated king: 9 * 1 passed pawn + 12 * 3 pawns + 9 * 0 outflanking + 21 for both
+        / flanks + 24 for the infiltration + 51 for a pure pawn ending - 110 gives 31, added in favour of the better side */
+        assert_eq!(initiative("8/p4k2/8/3K4/8/8/P5P1/8 w - - 0 1", 50), 31);
+        assert_eq!(initiative("8/p4k2/8/3K4/8/8/P5P1/8 w - - 0 1", -50), -31);
+    }
+
+    #[test]
+    fn kbnk_drives_king_to_bishop_corner() {
+        let mut bishop_corner = Position::from_fen("8/8/8/8/8/2K5/8/k1B1N3 w - - 0 1".to_string());
+        let mut wrong_corner = Position::from_fen("8/8/8/8/8/5K2/8/2B1N2k w - - 0 1".to_string());
+        assert!(evaluation::main_evaluation(&mut bishop_corner) > evaluation::main_evaluation(&mut wrong_corner));
+        assert!(evaluation::main_evaluation(&mut wrong_corner) > endgame::KNOWN_WIN);
+    }
+
+    #[test]
+    fn known_draws_evaluate_to_zero() {
+        for fen in ["8/8/3k4/8/8/3NN3/8/4K3 w - - 0 1", "8/8/3kb3/8/8/3N4/8/4K3 b - - 0 1", "8/8/3k4/8/8/8/8/4K3 w - - 0 1"] {
+            let mut pos = Position::from_fen(fen.to_string());
+            assert_eq!(evaluation::main_evaluation(&mut pos), 0);
+        }
+    }
+
+    #[test]
+    fn kpk_bitbase_knows_wins_and_draws() {
+        let wins = [
+            "4k3/4P3/4K3/8/8/8/8/8 w - - 0 1",
+            "8/8/8/8/8/8/k6P/7K w - - 0 1",
+            "8/8/8/8/8/4k3/4p3/4K3 b - - 0 1",
+        ];
+        for fen in wins {
+            let mut pos = Position::from_fen(fen.to_string());
+            assert!(evaluation::main_evaluation(&mut pos) > endgame::KNOWN_WIN, "{}", fen);
+        }
+
+        let draws = [
+            "4k3/4P3/4K3/8/8/8/8/8 b - - 0 1",
+            "k7/8/K7/P7/8/8/8/8 w - - 0 1",
+            "8/8/8/8/8/4k3/4P3/4K3 w - - 0 1",
+            "8/8/8/8/8/4k3/4p3/4K3 w - - 0 1",
+        ];
+        for fen in draws {
+            let mut pos = Position::from_fen(fen.to_string());
+            assert_eq!(evaluation::main_evaluation(&mut pos), 0, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn syzygy_probes_tables_and_filters_root_moves() {
+        // Tables of the Syzygy 3-4-5 set, the expected values are those of the reference probing code
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/syzygy");
+        let tablebases = syzygy::Tablebases::open(directory).unwrap();
+        assert_eq!(tablebases.len(), 6);
+        assert_eq!(tablebases.max_pieces(), 4);
+        assert_eq!(tablebases.cardinality(3), 3);
+
+        let position = |fen: &str| {
+            let mut pos = Position::from_fen(fen.to_string());
+            game::update_attackers(&mut pos, !BitBoard::empty());
+            pos
+        };
+
+        let expected = [
+            ("8/8/8/2R5/1K6/8/5k2/8 w - - 0 1", syzygy::WDL_WIN, 21),
+            ("8/3k4/8/8/8/8/4P3/3K4 w - - 0 1", syzygy::WDL_DRAW, 0),
+            // With the pawn on black's side, the table is probed with colors switched
+            ("8/5p2/6k1/K7/8/8/8/8 w - - 0 1", syzygy::WDL_LOSS, -2),
+            ("8/8/8/2K5/5kp1/8/8/8 b - - 0 1", syzygy::WDL_WIN, 1),
+            ("8/4r3/8/K7/4R3/8/8/5k2 w - - 0 1", syzygy::WDL_WIN, 1),
+            ("8/8/R2k4/8/8/K7/8/4r3 b - - 0 1", syzygy::WDL_DRAW, 0),
+            ("7k/1R6/8/8/8/8/4r3/K7 w - - 0 1", syzygy::WDL_DRAW, 0),
+        ];
+        for (fen, wdl, dtz) in expected {
+            assert_eq!(tablebases.probe_wdl(&mut position(fen)), Some(wdl), "{}", fen);
+            assert_eq!(tablebases.probe_dtz(&mut position(fen)), Some(dtz), "{}", fen);
+        }
+        // There is no KQvKQ table
+        assert_eq!(tablebases.probe_wdl(&mut position("8/k7/8/3q4/8/7Q/2K5/8 b - - 0 1")), None);
+
+        // Only the moves with the shortest distance to zero are kept, leaving black 20 plies
+        let mut krk = position("8/8/8/2R5/1K6/8/5k2/8 w - - 0 1");
+        let legal_moves = movegen::get_all_legal_moves_for_color(krk.state.active_player, &mut krk);
+        let tablebase_moves = tablebases.filter_root_moves(&mut krk, &legal_moves, syzygy::TB_PIECES).unwrap();
+        assert!(!tablebase_moves.is_empty() && tablebase_moves.len() < legal_moves.len());
+        for (from, to) in legal_moves {
+            let mut new_pos = krk.clone();
+            game::apply_move(&mut new_pos, from, to);
+            assert_eq!(tablebases.probe_dtz(&mut new_pos) == Some(-20), tablebase_moves.contains(&(from, to)));
+        }
+
+        // Only the engine that has the tablebases probes them
+        let mut engine = Engine::new(EngineOptions { deterministic: true, ..EngineOptions::default() });
+        let outcome = engine.search(&krk, SearchLimits { depth: 1, move_time: None });
+        assert_eq!(outcome.stats.tb_hits, 0);
+        engine.set_resources(Resources { tablebases: Some(Arc::new(tablebases)), ..Resources::default() });
+        let outcome = engine.search(&krk, SearchLimits { depth: 1, move_time: None });
+        assert!(tablebase_moves.contains(&outcome.best_move.unwrap()));
+        assert!(outcome.stats.tb_hits > 0);
+
+        // A file with the name of a table but without the magic number of one is an error for the caller to report
+        let corrupted = std::env::temp_dir().join(format!("corrupted_syzygy_{}", std::process::id()));
+        std::fs::create_dir_all(&corrupted).unwrap();
+        std::fs::copy(format!("{}/KQvK.rtbw", directory), corrupted.join("KQvK.rtbw")).unwrap();
+        std::fs::write(corrupted.join("KRvK.rtbw"), b"not a table").unwrap();
+        let error = syzygy::Tablebases::open(corrupted.to_str().unwrap()).err().unwrap();
+        std::fs::remove_dir_all(&corrupted).unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("KRvK.rtbw"));
+    }
+
+    #[test]
+    fn polyglot_keys_match_reference_values() {
+        let lines = [
+            vec![
+                ((12, 28), 0x823C9B50FD114196),
+                ((51, 35), 0x0756B94461C50FB0),
+                ((28, 36), 0x662FAFB965DB29D4),
+                ((53, 37), 0x22A48B5A8E47FF78),
+                ((4, 12), 0x652A607CA3F242C1),
+                ((60, 53), 0x00FDD303C946BDD9),
+            ],
+            vec![
+                ((8, 24), 0),
+                ((49, 33), 0),
+                ((15, 31), 0),
+                ((33, 25), 0),
+                ((10, 26), 0x3C8123EA7B067637),
+                ((25, 18), 0),
+                ((0, 16), 0x5C3F9B829B279560),
+            ],
+        ];
+        for line in lines {
+            let mut pos = Position::new();
+            assert_eq!(book::polyglot_key(&pos), 0x463B96181691FC9C);
+            for ((from, to), key) in line {
+                game::apply_move(&mut pos, from, to);
+                if key != 0 {
+                    assert_eq!(book::polyglot_key(&pos), key);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn polyglot_book_moves_are_decoded() {
+        let start_key: u64 = 0x463B96181691FC9C;
+        let mut bytes = Vec::new();
+        // e2e4 with weight 10, d2d4 with weight 5 and an illegal e2e5
+        for (book_move, weight) in [(12u16 << 6 | 28, 10u16), (11 << 6 | 27, 5), (12 << 6 | 36, 20)] {
+            bytes.extend_from_slice(&start_key.to_be_bytes());
+            bytes.extend_from_slice(&book_move.to_be_bytes());
+            bytes.extend_from_slice(&weight.to_be_bytes());
+            bytes.extend_from_slice(&0u32.to_be_bytes());
+        }
+        let opening_book = book::Book::from_bytes(&bytes).unwrap();
+
+        let mut pos = Position::new();
+        assert_eq!(opening_book.moves(&mut pos), vec![((12, 28), 10), ((11, 27), 5)]);
+        assert_eq!(opening_book.pick_move(&mut pos, true), Some((12, 28)));
+        assert_eq!(opening_book.book_move(&mut pos, 0, true), None);
+
+        // Engines share the book, but each decides itself whether and how long it plays from it
+        let resources = Resources { book: Some(Arc::new(opening_book)), ..Resources::default() };
+        let engine = |options: EngineOptions| {
+            let mut engine = Engine::new(options);
+            engine.set_resources(resources.clone());
+            engine
+        };
+        let own_book = EngineOptions { own_book: true, best_book_move: true, ..EngineOptions::default() };
+        assert_eq!(engine(own_book).book_move(&mut pos), Some((12, 28)));
+        assert_eq!(engine(EngineOptions::default()).book_move(&mut pos), None);
+        assert_eq!(engine(EngineOptions { book_depth: 0, ..own_book }).book_move(&mut pos), None);
+
+        // Castling is stored as the king capturing its own rook
+        let castling = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".to_string());
+        assert_eq!(book::encode_move(&castling, 4, 6), 4 << 6 | 7);
+        assert_eq!(book::decode_move(&castling, 60 << 6 | 56), Some((60, 58)));
+    }
+
+    #[test]
+    fn book_builder_weights_moves_by_results() {
+        let games = pgn::read_games(r#"
+[Event "Test"]
+[White "Alice"]
+[Black "Bob"]
+[WhiteElo "2400"]
+[BlackElo "2300"]
+[Result "1-0"]
+
+1. e4 e5 {the main line} 2. Nf3 (2. f4 exf4) Nc6 3. Bc4 $1 Bc5 4. O-O Nf6 1-0
+
+[White "Bob"]
+[Black "Alice"]
+[Result "1/2-1/2"]
+
+1.e4 e5 2.Nf3 Nf6 ; Petrov
+1/2-1/2
+
+[White "Carol"]
+[Black "Bob"]
+[WhiteElo "2000"]
+[Result "0-1"]
+
+1. d4 d5 0-1
+"#);
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[0].header("Black"), Some("Bob"));
+        let main_line: Vec<&str> = games[0].moves.iter().map(|pgn_move| pgn_move.san.as_str()).collect();
+        assert_eq!(main_line, ["e4", "e5", "Nf3", "Nc6", "Bc4", "Bc5", "O-O", "Nf6"]);
+        assert_eq!(games[1].moves.len(), 4);
+        assert_eq!(games[2].result, "0-1");
+
+        let mut builder = book_builder::BookBuilder::new();
+        assert_eq!(games.iter().filter(|game| builder.add_game(game)).count(), 3);
+        let opening_book = book::Book::from_bytes(&builder.to_bytes()).unwrap();
+        // e4 scored a win and a draw, d4 only lost
+        let mut pos = Position::new();
+        assert_eq!(opening_book.moves(&mut pos), vec![((12, 28), 3)]);
+        for (from, to) in [(12, 28), (52, 36), (6, 21), (57, 42), (5, 26), (61, 34)] {
+            game::apply_move(&mut pos, from, to);
+        }
+        // Castling is played and stored as the king capturing its rook
+        assert_eq!(opening_book.moves(&mut pos), vec![((4, 6), 2)]);
+
+        let mut builder = book_builder::BookBuilder::new();
+        builder.min_elo = Some(2200);
+        builder.max_ply = 1;
+        assert_eq!(games.iter().filter(|game| builder.add_game(game)).count(), 1);
+        assert_eq!(builder.entries().len(), 1);
+
+        let mut builder = book_builder::BookBuilder::new();
+        builder.player = Some("Bob".to_string());
+        assert_eq!(games.iter().filter(|game| builder.add_game(game)).count(), 3);
+        // Bob lost all his moves as black in the first game, drew e4 and Nf3 and won with d5
+        let weights: Vec<(u16, u16)> = builder.entries().iter().map(|entry| (entry.book_move, entry.weight)).collect();
+        assert_eq!(weights.len(), 3);
+        assert!(weights.contains(&(12 << 6 | 28, 1)));
+        assert!(weights.contains(&(6 << 6 | 21, 1)));
+        assert!(weights.contains(&(51 << 6 | 35, 2)));
+
+        builder.min_games = 2;
+        assert!(builder.entries().is_empty());
+    }
+
+    #[test]
+    fn pgn_games_are_written_and_read() {
+        let moves = [(12, 28), (52, 36), (5, 26), (57, 42), (3, 39), (62, 45), (39, 53)];
+        let mut game = pgn::PgnGame::from_moves(&Position::new(), &moves);
+        game.set_result(GameResult(Results::WHITE_VICTORY));
+        game.moves[4].comment = Some("+0.50".to_string());
+        assert_eq!(game.to_pgn(), "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n\
+            [White \"?\"]\n[Black \"?\"]\n[Result \"1-0\"]\n\n\
+            1. e4 e5 2. Bc4 Nc6 3. Qh5 {+0.50} 3... Nf6 4. Qxf7# 1-0\n\n");
+        assert_eq!(game.replay().unwrap().move_history, moves);
+
+        let text = r#"
+[Event "Test"]
+[White "A"]
+[Black "B"]
+[Result "1/2-1/2"]
+[ECO "C42"]
+
+{Petrov} 1. e4 e5 2. Nf3 Nf6!? (2... Nc6 3. Bb5 (3. Bc4 Bc5) 3... a6) 3. Nxe5 $1 d6 {main
+line} 4. Nf3 Nxe4 1/2-1/2
+"#;
+        let games = pgn::read_games(text);
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.comment.as_deref(), Some("Petrov"));
+        assert_eq!(game.moves.len(), 8);
+        assert_eq!(game.moves[3].nags, [5]);
+        assert_eq!(game.moves[3].variations[0][1].variations[0][0].san, "Bc4");
+        assert_eq!(game.moves[4].nags, [1]);
+        assert_eq!(game.moves[5].comment.as_deref(), Some("main line"));
+
+        let written = game.to_pgn();
+        assert!(written.starts_with("[Event \"Test\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n"));
+        assert!(written.contains("[Result \"1/2-1/2\"]\n[ECO \"C42\"]\n\n{Petrov} 1. e4"));
+        assert!(written.contains("2. Nf3 Nf6 $5 (2... Nc6 3. Bb5 (3. Bc4 Bc5) 3... a6) 3. Nxe5\n$1 d6 {main line}"));
+        assert!(written.lines().all(|line| line.len() < 80));
+        let read_back = &pgn::read_games(&written)[0];
+        assert_eq!((&read_back.comment, &read_back.moves, &read_back.result), (&game.comment, &game.moves, &game.result));
+
+        let pos = game.replay().unwrap();
+        assert!(fen_from_pos(&pos).starts_with("rnbqkb1r/ppp2ppp/3p4/8/4n3/5N2/PPPP1PPP/RNBQKB1R w KQkq"));
+    }
+
+    #[test]
+    fn san_moves_are_formatted_and_parsed() {
+        let mut queens = Position::from_fen("8/7k/8/8/8/Q1Q5/8/Q6K w - - 0 1".to_string());
+        game::update_attackers(&mut queens, !BitBoard::empty());
+        assert_eq!(san::move_to_san(&mut queens, 0, 9), "Q1b2");
+        assert_eq!(san::move_to_san(&mut queens, 16, 9), "Qa3b2");
+        assert_eq!(san::move_to_san(&mut queens, 18, 9), "Qcb2");
+        assert_eq!(san::parse_san(&mut queens, "Qcb2"), Some((18, 9)));
+        assert_eq!(san::parse_san(&mut queens, "Qa3xb2"), Some((16, 9)));
+        // Ambiguous moves are rejected
+        assert_eq!(san::parse_san(&mut queens, "Qab2"), None);
+
+        let mut pos = Position::new();
+        for (from, to) in [(12, 28), (48, 40), (28, 36), (51, 35)] {
+            game::apply_move(&mut pos, from, to);
+        }
+        assert_eq!(san::move_to_san(&mut pos, 36, 43), "exd6");
+        assert_eq!(san::parse_move(&mut pos, "exd6"), Ok((36, 43)));
+
+        let mut pos = Position::new();
+        for (from, to) in [(12, 28), (52, 36), (6, 21), (57, 42), (5, 26), (61, 34)] {
+            game::apply_move(&mut pos, from, to);
+        }
+        assert_eq!(san::move_to_san(&mut pos, 4, 6), "O-O");
+        for castling in ["O-O", "0-0", "e1g1", "Ke1-g1"] {
+            assert_eq!(san::parse_move(&mut pos, castling), Ok((4, 6)));
+        }
+        assert_eq!(san::move_to_uci(&pos, 4, 6), "e1g1");
+
+        let mut promotion = Position::from_fen("7k/4P3/8/8/8/8/8/4K3 w - - 0 1".to_string());
+        game::update_attackers(&mut promotion, !BitBoard::empty());
+        assert_eq!(san::move_to_san(&mut promotion, 52, 60), "e8=Q+");
+        assert_eq!(san::move_to_uci(&promotion, 52, 60), "e7e8q");
+        for text in ["e8=Q", "e7e8q", "e7e8", "e7-e8=Q"] {
+            assert_eq!(san::parse_move(&mut promotion, text), Ok((52, 60)));
+        }
+        // The engine only promotes to queens
+        assert!(san::parse_move(&mut promotion, "e8=N").is_err());
+        assert!(san::parse_move(&mut promotion, "e7e8n").is_err());
+
+        let mut pos = Position::new();
+        assert_eq!(parse_user_input("", &mut pos), Ok(UserInput::EngineMove));
+        assert_eq!(parse_user_input("legal", &mut pos), Ok(UserInput::LegalMoves));
+        assert_eq!(parse_user_input("fen", &mut pos), Ok(UserInput::Fen));
+        assert_eq!(parse_user_input("e2 e4", &mut pos), Ok(UserInput::Move(12, 28)));
+        assert_eq!(parse_user_input("Nf3", &mut pos), Ok(UserInput::Move(6, 21)));
+        assert!(parse_user_input("e2e5", &mut pos).is_err_and(|e| e.contains("help")));
+        assert_eq!(parse_user_input("go", &mut pos), Ok(UserInput::Go(None)));
+        assert_eq!(parse_user_input("go depth 6", &mut pos), Ok(UserInput::Go(Some(6))));
+        assert!(parse_user_input("go depth 0", &mut pos).is_err());
+        assert!(parse_user_input("go fast", &mut pos).is_err());
+        assert_eq!(parse_user_input("setfen 4k3/8/8/8/8/8/8/4K3 w - - 0 1", &mut pos),
+            Ok(UserInput::SetFen("4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string())));
+        assert_eq!(parse_user_input("save game.pgn", &mut pos), Ok(UserInput::Save("game.pgn".to_string())));
+        assert!(parse_user_input("load", &mut pos).is_err_and(|e| e.contains("load <file.pgn>")));
+        assert_eq!(parse_user_input(" undo ", &mut pos), Ok(UserInput::Undo));
+
+        // Games are replayed with their move numbers
+        let replayed = game::replay(&Position::new(), &[(12, 28), (52, 36), (6, 21)]);
+        assert_eq!(fen_from_pos(&replayed), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        assert_eq!(replayed.move_history.len(), 3);
+    }
+
+    #[test]
+    fn fen_parsing_sets_up_positions() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "4k3/8/8/8/8/8/4r3/4K3 w - - 12 40",
+        ] {
+            let mut pos: Position = fen.parse().unwrap();
+            let mut expected = pos.clone();
+            game::update_attackers(&mut expected, !BitBoard::empty());
+            assert_eq!(pos.attack_bitboards, expected.attack_bitboards);
+            assert_eq!(fen_from_pos(&pos), fen);
+            assert_eq!(pos.check, fen.starts_with("4k3"));
+            assert!(!movegen::get_all_legal_moves_for_color(pos.state.active_player, &mut pos).is_empty());
+        }
+
+        let mut en_passant = Position::try_from("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+        assert_eq!(en_passant.en_passant_square, Some(45));
+        assert!(movegen::get_all_legal_moves_for_color(Color::White, &mut en_passant).contains(&(36, 45)));
+
+        // Shredder-FEN and X-FEN name the castling rooks by their files
+        let standard: Position = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1".parse().unwrap();
+        for fen in ["r3k2r/8/8/8/8/8/8/R3K2R b HAha - 0 1", "r3k2r/8/8/8/8/8/8/R3K2R b KAhq - 0 1"] {
+            assert_eq!(fen.parse::<Position>().unwrap().state.castling_rights, standard.state.castling_rights);
+        }
+        // The move counters may be left out
+        assert_eq!("4k3/8/8/8/8/8/8/4K3 w - -".parse::<Position>().unwrap().state.full_move_counter, 1);
+    }
+
+    #[test]
+    fn fen_errors_are_reported() {
+        let errors = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq", FenError::FieldCount(3)),
+            ("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::InvalidPiece { index: 13, character: 'x' }),
+            ("rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::InvalidRank { index: 16 }),
+            ("rnbqkbnr/pppppppp/8p/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::InvalidRank { index: 19 }),
+            ("8/8/8/8/8/8/8 w - - 0 1", FenError::RankCount { index: 13, ranks: 7 }),
+            ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::InvalidSideToMove { index: 20 }),
+            ("4k3/8/8/8/8/8/8/4K3 w KX - 0 1", FenError::InvalidCastling { index: 23, character: 'X' }),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KH - 0 1", FenError::InvalidCastling { index: 27, character: 'H' }),
+            ("4k3/8/8/8/8/8/8/4K3 w - e4 0 1", FenError::InvalidEnPassant { index: 24 }),
+            ("4k3/8/8/8/8/8/8/4K3 w - - x 1", FenError::InvalidHalfMoveClock { index: 26 }),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 0", FenError::InvalidFullMoveNumber { index: 28 }),
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::KingCount { color: Color::Black, count: 0 }),
+            ("4k2P/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PawnOnBackRank { square: 63 }),
+            ("4k3/8/8/8/8/8/4R3/4K3 w - - 0 1", FenError::OpponentInCheck),
+            ("4k3/8/8/8/8/8/8/4K3 w K - 0 1", FenError::InconsistentCastling { right: 'K' }),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3 0 1", FenError::InconsistentEnPassant { square: 20 }),
+        ];
+        for (fen, error) in errors {
+            assert_eq!(fen.parse::<Position>(), Err(error), "{}", fen);
+        }
+        assert_eq!(FenError::InvalidPiece { index: 13, character: 'x' }.to_string(), "invalid piece 'x' at index 13");
+    }
+
+    #[test]
+    fn chess960_castling_is_played() {
+        assert_eq!(fen_from_pos(&chess960::start_position(518)), fen_from_pos(&Position::new()));
+        assert_eq!(chess960::back_rank(0), ['B', 'B', 'Q', 'N', 'N', 'R', 'K', 'R']);
+        assert_eq!(chess960::back_rank(959), ['R', 'K', 'R', 'N', 'N', 'Q', 'B', 'B']);
+        let mut start = chess960::start_position(0);
+        assert_eq!(fen_from_pos(&start), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+        assert_eq!(movegen::get_all_legal_moves_for_color(Color::White, &mut start).len(), 20);
+
+        // Castling moves are stored as the king capturing its rook, the king and rook end up on the usual squares
+        let mut pos: Position = "4k3/8/8/8/8/8/8/RK5R w KQ - 0 1".parse().unwrap();
+        assert_eq!(shredder_fen_from_pos(&pos), "4k3/8/8/8/8/8/8/RK5R w HA - 0 1");
+        let legal_moves = movegen::get_all_legal_moves_for_color(Color::White, &mut pos);
+        assert!(legal_moves.contains(&(1, 0)) && legal_moves.contains(&(1, 7)) && legal_moves.contains(&(1, 2)));
+        assert_eq!(san::move_to_san(&mut pos, 1, 0), "O-O-O");
+        assert_eq!(san::move_to_san(&mut pos, 1, 7), "O-O");
+        assert_eq!(san::parse_move(&mut pos, "O-O-O"), Ok((1, 0)));
+        assert_eq!(san::parse_move(&mut pos, "b1h1"), Ok((1, 7)));
+        assert_eq!(book::decode_move(&pos, 1 << 6), Some((1, 0)));
+        let mut queen_side = pos.clone();
+        game::apply_move(&mut queen_side, 1, 0);
+        assert_eq!(fen_from_pos(&queen_side), "4k3/8/8/8/8/8/8/2KR3R b - - 1 1");
+        game::apply_move(&mut pos, 1, 7);
+        assert_eq!(fen_from_pos(&pos), "4k3/8/8/8/8/8/8/R4RK1 b - - 1 1");
+
+        // The king and the rook may swap places or the king may stay where it is
+        for (fen, castling, expected) in [
+            ("4k3/8/8/8/8/8/8/5KR1 w K - 0 1", (5, 6), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1"),
+            ("4k3/8/8/8/8/8/8/6KR w K - 0 1", (6, 7), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1"),
+        ] {
+            let mut pos: Position = fen.parse().unwrap();
+            assert!(movegen::get_all_legal_moves_for_color(Color::White, &mut pos).contains(&castling));
+            game::apply_move(&mut pos, castling.0, castling.1);
+            assert_eq!(fen_from_pos(&pos), expected);
+        }
+
+        // No castling through attacked squares, into check revealed by the rook or past other pieces
+        for (fen, castling) in [
+            ("4kr2/8/8/8/8/8/8/RK5R w KQ - 0 1", (1, 7)),
+            ("4k3/8/8/8/8/8/8/rR2K3 w Q - 0 1", (4, 1)),
+            ("1k6/8/8/8/8/8/8/1K1R2R1 w D - 0 1", (1, 3)),
+        ] {
+            let mut pos: Position = fen.parse().unwrap();
+            assert_eq!(fen_from_pos(&pos), fen);
+            assert!(!movegen::get_all_legal_moves_for_color(Color::White, &mut pos).contains(&castling), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn variants_change_the_rules() {
+        assert_eq!("3check".parse(), Ok(Variant::ThreeCheck));
+        assert_eq!("King of the Hill".parse(), Ok(Variant::KingOfTheHill));
+        assert!("horde".parse::<Variant>().is_err());
+
+        // King of the Hill is won by reaching the center, the search walks the king there
+        let mut hill = Position::from_variant_fen("4k3/8/8/8/8/4K3/8/8 w - - 0 1", Variant::KingOfTheHill).unwrap();
+        let best_move = Engine::default().search(&hill, SearchLimits { depth: 2, move_time: None }).best_move.unwrap();
+        assert!([27, 28].contains(&best_move.1));
+        game::apply_move(&mut hill, 20, 28);
+        assert_eq!(variants::winner(&mut hill), Some(Color::White));
+
+        // Three-check counts the checks in the state and writes the remaining checks into the FEN
+        let fen = "4k3/8/8/8/8/8/8/4K2R w - - 1+3 0 1";
+        let mut three_check = Position::from_variant_fen(fen, Variant::ThreeCheck).unwrap();
+        assert_eq!(three_check.state.checks, [2, 0]);
+        assert_eq!(fen_from_pos(&three_check), fen);
+        let given = Position::from_variant_fen("4k3/8/8/8/8/8/8/4K2R w - - 0 1 +2+0", Variant::ThreeCheck).unwrap();
+        assert_eq!(fen_from_pos(&given), fen);
+        assert_eq!(variants::winner(&mut three_check), None);
+        game::apply_move(&mut three_check, 7, 63);
+        assert_eq!(three_check.state.checks, [3, 0]);
+        assert_eq!(variants::winner(&mut three_check), Some(Color::White));
+
+        // Antichess has compulsory captures and is won by losing all pieces, kings are optional
+        let mut antichess = variants::start_position(Variant::Antichess);
+        assert_eq!(movegen::get_all_legal_moves_for_color(Color::White, &mut antichess).len(), 20);
+        let mut antichess = Position::from_variant_fen("8/8/8/3p4/4P3/8/8/R7 w - - 0 1", Variant::Antichess).unwrap();
+        assert_eq!(movegen::get_all_legal_moves_for_color(Color::White, &mut antichess), vec![(28, 35)]);
+        assert!(evaluation::main_evaluation(&mut antichess) < 0);
+        game::apply_move(&mut antichess, 28, 35);
+        assert_eq!(variants::winner(&mut antichess), Some(Color::Black));
+        assert!("8/8/8/3p4/4P3/8/8/R7 w - - 0 1".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn crazyhouse_pieces_are_dropped() {
+        let start = variants::start_position(Variant::Crazyhouse);
+        assert_eq!(fen_from_pos(&start), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1");
+
+        // Captured pieces go to the hand of the capturing side and can be dropped anywhere but pawns on the back ranks
+        let mut pos = Position::from_variant_fen("4k3/8/8/3p4/4P3/8/8/4K3[] w - - 0 1", Variant::Crazyhouse).unwrap();
+        game::apply_move(&mut pos, 28, 35);
+        assert_eq!(fen_from_pos(&pos), "4k3/8/8/3P4/8/8/8/4K3[P] b - - 0 1");
+        game::apply_move(&mut pos, 60, 52);
+        let legal_moves = movegen::get_all_legal_moves_for_color(Color::White, &mut pos);
+        assert!(legal_moves.contains(&(DropMove::origin(Piece::PAWN), 28)));
+        assert!(!legal_moves.contains(&(DropMove::origin(Piece::PAWN), 0)));
+        assert_eq!(san::move_to_san(&mut pos, DropMove::origin(Piece::PAWN), 28), "P@e4");
+        assert_eq!(san::parse_move(&mut pos, "@e4"), Ok((DropMove::origin(Piece::PAWN), 28)));
+        assert_eq!(san::move_to_uci(&pos, DropMove::origin(Piece::PAWN), 28), "P@e4");
+        game::apply_move(&mut pos, DropMove::origin(Piece::PAWN), 28);
+        assert_eq!(fen_from_pos(&pos), "8/4k3/8/3P4/4P3/8/8/4K3[] b - - 2 1");
+
+        // Promoted pieces are marked with ~ and go back into the hand as pawns
+        let mut promoted = Position::from_variant_fen("4k3/7q~/8/8/8/8/8/4K2R/ w - - 0 1", Variant::Crazyhouse).unwrap();
+        assert_eq!(fen_from_pos(&promoted), "4k3/7q~/8/8/8/8/8/4K2R[] w - - 0 1");
+        game::apply_move(&mut promoted, 7, 55);
+        assert_eq!(fen_from_pos(&promoted), "4k3/7R/8/8/8/8/8/4K3[P] b - - 0 1");
+        assert!(Position::from_variant_fen("4k3/8/8/8/8/8/8/4K3[K] w - - 0 1", Variant::Crazyhouse).is_err());
+
+        // Only drops that block the check are legal, pieces in hand count as material
+        let mut check = Position::from_variant_fen("4k3/8/8/8/8/8/8/r3K3[N] w - - 0 1", Variant::Crazyhouse).unwrap();
+        let legal_moves = movegen::get_all_legal_moves_for_color(Color::White, &mut check);
+        assert_eq!(legal_moves.iter().filter(|&&(from, _)| DropMove::piece(from).is_some()).count(), 3);
+        let mut without_hand = Position::from_variant_fen("4k3/8/8/8/8/8/8/4K3[] w - - 0 1", Variant::Crazyhouse).unwrap();
+        let mut with_hand = Position::from_variant_fen("4k3/8/8/8/8/8/8/4K3[N] w - - 0 1", Variant::Crazyhouse).unwrap();
+        assert!(evaluation::main_evaluation(&mut with_hand) > evaluation::main_evaluation(&mut without_hand));
+
+        // The search finds a mate by dropping a rook on the back rank
+        let mut mate = Position::from_variant_fen("6k1/5ppp/8/8/8/8/8/4K3[R] w - - 0 1", Variant::Crazyhouse).unwrap();
+        let best_move = Engine::default().search(&mate, SearchLimits { depth: 1, move_time: None }).best_move.unwrap();
+        assert_eq!(DropMove::piece(best_move.0), Some(Piece::ROOK));
+        assert!(san::move_to_san(&mut mate, best_move.0, best_move.1).ends_with('#'));
+    }
+
+    #[test]
+    fn epd_records_are_parsed_and_solved() {
+        let suite = "# Back rank mate\n\
+            6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8#; am Kf1; id \"mate.001\"; c0 \"back rank; rook\"; hmvc 3;\n\
+            \n\
+            2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";\n\
+            6k1/8/8/8/8/8/8/R5K1 w - - bm Rb8#;\n\
+            6k1/8/8/8/8/8/8/R5K1 w - - id \"unterminated\"\n";
+        let records = epd::read_records(suite);
+        assert_eq!(records.len(), 4);
+        assert!(records[2].as_ref().is_err_and(|e| e.contains("Rb8#")));
+        assert!(records[3].is_err());
+
+        let wac = records[1].as_ref().unwrap();
+        assert_eq!(wac.id(), Some("WAC.001"));
+        assert_eq!(wac.best_moves, vec![(22, 46)]);
+        assert!(wac.is_test());
+
+        let record = records[0].clone().unwrap();
+        assert_eq!(record.id(), Some("mate.001"));
+        assert_eq!(record.comment(), Some("back rank; rook"));
+        assert_eq!(record.best_moves, vec![(0, 56)]);
+        assert_eq!(record.avoid_moves, vec![(6, 5)]);
+        assert_eq!(record.position.state.half_move_counter, 3);
+        assert!(record.is_solved_by((0, 56)));
+        assert!(!record.is_solved_by((6, 5)));
+        assert!(!record.is_solved_by((0, 8)));
+
+        let mut engine = Engine::default();
+        let outcome = engine.search(&record.position, SearchLimits { depth: 2, move_time: None });
+        assert_eq!(outcome.best_move, Some((0, 56)));
+        assert_eq!(outcome.depth, 2);
+        assert!(outcome.nodes > 0);
+
+        let outcome = engine.search(&record.position, SearchLimits { depth: 64, move_time: Some(Duration::from_millis(200)) });
+        assert!(outcome.best_move.is_some_and(|found| record.is_solved_by(found)));
+        assert_eq!(outcome.depth, 1);
+
+        // Without a mate in one, the search stops when the time is up
+        let outcome = engine.search(&wac.position, SearchLimits { depth: 64, move_time: Some(Duration::from_millis(300)) });
+        assert!(outcome.best_move.is_some());
+        assert!(outcome.depth >= 1 && outcome.depth < 64);
+        assert!(outcome.time < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn engines_search_independently() {
+        // Two engines with their own tables search different positions at the same time
+        let mate: Position = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1".parse().unwrap();
+        let queen_up: Position = "4k3/8/8/8/8/8/3Q4/4K3 b - - 0 1".parse().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut first = Engine::new(EngineOptions { threads: 2, ..EngineOptions::default() });
+        first.set_info_callback(move |info| sender.send(info.clone()).unwrap());
+        let mut second = Engine::new(EngineOptions { threads: 1, ..EngineOptions::default() });
+        assert_eq!(first.threads(), 2);
+
+        let limits = SearchLimits { depth: 3, move_time: Some(Duration::from_secs(60)) };
+        let (mate_outcome, other_outcome) = std::thread::scope(|scope| {
+            let mate_search = scope.spawn(|| first.search(&mate, limits));
+            let other_search = scope.spawn(|| second.search(&queen_up, limits));
+            (mate_search.join().unwrap(), other_search.join().unwrap())
+        });
+        assert_eq!(mate_outcome.best_move, Some((0, 56)));
+        assert_eq!(mate_outcome.score, i32::MAX);
+        assert!(other_outcome.best_move.is_some() && other_outcome.score < 0);
+
+        // The info callback gets every searched root move and the finished iteration
+        drop(first);
+        let infos: Vec<SearchInfo> = receiver.iter().collect();
+        assert!(matches!(infos.last(), Some(SearchInfo::Iteration(outcome)) if outcome.best_move == mate_outcome.best_move));
+        let root_moves = infos.iter().filter(|info| matches!(info, SearchInfo::RootMove { depth: 1, .. })).count();
+        assert!(root_moves > 0);
+    }
+
+    #[test]
+    fn bench_positions_are_distinct_and_playable() {
+        let mut fens: Vec<&str> = bench::POSITIONS.to_vec();
+        fens.sort();
+        fens.dedup();
+        assert_eq!(fens.len(), bench::POSITIONS.len());
+        for fen in bench::POSITIONS {
+            let mut pos: Position = fen.parse().unwrap_or_else(|e| panic!("Invalid bench position {}: {:?}", fen, e));
+            assert!(!movegen::get_all_legal_moves_for_color(pos.state.active_player, &mut pos).is_empty(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn search_statistics_add_up() {
+        for threads in [1, 2] {
+            let mut engine = Engine::new(EngineOptions { threads, ..EngineOptions::default() });
+            let outcome = engine.search(&Position::new(), SearchLimits { depth: 2, move_time: None });
+            let stats = outcome.stats;
+            // Every thread counts its own nodes, together they are all nodes of the search
+            assert_eq!(stats.nodes, outcome.nodes);
+            // Without a move time only the requested depth is searched
+            assert_eq!(stats.iterations.iter().map(|i| (i.depth, i.nodes)).collect::<Vec<_>>(), vec![(2, outcome.nodes)]);
+            assert!(stats.qnodes > 0 && stats.qnodes < stats.nodes);
+            assert!(stats.tt_cutoffs <= stats.tt_hits && stats.tt_hits <= stats.tt_probes && stats.tt_probes > 0);
+            assert!(stats.first_move_cutoffs <= stats.beta_cutoffs && stats.beta_cutoffs > 0);
+            assert!(stats.branching_factor() > 1.0);
+        }
+        // With a move time every iteration from depth 1 on is recorded, together they are all nodes of the search
+        let mut engine = Engine::new(EngineOptions::default());
+        let outcome = engine.search(&Position::new(), SearchLimits { depth: 3, move_time: Some(Duration::from_secs(60)) });
+        let iterations = &outcome.stats.iterations;
+        assert_eq!(iterations.iter().map(|i| i.depth).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(iterations.iter().map(|i| i.nodes).sum::<u64>(), outcome.nodes);
+        assert!(iterations.windows(2).all(|pair| pair[0].nodes < pair[1].nodes));
+        assert_eq!(outcome.stats.last_iteration(), iterations[2]);
+
+        let iteration = |depth, nodes| IterationStats { depth, nodes, time: Duration::from_millis(nodes) };
+        let mut total = SearchStats { nodes: 10, tt_probes: 4, tt_hits: 1, beta_cutoffs: 5, first_move_cutoffs: 4, iterations: vec![iteration(1, 2), iteration(3, 8)], ..SearchStats::default() };
+        total += SearchStats { nodes: 5, tt_probes: 4, tt_hits: 3, iterations: vec![iteration(1, 1), iteration(2, 4)], ..SearchStats::default() };
+        // Iterations of the same depth add up, the others are kept in order of depth
+        assert_eq!(total.nodes, 15);
+        assert_eq!(total.iterations, vec![iteration(1, 3), iteration(2, 4), iteration(3, 8)]);
+        // The statistics don't pretend to count pruning the search doesn't do
+        assert!(total.to_string().contains("no null-move pruning or late move reductions"));
+        assert_eq!(total.tt_hit_rate(), 50.0);
+        assert_eq!(total.first_move_cutoff_rate(), 80.0);
+    }
+
+    #[test]
+    fn deterministic_searches_repeat_exactly() {
+        let options = EngineOptions { threads: 4, deterministic: true, ..EngineOptions::default() };
+        let mut first = Engine::new(options);
+        let mut second = Engine::new(options);
+        assert_eq!(first.threads(), 1);
+        for fen in bench::POSITIONS.iter().skip(20).step_by(10) {
+            let pos: Position = fen.parse().unwrap();
+            let limits = SearchLimits { depth: 1, move_time: None };
+            let outcome = first.search(&pos, limits);
+            // Searches of the same engine, of another engine and with a callback find the same move with the same nodes
+            for engine in [&mut first, &mut second] {
+                engine.set_info_callback(|_| {});
+                let repeated = engine.search(&pos, limits);
+                assert_eq!((repeated.best_move, repeated.score, repeated.nodes), (outcome.best_move, outcome.score, outcome.nodes), "{}", fen);
+                let without_time = |stats: &SearchStats| SearchStats {
+                    iterations: stats.iterations.iter().map(|i| IterationStats { time: Duration::ZERO, ..*i }).collect(),
+                    ..stats.clone()
+                };
+                assert_eq!(without_time(&repeated.stats), without_time(&outcome.stats));
+            }
+        }
+    }
+
+    #[test]
+    fn perft_counts_and_uci_commands() {
+        let mut start = Position::new();
+        let counts: Vec<u64> = (1..=3).map(|depth| perft::perft(&mut start, depth)).collect();
+        assert_eq!(counts, [20, 400, 8902]);
+        let mut kiwipete: Position = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1".parse().unwrap();
+        let divide = perft::divide(&mut kiwipete, 2);
+        assert_eq!(divide.len(), 48);
+        assert_eq!(divide.iter().map(|(_, count)| count).sum::<u64>(), 2039);
+        // En passant may uncover an attack along the rank and kings on opposite edges of the board don't touch
+        let mut rook_endgame: Position = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1".parse().unwrap();
+        assert_eq!(perft::perft(&mut rook_endgame, 3), 2812);
+
+        // Moves of position commands are played from the start position or the FEN, castling may be king takes rook
+        let pos = uci::parse_position("startpos moves e2e4 e7e5 g1f3", Variant::Standard, false).unwrap();
+        assert_eq!(fen_from_pos(&pos), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        let pos = uci::parse_position("fen r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1 moves e1h1", Variant::Standard, false).unwrap();
+        assert_eq!(fen_from_pos(&pos), "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1");
+        assert!(uci::parse_position("startpos moves e2e5", Variant::Standard, false).is_err());
+        assert!(uci::parse_position("fen 4k3/8 w - - 0 1", Variant::Standard, false).is_err());
+
+        assert_eq!(uci::parse_go("depth 5", Color::White), SearchLimits { depth: 5, move_time: None });
+        assert_eq!(uci::parse_go("movetime 1500", Color::White),
+            SearchLimits { depth: uci::MAX_DEPTH, move_time: Some(Duration::from_millis(1500)) });
+        // Black's minute minus the move overhead is split over 30 moves, three quarters of the increment are added
+        assert_eq!(uci::parse_go("wtime 1000 btime 60050 winc 0 binc 1000", Color::Black).move_time, Some(Duration::from_millis(2750)));
+        assert_eq!(uci::parse_go("wtime -20 btime 5000", Color::White).move_time, Some(Duration::ZERO));
+        assert!(uci::parse_go("infinite", Color::White).move_time.is_some_and(|move_time| move_time > Duration::from_secs(3600)));
+        assert_eq!(uci::score_to_uci(i32::MAX), "mate 1");
+        assert_eq!(uci::score_to_uci(-150), "cp -150");
+
+        // A stopped search still returns a move and the stop flag is cleared for the next search
+        let mut engine = Engine::default();
+        engine.stop_handle().store(true, std::sync::atomic::Ordering::Relaxed);
+        let outcome = engine.search(&Position::new(), SearchLimits { depth: 64, move_time: Some(Duration::from_secs(3600)) });
+        assert!(outcome.best_move.is_some());
+        assert_eq!(outcome.depth, 0);
+        assert_eq!(engine.search(&Position::new(), SearchLimits { depth: 1, move_time: None }).depth, 1);
+    }
+
+    #[test]
+    fn perft_matches_published_counts() {
+        // The published counts of the standard perft positions, to depths without promotions as pawns only promote to queens
+        let positions = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 4, 197281),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3, 97862),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5, 674624),
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 1, 6),
+            ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 3, 89890),
+        ];
+        for (fen, depth, nodes) in positions {
+            let mut pos: Position = fen.parse().unwrap();
+            assert_eq!(perft::perft(&mut pos, depth), nodes, "{}", fen);
+        }
+
+        // Capturing the pawn that gives check en passant ends the check, here it is the only move
+        let mut pos: Position = "r7/8/3p4/1Ppp4/1K6/7r/8/3k4 w - c6 0 1".parse().unwrap();
+        assert_eq!(movegen::get_all_legal_moves_for_color(Color::White, &mut pos), [(33, 42)]);
+        assert!(!game::is_in_checkmate(&mut pos));
+    }
+
+    #[test]
+    fn uci_stop_after_a_finished_search_is_ignored() {
+        let mut session = uci::Uci::new(EngineOptions { deterministic: true, ..EngineOptions::default() });
+        session.execute("position startpos");
+        session.execute("go depth 2");
+        while session.searching() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // The search has printed its move but hasn't been joined yet when the stop arrives
+        session.execute("stop");
+        assert_eq!(session.last_outcome().map(|outcome| outcome.depth), Some(2));
+
+        session.execute("go depth 2");
+        let outcome = session.last_outcome().unwrap();
+        assert_eq!(outcome.depth, 2);
+        assert!(outcome.nodes > 0);
+    }
+
+    #[test]
+    fn players_are_configured_per_side() {
+        let defaults = EngineSettings { options: EngineOptions::default(), limits: SearchLimits { depth: 4, move_time: None }, thresholds: Thresholds::default() };
+        assert_eq!(PlayerConfig::parse("human", defaults), Ok(PlayerConfig::Human));
+        assert_eq!(PlayerConfig::parse("engine", defaults), Ok(PlayerConfig::Engine(defaults)));
+        let Ok(PlayerConfig::Engine(settings)) = PlayerConfig::parse("engine:depth=6,hash=16,profile=aggressive,psqt=80", defaults) else {
+            panic!("Engine settings weren't parsed");
+        };
+        assert_eq!(settings.limits, SearchLimits { depth: 6, move_time: None });
+        assert_eq!(settings.options.hash, 16);
+        assert_eq!(settings.options.profile, evaluation::EvalProfile { psqt: 80, .."aggressive".parse().unwrap() });
+        let Ok(PlayerConfig::Uci(settings)) = PlayerConfig::parse("uci:/usr/bin/stockfish,movetime=500,Skill Level=3", defaults) else {
+            panic!("UCI engine settings weren't parsed");
+        };
+        assert_eq!(settings.command, "/usr/bin/stockfish");
+        assert_eq!(settings.limits, SearchLimits { depth: uci::MAX_DEPTH, move_time: Some(Duration::from_millis(500)) });
+        assert_eq!(settings.options, [("Skill Level".to_string(), "3".to_string())]);
+        for invalid in ["robot", "human:depth=3", "engine:depth=0", "engine:speed=9", "engine:profile=reckless", "uci:"] {
+            assert!(PlayerConfig::parse(invalid, defaults).is_err(), "{}", invalid);
+        }
+
+        // The default profile evaluates like the main evaluation, other profiles weigh the terms differently
+        let mut pos: Position = "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP1B1PPP/R2QKB1R w - - 0 1".parse().unwrap();
+        let default_eval = evaluation::main_evaluation(&mut pos);
+        let mut cache = evaluation::ImbalanceCache::default();
+        assert_eq!(evaluation::evaluate(&mut pos, &evaluation::EvalProfile::default(), &mut cache), default_eval);
+        assert_ne!(evaluation::evaluate(&mut pos, &"positional".parse().unwrap(), &mut cache), default_eval);
+
+        let moves = [(12, 28), (52, 36)];
+        assert_eq!(uci::position_command(&Position::new(), &moves),
+            "position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 moves e2e4 e7e5");
+        assert_eq!(uci::go_command(SearchLimits { depth: 5, move_time: None }), "go depth 5");
+        assert_eq!(uci::go_command(SearchLimits { depth: uci::MAX_DEPTH, move_time: Some(Duration::from_millis(250)) }), "go movetime 250");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn engine_plays_external_uci_engine() {
+        use std::os::unix::fs::PermissionsExt;
+        // A scripted UCI engine that always answers with the mate of the fool's mate
+        let path = std::env::temp_dir().join(format!("scripted_uci_engine_{}.sh", std::process::id()));
+        std::fs::write(&path, "#!/bin/sh\nwhile read line; do case \"$line\" in\n\
+            uci) echo 'id name Scripted'; echo uciok;;\n\
+            isready) echo readyok;;\n\
+            go*) echo 'bestmove d8h4';;\n\
+            quit) exit 0;;\n\
+            esac; done\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let defaults = EngineSettings { options: EngineOptions { deterministic: true, ..EngineOptions::default() }, limits: SearchLimits { depth: 1, move_time: None }, thresholds: Thresholds::default() };
+        let black = PlayerConfig::parse(&format!("uci:{}", path.display()), defaults).unwrap();
+        let mut players = [Player::new(&PlayerConfig::Engine(defaults), &Resources::default(), &Position::new()).unwrap(), Player::new(&black, &Resources::default(), &Position::new()).unwrap()];
+        assert_eq!(players[1].name(), "Scripted");
+        let start: Position = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2".parse().unwrap();
+        let mut helper = Engine::new(defaults.options);
+        let game = game::main_game_loop(&mut helper, &mut players, 1, Some(start), None);
+        drop(players);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(game.header("Black"), Some("Scripted"));
+        assert_eq!(game.moves.len(), 1);
+        assert_eq!(game.result, "0-1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn external_engines_time_out_and_underpromotions_are_unsupported() {
+        use std::os::unix::fs::PermissionsExt;
+        use engine::player::{MoveError, UciEngine, UciSettings};
+        // Scripted UCI engines, one never answers go and the other always promotes to a knight
+        let script = |name: &str, answer: &str| {
+            let path = std::env::temp_dir().join(format!("{}_{}.sh", name, std::process::id()));
+            std::fs::write(&path, format!("#!/bin/sh\nwhile read line; do case \"$line\" in\n\
+                uci) echo 'id name {}'; echo uciok;;\n\
+                isready) echo readyok;;\n\
+                go*) {};;\n\
+                quit) exit 0;;\n\
+                esac; done\n", name, answer)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let silent_path = script("silent", ":");
+        let knight_path = script("knight", "echo 'bestmove e7e8n'");
+        let settings = |path: &std::path::Path| UciSettings {
+            command: path.display().to_string(),
+            limits: SearchLimits { depth: uci::MAX_DEPTH, move_time: Some(Duration::from_millis(100)) },
+            thresholds: Thresholds::default(),
+            options: Vec::new(),
+        };
+        let start: Position = "8/4P3/8/8/8/8/k6r/4K3 w - - 0 1".parse().unwrap();
+
+        // An engine that doesn't answer is killed after its move time and started again for the next game
+        let mut silent = UciEngine::start(&settings(&silent_path), &start).unwrap();
+        let asked = std::time::Instant::now();
+        assert!(matches!(silent.best_move(&start, &start, None), Err(MoveError::Timeout(_))));
+        assert!(asked.elapsed() < Duration::from_secs(5));
+        assert!(silent.new_game().is_ok());
+        let mut clock = clock::Clock::new("0.2".parse().unwrap());
+        clock.update(Color::White);
+        assert!(matches!(silent.best_move(&start, &start, Some(&clock)), Err(MoveError::Timeout(_))));
+
+        let mut knight = UciEngine::start(&settings(&knight_path), &start).unwrap();
+        assert!(matches!(knight.best_move(&start, &start, None), Err(MoveError::Unsupported(_))));
+
+        // In matches, a timeout loses on time and an underpromotion leaves the game unterminated
+        let defaults = EngineSettings { options: EngineOptions { deterministic: true, ..EngineOptions::default() }, limits: SearchLimits { depth: 1, move_time: None }, thresholds: Thresholds::default() };
+        let mut engine = Player::new(&PlayerConfig::Engine(defaults), &Resources::default(), &start).unwrap();
+        let opening = Opening { start: start.clone(), moves: Vec::new() };
+        let adjudication = Adjudication { thresholds: Thresholds::default(), max_plies: 0 };
+        let mut silent = Player::Uci(silent);
+        let game = match_runner::play_game([&mut silent, &mut engine], &opening, None, adjudication);
+        assert_eq!((game.result.as_str(), game.header("Termination")), ("0-1", Some("time forfeit")));
+        let mut knight = Player::Uci(knight);
+        let game = match_runner::play_game([&mut knight, &mut engine], &opening, None, adjudication);
+        assert_eq!((game.result.as_str(), game.header("Termination")), ("*", Some("unterminated")));
+        drop((silent, knight));
+        std::fs::remove_file(&silent_path).unwrap();
+        std::fs::remove_file(&knight_path).unwrap();
+    }
+
+    #[test]
+    fn clocks_follow_time_controls() {
+        use clock::{Clock, TimeControl};
+        for text in ["300", "300+2", "180d2", "40/5400", "0.5+0.1"] {
+            assert_eq!(text.parse::<TimeControl>().unwrap().to_string(), text);
+        }
+        for invalid in ["", "0", "5+x", "0/60", "-3"] {
+            assert!(invalid.parse::<TimeControl>().is_err(), "{}", invalid);
+        }
+
+        let seconds = Duration::from_secs;
+        let mut fischer = Clock::new("60+2".parse().unwrap());
+        assert!(fischer.complete_move(Color::White, seconds(10)));
+        assert_eq!(fischer.remaining(Color::White), seconds(52));
+        assert_eq!(fischer.remaining(Color::Black), seconds(60));
+        // Bronstein gives back the time used up to the delay
+        let mut bronstein = Clock::new("60d5".parse().unwrap());
+        assert!(bronstein.complete_move(Color::White, seconds(3)));
+        assert!(bronstein.complete_move(Color::Black, seconds(8)));
+        assert_eq!([bronstein.remaining(Color::White), bronstein.remaining(Color::Black)], [seconds(60), seconds(57)]);
+        // The time of the next period is added after every two moves
+        let mut periods = Clock::new("2/60".parse().unwrap());
+        assert!(periods.complete_move(Color::White, seconds(20)));
+        assert_eq!(periods.go_parameters(Color::White), "wtime 40000 btime 60000 movestogo 1");
+        assert!(periods.complete_move(Color::White, seconds(20)));
+        assert_eq!(periods.remaining(Color::White), seconds(80));
+        let mut sudden_death = Clock::new("60".parse().unwrap());
+        assert!(!sudden_death.complete_move(Color::Black, seconds(61)));
+        assert_eq!(sudden_death.remaining(Color::Black), Duration::ZERO);
+
+        // The engine gets its move time from the time manager of the UCI go command, capped by its own move time
+        assert_eq!(fischer.go_parameters(Color::Black), "wtime 52000 btime 60000 winc 2000 binc 2000");
+        let limits = fischer.limit(Color::Black, SearchLimits { depth: uci::MAX_DEPTH, move_time: None });
+        assert_eq!(limits.move_time, uci::parse_go("wtime 52000 btime 60000 winc 2000 binc 2000", Color::Black).move_time);
+        let capped = fischer.limit(Color::Black, SearchLimits { depth: 3, move_time: Some(Duration::from_millis(100)) });
+        assert_eq!(capped, SearchLimits { depth: 3, move_time: Some(Duration::from_millis(100)) });
+        assert_eq!(clock::format_time(Duration::from_millis(299_350)), "4:59.3");
+
+        let bare_king: Position = "4k3/8/8/8/8/8/8/3QK3 w - - 0 1".parse().unwrap();
+        assert!(clock::can_checkmate(&bare_king, Color::White));
+        assert!(!clock::can_checkmate(&bare_king, Color::Black));
+        let minor_pieces: Position = "4k3/8/8/8/8/8/8/2BNK3 w - - 0 1".parse().unwrap();
+        assert!(clock::can_checkmate(&minor_pieces, Color::White));
+
+        // A side that runs out of time loses on time
+        let defaults = EngineSettings { options: EngineOptions { deterministic: true, ..EngineOptions::default() }, limits: SearchLimits { depth: 1, move_time: None }, thresholds: Thresholds::default() };
+        let mut players = [Player::new(&PlayerConfig::Engine(defaults), &Resources::default(), &Position::new()).unwrap(), Player::new(&PlayerConfig::Engine(defaults), &Resources::default(), &Position::new()).unwrap()];
+        let mut helper = Engine::new(defaults.options);
+        let game = game::main_game_loop(&mut helper, &mut players, 1, None, Some("0.001".parse().unwrap()));
+        assert_eq!(game.moves.len(), 1);
+        assert_eq!(game.result, "0-1");
+        assert_eq!(game.header("Termination"), Some("time forfeit"));
+        assert_eq!(game.header("TimeControl"), Some("0.001"));
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_game() {
+        let humans = [Player::Human, Player::Human];
+        let mut clock = Some(clock::Clock::new("60+1".parse().unwrap()));
+        let mut game = game::Game::new(Position::new());
+        let mut fens = vec![chess960::fen(game.position())];
+        let remaining = |clock: &Option<clock::Clock>| clock.as_ref().map(|clock| [clock.remaining(Color::White), clock.remaining(Color::Black)]);
+        let mut clocks = vec![remaining(&clock)];
+        let mut moves = Vec::new();
+        for (seconds, text) in [(5, "e4"), (7, "e5"), (11, "Nf3")] {
+            let mut pos = game.position().clone();
+            let (from, to) = san::parse_move(&mut pos, text).unwrap();
+            game.play(from, to, clock.as_ref());
+            clock.as_mut().unwrap().complete_move(pos.state.active_player, Duration::from_secs(seconds));
+            moves.push((from, to));
+            fens.push(chess960::fen(game.position()));
+            clocks.push(remaining(&clock));
+        }
+
+        // Between humans every undo takes back one move with the time used for it
+        assert!(game.undo(&humans, &mut clock));
+        assert_eq!((chess960::fen(game.position()), remaining(&clock)), (fens[2].clone(), clocks[2]));
+        assert!(game.undo(&humans, &mut clock));
+        assert_eq!((chess960::fen(game.position()), remaining(&clock)), (fens[1].clone(), clocks[1]));
+        assert_eq!(game.redo_moves(), vec![moves[1], moves[2]]);
+        assert!(game.redo(&humans, clock.as_ref()));
+        assert_eq!(chess960::fen(game.position()), fens[2]);
+        assert_eq!(game.position().move_history, moves[..2]);
+        assert_eq!(game.redo_moves(), vec![moves[2]]);
+
+        // A new move replaces the moves taken back
+        let (from, to) = san::parse_move(&mut game.position().clone(), "d4").unwrap();
+        game.play(from, to, clock.as_ref());
+        assert!(game.redo_moves().is_empty());
+        assert!(!game.redo(&humans, clock.as_ref()));
+
+        // Against an engine, undo takes back its reply as well and redo plays both again
+        let defaults = EngineSettings { options: EngineOptions { deterministic: true, ..EngineOptions::default() }, limits: SearchLimits { depth: 1, move_time: None }, thresholds: Thresholds::default() };
+        let against_engine = [Player::Human, Player::new(&PlayerConfig::Engine(defaults), &Resources::default(), &Position::new()).unwrap()];
+        let mut game = game::Game::new(Position::new());
+        let mut no_clock = None;
+        for &(from, to) in &moves[..2] {
+            game.play(from, to, None);
+        }
+        assert!(game.undo(&against_engine, &mut no_clock));
+        assert_eq!(chess960::fen(game.position()), fens[0]);
+        assert_eq!(game.redo_moves(), moves[..2]);
+        assert!(!game.undo(&against_engine, &mut no_clock));
+        assert!(game.redo(&against_engine, None));
+        assert_eq!(chess960::fen(game.position()), fens[2]);
+    }
+
+    #[test]
+    fn game_loop_plays_moves_and_commands_from_the_input() {
+        let defaults = EngineSettings { options: EngineOptions { deterministic: true, ..EngineOptions::default() }, limits: SearchLimits { depth: 1, move_time: None }, thresholds: Thresholds::default() };
+        let mut engine = Engine::new(defaults.options);
+        let mut humans = [Player::Human, Player::Human];
+        let path = std::env::temp_dir().join(format!("game_loop_{}.pgn", std::process::id()));
+        let mut play = |commands: String| {
+            game::game_loop(&mut engine, &mut humans, 1, None, None, &mut commands.as_bytes())
+        };
+
+        // Moves in SAN, UCI and LAN, invalid input and FENs change nothing, go lets the engine play a move
+        let game = play(format!("e4\ne7e5\ng1-f3\ne2e5\nsetfen 8/8/8 w - - 0 1\nsave {}\ngo depth 1\nquit\n", path.display()));
+        let sans: Vec<&str> = game.moves.iter().map(|pgn_move| pgn_move.san.as_str()).collect();
+        assert_eq!((&sans[..3], sans.len()), (&["e4", "e5", "Nf3"][..], 4));
+        assert_eq!(game.header("FEN"), None);
+
+        // The saved game is loaded to be continued, without more input the game stops
+        let game = play(format!("load {}\n", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        let sans: Vec<&str> = game.moves.iter().map(|pgn_move| pgn_move.san.as_str()).collect();
+        assert_eq!(sans, ["e4", "e5", "Nf3"]);
+        assert_eq!(game.result, "*");
+
+        // A new position is set up with setfen, a draw offer is answered on the input
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        let game = play(format!("setfen {}\ne3\ndraw\nyes\n", fen));
+        assert_eq!(game.header("FEN"), Some(fen));
+        assert_eq!((game.moves.len(), game.result.as_str()), (1, "1/2-1/2"));
+    }
+
+    #[test]
+    fn engines_resign_and_agree_to_draws() {
+        let thresholds = Thresholds { resign_score: 500, resign_moves: 2, draw_score: 20, draw_moves: 3 };
+        assert!(!thresholds.should_resign(&[-600]));
+        assert!(thresholds.should_resign(&[0, -600, i32::MIN + 2]));
+        assert!(!thresholds.should_resign(&[-600, -400]));
+        assert!(!Thresholds { resign_moves: 0, ..thresholds }.should_resign(&[-600, -600]));
+        assert!(!thresholds.should_offer_draw(&[5, -10]));
+        assert!(thresholds.should_offer_draw(&[300, 5, -10, 0]));
+        // A declined offer is made again after as many moves
+        assert!(!thresholds.should_offer_draw(&[5, -10, 0, 15]));
+        assert!(thresholds.should_offer_draw(&[5, -10, 0, 15, 0, 1]));
+        // Engines accept when they are worse, or equal without the material to play for a win
+        assert!(thresholds.accepts_draw(-100, 128));
+        assert!(!thresholds.accepts_draw(0, 128));
+        assert!(thresholds.accepts_draw(0, 10));
+        assert!(!thresholds.accepts_draw(100, 0));
+        assert_eq!(uci::parse_info_score("info depth 5 score cp -35 nodes 1000 pv e2e4"), Some(-35));
+        assert_eq!(uci::parse_info_score("info depth 9 score mate -3 pv h2h3"), Some(i32::MIN + 2));
+        assert_eq!(uci::parse_info_score("info string no score"), None);
+        let mut pos = Position::new();
+        assert_eq!(parse_user_input("resign", &mut pos), Ok(UserInput::Resign));
+        assert_eq!(parse_user_input("draw", &mut pos), Ok(UserInput::OfferDraw));
+
+        let defaults = EngineSettings { options: EngineOptions { deterministic: true, ..EngineOptions::default() }, limits: SearchLimits { depth: 1, move_time: None }, thresholds: Thresholds::default() };
+        let engine = |spec: &str| Player::new(&PlayerConfig::parse(spec, defaults).unwrap(), &Resources::default(), &Position::new()).unwrap();
+        let mut helper = Engine::new(defaults.options);
+        // Against two queens, white resigns right away instead of moving
+        let mut players = [engine("engine:resignmoves=1"), engine("engine")];
+        let lost: Position = "k7/8/8/8/8/1qq5/8/7K w - - 0 1".parse().unwrap();
+        let game = game::main_game_loop(&mut helper, &mut players, 1, Some(lost), None);
+        assert!(game.moves.is_empty());
+        assert_eq!(game.result, "0-1");
+        // In an endgame, a draw offered after the first move is accepted
+        let mut players = [engine("engine:drawmoves=1,drawscore=100000"), engine("engine:drawscore=100000")];
+        let endgame: Position = "8/8/8/4k3/8/8/4P3/4K3 w - - 0 1".parse().unwrap();
+        let game = game::main_game_loop(&mut helper, &mut players, 1, Some(endgame), None);
+        assert_eq!(game.moves.len(), 1);
+        assert_eq!(game.result, "1/2-1/2");
+    }
+
+    #[test]
+    fn matches_score_colour_reversed_games() {
+        let score = Score { wins: 60, draws: 20, losses: 20 };
+        assert_eq!(score.games(), 100);
+        assert!((score.ratio() - 0.7).abs() < 1e-9);
+        let (elo, margin) = score.elo();
+        assert!((elo - 147.2).abs() < 0.1 && margin > 0.0 && margin < 100.0);
+        assert!((sprt::elo_to_ratio(sprt::ratio_to_elo(0.3)) - 0.3).abs() < 1e-9);
+        let test = Sprt { elo0: 0.0, elo1: 10.0, alpha: 0.05, beta: 0.05 };
+        let (lower, upper) = test.bounds();
+        assert!((lower + 2.944).abs() < 0.001 && (upper - 2.944).abs() < 0.001);
+        assert_eq!(test.result(&Score::default()), SprtResult::Continue);
+        assert_eq!(test.result(&score), SprtResult::Continue);
+        assert_eq!(test.result(&Score { wins: 300, draws: 100, losses: 100 }), SprtResult::AcceptH1);
+        assert_eq!(test.result(&Score { wins: 100, draws: 100, losses: 300 }), SprtResult::AcceptH0);
+
+        let defaults = EngineSettings { options: EngineOptions { deterministic: true, ..EngineOptions::default() }, limits: SearchLimits { depth: 1, move_time: None }, thresholds: Thresholds::default() };
+        // Only the first engine has a book, which opens with a3
+        let mut bytes = 0x463B96181691FC9Cu64.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(8u16 << 6 | 16).to_be_bytes());
+        bytes.extend_from_slice(&[0, 1, 0, 0, 0, 0]);
+        let resources = Resources { book: Some(Arc::new(book::Book::from_bytes(&bytes).unwrap())), ..Resources::default() };
+        let first = EngineSettings { options: EngineOptions { own_book: true, ..defaults.options }, ..defaults };
+        let settings = MatchSettings {
+            engines: [PlayerConfig::Engine(first), PlayerConfig::parse("engine:profile=materialistic", defaults).unwrap()],
+            resources: [resources.clone(), resources],
+            names: ["first".to_string(), "second".to_string()],
+            openings: vec![Opening::default()],
+            games: 2,
+            concurrency: 2,
+            time_control: None,
+            adjudication: Adjudication { thresholds: Thresholds::default(), max_plies: 4 },
+            sprt: None,
+        };
+        let result = match_runner::run(&settings, |_, _, _| {}).unwrap();
+        assert_eq!(result.score, Score { wins: 0, draws: 2, losses: 0 });
+        assert_eq!(result.games.len(), 2);
+        // The opening is played with both colours, games end after the maximum number of plies
+        assert_eq!(result.games[0].header("White"), Some("first"));
+        assert_eq!(result.games[1].header("White"), Some("second"));
+        assert!(result.games.iter().all(|game| game.moves.len() == 4 && game.header("Termination") == Some("adjudication")));
+        assert_eq!(result.games[0].moves[0].san, "a3");
+        assert_ne!(result.games[1].moves[0].san, "a3");
+    }
+
     #[test]
     #[should_panic(expected = "called on empty square")]
     fn moves_empty_square() {
@@ -233,4 +1902,4 @@ mod tests {
                         movegen::get_pawn_moves(square, &test_pos));
     }
 
-}
\ No newline at end of file
+}
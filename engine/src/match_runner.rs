@@ -0,0 +1,297 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+use types::{
+    position::Position,
+    state::GameResult,
+    variant::Variant,
+    Color,
+    Results,
+};
+use crate::{
+    chess960,
+    clock::{self, Clock, TimeControl},
+    epd,
+    game,
+    movegen,
+    pgn::{self, PgnGame},
+    negamax::Resources,
+    player::{MoveError, Player, PlayerConfig, Thresholds},
+    sprt::{Score, Sprt, SprtResult},
+    variants,
+};
+
+/* Matches between two engine configurations, to find out whether a change gains strength. Every opening of the suite
+/ is played twice with reversed colours, so neither engine profits from a good opening. Games run concurrently, each
+/ worker has its own pair of engines. Games that are clearly decided or drawn are adjudicated by the scores of both
+/ engines, and with an SPRT the match stops as soon as the test has a result. */
+
+// A position to start games from, the moves of a PGN opening are part of the games
+#[derive(Debug, Clone)]
+pub struct Opening {
+    pub start: Position,
+    pub moves: Vec<(u8, u8)>,
+}
+
+impl Default for Opening {
+    fn default() -> Self {
+        Opening {
+            start: Position::new(),
+            moves: Vec::new(),
+        }
+    }
+}
+
+// Reads the openings of a PGN file, with the moves of every game, or of an EPD file, with the position of every record
+pub fn read_openings(path: &str) -> Result<Vec<Opening>, String> {
+    let openings = if path.ends_with(".pgn") {
+        let games = pgn::read_file(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        games.iter()
+            .map(|pgn_game| {
+                let start = pgn_game.start_position().map_err(|e| format!("Invalid FEN tag: {}", e))?;
+                Ok(Opening { start, moves: pgn_game.replay()?.move_history })
+            })
+            .collect::<Result<Vec<Opening>, String>>()?
+    } else {
+        let records = epd::read_file(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        records.into_iter()
+            .map(|record| record.map(|record| Opening { start: record.position, moves: Vec::new() }))
+            .collect::<Result<Vec<Opening>, String>>()?
+    };
+    if openings.is_empty() {
+        return Err(format!("There are no openings in {}", path));
+    }
+    Ok(openings)
+}
+
+/* Games are adjudicated as lost when the score of an engine has been below -resign_score for resign_moves moves in a
+/ row, as drawn when the scores of both engines have been within draw_score for draw_moves moves and as drawn after
+/ max_plies plies, 0 turns each off. */
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Adjudication {
+    pub thresholds: Thresholds,
+    pub max_plies: usize,
+}
+
+pub struct MatchSettings {
+    // The engines and their names in the PGN, the score is kept for the first engine
+    pub engines: [PlayerConfig; 2],
+    // The opening books and tablebases of the engines, each engine plays from its own book
+    pub resources: [Resources; 2],
+    pub names: [String; 2],
+    pub openings: Vec<Opening>,
+    // Maximum number of games, the openings are repeated if there are fewer
+    pub games: usize,
+    // Number of games played at the same time
+    pub concurrency: usize,
+    pub time_control: Option<TimeControl>,
+    pub adjudication: Adjudication,
+    pub sprt: Option<Sprt>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    pub score: Score,
+    // The games in the order they were started
+    pub games: Vec<PgnGame>,
+    pub sprt: Option<SprtResult>,
+}
+
+/* Plays the match and calls report with the number, the game and the score of the first engine after every game.
+/ Returns an error if an engine couldn't be started. */
+pub fn run(settings: &MatchSettings, report: impl Fn(usize, &PgnGame, &Score) + Sync) -> Result<MatchResult, String> {
+    let next_game = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let result = Mutex::new((Score::default(), Vec::new(), None));
+    let error = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..settings.concurrency.clamp(1, settings.games.max(1)) {
+            scope.spawn(|| {
+                // External engines are set up for the variant of the openings, which all have the same
+                let start = &settings.openings[0].start;
+                let player = |i: usize| Player::new(&settings.engines[i], &settings.resources[i], start);
+                let players = player(0).and_then(|first| Ok([first, player(1)?]));
+                let mut players = match players {
+                    Ok(players) => players,
+                    Err(e) => {
+                        stop.store(true, Ordering::Relaxed);
+                        *error.lock().unwrap() = Some(e);
+                        return;
+                    },
+                };
+                while !stop.load(Ordering::Relaxed) {
+                    let number = next_game.fetch_add(1, Ordering::Relaxed);
+                    if number >= settings.games {
+                        break;
+                    }
+                    // Each opening is played by both engines with white, the first engine has white in even games
+                    let opening = &settings.openings[number / 2 % settings.openings.len()];
+                    let first_white = number.is_multiple_of(2);
+                    let [first, second] = &mut players;
+                    let (sides, names) = if first_white {
+                        ([first, second], [&settings.names[0], &settings.names[1]])
+                    } else {
+                        ([second, first], [&settings.names[1], &settings.names[0]])
+                    };
+                    let mut pgn_game = play_game(sides, opening, settings.time_control, settings.adjudication);
+                    pgn_game.set_header("Event", "AmselChess match");
+                    pgn_game.set_header("Round", &(number + 1).to_string());
+                    pgn_game.set_header("White", names[0]);
+                    pgn_game.set_header("Black", names[1]);
+
+                    let mut result = result.lock().unwrap();
+                    let (score, games, sprt_result) = &mut *result;
+                    // Unterminated games don't count
+                    match (pgn_game.result.as_str(), first_white) {
+                        ("1-0", true) | ("0-1", false) => score.wins += 1,
+                        ("0-1", true) | ("1-0", false) => score.losses += 1,
+                        ("1/2-1/2", _) => score.draws += 1,
+                        _ => {},
+                    }
+                    report(number, &pgn_game, score);
+                    games.push((number, pgn_game));
+                    if let Some(sprt) = settings.sprt {
+                        *sprt_result = Some(sprt.result(score));
+                        if *sprt_result != Some(SprtResult::Continue) {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
+    let (score, mut games, sprt) = result.into_inner().unwrap();
+    games.sort_by_key(|&(number, _)| number);
+    Ok(MatchResult {
+        score,
+        games: games.into_iter().map(|(_, game)| game).collect(),
+        sprt,
+    })
+}
+
+// Plays a game between the players of white and black from the opening without any output, returns its PGN
+pub fn play_game(mut players: [&mut Player; 2], opening: &Opening, time_control: Option<TimeControl>, adjudication: Adjudication) -> PgnGame {
+    let mut pos = game::replay(&opening.start, &opening.moves);
+    let mut clock = time_control.map(Clock::new);
+    // Search scores of white and black, from their own view
+    let mut scores: [Vec<i32>; 2] = [Vec::new(), Vec::new()];
+    let mut repetitions: HashMap<String, u32> = HashMap::new();
+    *repetitions.entry(repetition_key(&pos)).or_default() += 1;
+    let thresholds = adjudication.thresholds;
+
+    let failed = players.iter_mut().position(|player| player.new_game().is_err());
+    let (result, termination) = loop {
+        if let Some(side) = failed {
+            break (game::victory(if side == 0 { Color::Black } else { Color::White }), Some("abandoned"));
+        }
+        if let Some(result) = game_end(&mut pos, &repetitions) {
+            break (result, None);
+        }
+        let mover = pos.state.active_player;
+        if let Some(flagged) = clock.as_mut().and_then(|clock| clock.update(mover)) {
+            let result = if clock::can_checkmate(&pos, !flagged) { game::victory(!flagged) } else { GameResult(Results::DRAW) };
+            break (result, Some("time forfeit"));
+        }
+        if adjudication.max_plies > 0 && pos.move_history.len() - opening.moves.len() >= adjudication.max_plies {
+            break (GameResult(Results::DRAW), Some("adjudication"));
+        }
+
+        let chosen = match &mut *players[mover as usize] {
+            Player::Human => Err(MoveError::Failed("Humans can't play in matches".to_string())),
+            Player::Engine { engine, limits, .. } => {
+                let limits = clock.as_ref().map_or(*limits, |clock| clock.limit(mover, *limits));
+                match engine.book_move(&mut pos) {
+                    Some(book_move) => Ok((book_move, None)),
+                    None => {
+                        let outcome = engine.search(&pos, limits);
+                        Ok((outcome.best_move.expect("The game has ended without legal moves"), Some(outcome.score)))
+                    },
+                }
+            },
+            Player::Uci(uci_engine) => uci_engine.best_move(&opening.start, &pos, clock.as_ref()),
+        };
+        let ((from, to), score) = match chosen {
+            Ok(chosen) => chosen,
+            Err(MoveError::Timeout(_)) => {
+                let result = if clock::can_checkmate(&pos, !mover) { game::victory(!mover) } else { GameResult(Results::DRAW) };
+                break (result, Some("time forfeit"));
+            },
+            Err(MoveError::Unsupported(e)) => {
+                eprintln!("Error: {}", e);
+                break (GameResult(Results::ONGOING), Some("unterminated"));
+            },
+            Err(MoveError::Failed(e)) => {
+                eprintln!("Error: {}", e);
+                break (game::victory(!mover), Some("abandoned"));
+            },
+        };
+
+        if let Some(score) = score {
+            scores[mover as usize].push(score);
+        }
+        if thresholds.should_resign(&scores[mover as usize]) {
+            break (game::victory(!mover), Some("adjudication"));
+        }
+        let drawn = |scores: &Vec<i32>| scores.len() >= thresholds.draw_moves
+            && scores[scores.len() - thresholds.draw_moves..].iter().all(|score| score.abs() <= thresholds.draw_score);
+        if thresholds.draw_moves > 0 && scores.iter().all(drawn) {
+            break (GameResult(Results::DRAW), Some("adjudication"));
+        }
+        game::play_move(&mut pos, from, to);
+        *repetitions.entry(repetition_key(&pos)).or_default() += 1;
+    };
+
+    let mut pgn_game = PgnGame::from_moves(&opening.start, &pos.move_history);
+    pgn_game.set_header("Date", &pgn::today());
+    if pos.state.chess960 {
+        pgn_game.set_header("Variant", "Chess960");
+    } else if pos.state.variant != Variant::Standard {
+        pgn_game.set_header("Variant", pos.state.variant.pgn_name());
+    }
+    if let Some(time_control) = time_control {
+        pgn_game.set_header("TimeControl", &time_control.to_string());
+    }
+    if let Some(termination) = termination {
+        pgn_game.set_header("Termination", termination);
+    }
+    pgn_game.set_result(result);
+    pgn_game
+}
+
+// Positions are repeated if the pieces, the side to move, the castling rights and the en passant square are the same
+fn repetition_key(pos: &Position) -> String {
+    chess960::fen(pos).split_whitespace().take(4).collect::<Vec<&str>>().join(" ")
+}
+
+// The result of a game that has ended by the rules: mate, stalemate, the fifty-move rule, repetition or lack of material
+fn game_end(pos: &mut Position, repetitions: &HashMap<String, u32>) -> Option<GameResult> {
+    if !pos.state.game_result.is_ongoing() {
+        return Some(pos.state.game_result);
+    }
+    if let Some(winner) = variants::winner(pos) {
+        return Some(game::victory(winner));
+    }
+    if game::is_in_checkmate(pos) {
+        return Some(game::victory(!pos.state.active_player));
+    }
+    if movegen::get_all_legal_moves_for_color(pos.state.active_player, pos).is_empty() {
+        return Some(GameResult(Results::STALEMATE));
+    }
+    if repetitions.get(&repetition_key(pos)).is_some_and(|&count| count >= 3) {
+        return Some(GameResult(Results::DRAW));
+    }
+    if !clock::can_checkmate(pos, Color::White) && !clock::can_checkmate(pos, Color::Black) {
+        return Some(GameResult(Results::DRAW));
+    }
+    None
+}
@@ -1,13 +1,18 @@
 use crate::{
     magics::*,
-    game
+    game,
+    variants,
 };
 use precompute::magics::MagicTableEntry;
+use std::cmp;
 use types::{
     bitboard::BitBoard,
     position::Position,
+    variant::Variant,
     Castling,
     Color,
+    DropMove,
+    Piece,
     types_utils::*,
 };
 
@@ -53,25 +58,25 @@ fn get_all_actual_blockers(
 #[inline]
 pub fn pseudolegal_slider_moves(piece: u8, square: u8, pos: &Position) -> BitBoard {
     let blockers = get_all_actual_blockers(piece, square, pos);
-    return match piece {
+    match piece {
         0 => BitBoard::from_u64(ROOK_MOVES[magic_index(&ROOK_MAGICS[square as usize], blockers)]),
         2 => BitBoard::from_u64(BISHOP_MOVES[magic_index(&BISHOP_MAGICS[square as usize], blockers)]),
         3 => BitBoard::from_u64(ROOK_MOVES[magic_index(&ROOK_MAGICS[square as usize], blockers)]
             | BISHOP_MOVES[magic_index(&BISHOP_MAGICS[square as usize], blockers)]),
         _ => panic!("Piece different than slider passed to movegen::slider_moves()"),
-    };
+    }
 }
 
 pub fn slider_moves(piece: u8, square: u8, pos: &Position) -> BitBoard {
     let blockers = get_all_actual_blockers(piece, square, pos);
     let color = pos.piece_color(square);
-    return match piece {
+    match piece {
         0 => BitBoard::from_u64(ROOK_MOVES[magic_index(&ROOK_MAGICS[square as usize], blockers)] & !pos.color_bitboards[color as usize].0),
         2 => BitBoard::from_u64(BISHOP_MOVES[magic_index(&BISHOP_MAGICS[square as usize], blockers)] & !pos.color_bitboards[color as usize].0),
         3 => BitBoard::from_u64((ROOK_MOVES[magic_index(&ROOK_MAGICS[square as usize], blockers)]
             | BISHOP_MOVES[magic_index(&BISHOP_MAGICS[square as usize], blockers)]) & !pos.color_bitboards[color as usize].0),
         _ => panic!("Piece different than slider passed to movegen::slider_moves()"),
-    };
+    }
 }
 
 #[inline]
@@ -81,7 +86,10 @@ pub fn get_pseudolegal_knight_moves(square: u8) -> BitBoard {
 
 pub fn pawn_attacks(square: u8, color: usize) -> BitBoard {
     let file_index = square as usize % 8;
-    let rank_index = (square as usize / 8) - 1.clamp(0, 5);
+    // Unpromoted pawns on the first rank only appear while testing check evasions for legality and don't attack anything
+    let Some(rank_index) = (square as usize / 8).checked_sub(1) else {
+        return BitBoard::empty();
+    };
     let attacks = PAWN_ATTACKS[color][file_index] << (8 * rank_index);
     BitBoard::from_u64(attacks)
 }
@@ -103,11 +111,9 @@ pub fn get_knight_moves(square: u8, position: &Position) -> BitBoard {
 
 pub fn get_king_moves(square: u8, position: &Position) -> BitBoard {
     // Handle potential errors when trying to unwrap a piece from an empty square
-    let piece = position.piece_at(square);
-    match piece {
-        None => panic!("get_king_moves called on empty square"),
-        _ => (),
-    }
+    let Some((_, color)) = position.piece_at(square) else {
+        panic!("get_king_moves called on empty square");
+    };
 
     let mut moves = BitBoard::empty();
     for &(dx, dy) in &[
@@ -124,50 +130,13 @@ pub fn get_king_moves(square: u8, position: &Position) -> BitBoard {
             moves |= BitBoard::from_square(offset_by_delta);
         }
     }
-    
-    let color = position.piece_at(square).unwrap().1;
 
+    moves &= !position.color_bitboards[color as usize];
+
+    // Castling targets are added after removing the own pieces, a Chess960 castling move captures the own rook
     if position.state.castling_rights.0 != Castling::NO_CASTLING && !position.check {
-        match color {
-            Color::White => {
-                if position.state.castling_rights.0 & Castling::WHITE_KING_SIDE != Castling::NO_CASTLING {
-                    if position.piece_at(5).is_none()
-                        && position.piece_at(6).is_none()
-                    {
-                        moves |= BitBoard::from_square(6);
-                    }
-                }
-                if position.state.castling_rights.0 & Castling::WHITE_QUEEN_SIDE != Castling::NO_CASTLING {
-                    if position.piece_at(3).is_none()
-                        && position.piece_at(2).is_none()
-                        && position.piece_at(1).is_none()
-                    {
-                        moves |= BitBoard::from_square(2);
-                    }
-                }
-            }
-            Color::Black => {
-                if position.state.castling_rights.0 & Castling::BLACK_KING_SIDE != Castling::NO_CASTLING {
-                    if position.piece_at(61).is_none()
-                        && position.piece_at(62).is_none()
-                    {
-                        moves |= BitBoard::from_square(62);
-                    }
-                }
-                if position.state.castling_rights.0 & Castling::BLACK_QUEEN_SIDE != Castling::NO_CASTLING {
-                    if position.piece_at(59).is_none()
-                        && position.piece_at(58).is_none()
-                        && position.piece_at(57).is_none()
-                    {
-                        moves |= BitBoard::from_square(58);
-                    }
-                }
-            
-            }
-        }
+        moves |= position.castling_targets(color);
     }
-
-    moves = moves & !position.color_bitboards[color as usize];
     moves
 }
 
@@ -210,15 +179,30 @@ pub fn get_pawn_moves(square: u8, position: &Position) -> BitBoard {
 }
 
 pub fn get_all_legal_moves_for_color(color: Color, pos: &mut Position) -> Vec<(u8, u8)> {
-    if pos.check {
-        return get_legal_moves_from_check(color, pos);
+    // Antichess has no check, so the moves don't have to be filtered for the safety of the king
+    if pos.state.variant == Variant::Antichess {
+        return variants::antichess_moves(color, pos);
     }
+    let mut moves = if pos.check {
+        get_legal_moves_from_check(color, pos)
+    } else {
+        get_legal_moves(color, pos)
+    };
+    // Crazyhouse adds the drops of the pieces in hand
+    if pos.state.variant == Variant::Crazyhouse {
+        moves.extend(get_drop_moves(color, pos));
+    }
+    moves
+}
+
+// Legal moves of the pieces on the board when not in check
+fn get_legal_moves(color: Color, pos: &mut Position) -> Vec<(u8, u8)> {
     let mut moves: Vec<(u8, u8)> = Vec::new();
 
     // Iterate over all squares with a piece of the given color
     let mut squares = pos.color_bitboards[color as usize];
     while !squares.is_empty() {
-        let square = squares.trailing_zeros() as u8;
+        let square = squares.trailing_zeros();
         let piece = pos.piece_at(square).unwrap().0;
         let piece_moves = match piece {
             0 | 2 | 3 => slider_moves(piece, square, pos),
@@ -230,7 +214,7 @@ pub fn get_all_legal_moves_for_color(color: Color, pos: &mut Position) -> Vec<(u
 
         let mut piece_moves_iterator = piece_moves;
         while !piece_moves_iterator.is_empty() {
-            let piece_move = piece_moves_iterator.trailing_zeros() as u8;
+            let piece_move = piece_moves_iterator.trailing_zeros();
             moves.push((square, piece_move));
             piece_moves_iterator.clear_lsb();
         }
@@ -244,13 +228,13 @@ pub fn get_all_legal_moves_for_color(color: Color, pos: &mut Position) -> Vec<(u
         let is_pawn = new_pos.piece_at(*from).unwrap().0 == 5;
         let is_king = new_pos.piece_at(*from).unwrap().0 == 4;
 
+        let castling = if is_king { pos.castling_right_of(*from, *to).map(|right| pos.castling_squares(right)) } else { None };
+
         // If the move would put a king next to another king, remove it
         if is_king {
-            let opposite_king = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[!color as usize]).trailing_zeros() as i8;
-            if (opposite_king - *to as i8).abs() < 2 {
-                moves_to_remove.push((*from, *to));
-                continue;
-            } else if (opposite_king - *to as i8).abs() > 6 && (opposite_king - *to as i8).abs() < 10 {
+            let king_to = castling.map_or(*to, |castling| castling.king_to);
+            let opposite_king = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[!color as usize]).trailing_zeros();
+            if is_next_to(king_to, opposite_king) {
                 moves_to_remove.push((*from, *to));
                 continue;
             }
@@ -259,44 +243,35 @@ pub fn get_all_legal_moves_for_color(color: Color, pos: &mut Position) -> Vec<(u
         let mut attackers_to_update = BitBoard::empty();
 
         // Remove the move if it would castle through check
-        if is_king && (*to as i8 - *from as i8).abs() == 2 {
-            match to {
-                2 | 58 => {
-                    if pos.is_square_attacked_by_color(*to, !color) ||
-                            pos.is_square_attacked_by_color(*to - 1, !color) ||
-                            pos.is_square_attacked_by_color(*to - 2, !color) {
-                        moves_to_remove.push((*from, *to));
-                        continue;
-                    }
-                },
-                6 | 62 => {
-                    if pos.is_square_attacked_by_color(*to, !color) ||
-                            pos.is_square_attacked_by_color(*to + 1, !color) {
-                        moves_to_remove.push((*from, *to));
-                        continue;
-                    }
-                },
-                _ => (),
+        if let Some(castling) = castling {
+            let path = cmp::min(castling.king_from, castling.king_to)..=cmp::max(castling.king_from, castling.king_to);
+            if path.into_iter().any(|square| pos.is_square_attacked_by_color(square, !color)) {
+                moves_to_remove.push((*from, *to));
+                continue;
             }
+            // Sliders that were blocked by the rook
+            attackers_to_update |= game::get_attacking_sliders(&mut new_pos, castling.rook_from);
         }
 
         // List of sliders that after the move no longer have their path blocker by the moved piece
         let freed_sliders = game::get_attacking_sliders(&mut new_pos, *from);
         attackers_to_update |= freed_sliders;
 
-        new_pos.make_move(&from, &to);
+        // Castling moves the rook as well
+        new_pos.make_move(from, to);
 
-        // If the move is a castling move, move the rook as well
-        if is_king && ((*from as i8 % 8) - (*to as i8 % 8)).abs() > 1 {
-            if from > to {
-                new_pos.make_castling_move(&(to - 2), &(from - 1));
-                attackers_to_update |= BitBoard::from_square(from - 1);
-                attackers_to_update ^= BitBoard::from_square(to - 1);
-            } else {
-                new_pos.make_castling_move(&(to + 1), &(from + 1));
-                attackers_to_update |= BitBoard::from_square(from + 1);
-                attackers_to_update ^= BitBoard::from_square(to + 1);
-            
+        // En passant removes the captured pawn as well, which may uncover an attack along the rank of the king
+        if is_pawn && pos.en_passant_square == Some(*to) {
+            let captured = if color == Color::White { to - 8 } else { to + 8 };
+            attackers_to_update |= game::get_attacking_sliders(&mut new_pos, captured);
+            new_pos.color_bitboards[!color as usize] ^= BitBoard::from_square(captured);
+            new_pos.piece_bitboards[Piece::PAWN as usize] ^= BitBoard::from_square(captured);
+        }
+
+        if let Some(castling) = castling {
+            for square in [castling.king_to, castling.rook_to] {
+                attackers_to_update |= BitBoard::from_square(square);
+                attackers_to_update |= game::get_attacking_sliders(&mut new_pos, square);
             }
         }
 
@@ -315,7 +290,7 @@ pub fn get_all_legal_moves_for_color(color: Color, pos: &mut Position) -> Vec<(u
         }
 
         // If after these updates, the king is in the list of attacked squares, the move is illegal
-        let king_square = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[color as usize]).trailing_zeros() as u8;
+        let king_square = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[color as usize]).trailing_zeros();
         if new_pos.is_square_attacked_by_color(king_square, !color) {
             moves_to_remove.push((*from, *to));
             continue;
@@ -334,7 +309,7 @@ fn get_legal_moves_from_check(color: Color, pos: &mut Position) -> Vec<(u8, u8)>
     // Iterate over all squares with a piece of the given color
     let mut squares = pos.color_bitboards[color as usize];
     while squares.0 != 0 {
-        let square = squares.trailing_zeros() as u8;
+        let square = squares.trailing_zeros();
         let piece = pos.piece_at(square).unwrap().0;
         let mut piece_moves = match piece {
             0 | 2 | 3 => slider_moves(piece, square, pos),
@@ -345,7 +320,7 @@ fn get_legal_moves_from_check(color: Color, pos: &mut Position) -> Vec<(u8, u8)>
         };
         squares.clear_lsb();
         while piece_moves.0 != 0 {
-            let piece_move = piece_moves.trailing_zeros() as u8;
+            let piece_move = piece_moves.trailing_zeros();
             moves.push((square, piece_move));
             piece_moves.clear_lsb();
         }
@@ -356,15 +331,13 @@ fn get_legal_moves_from_check(color: Color, pos: &mut Position) -> Vec<(u8, u8)>
     for (from, to) in moves.iter() {
         
         let mut new_pos = pos.clone();
+        let is_pawn = new_pos.piece_at(*from).unwrap().0 == 5;
         let is_king = new_pos.piece_at(*from).unwrap().0 == 4;
         
         // If the move would put a king next to another king, remove it
         if is_king {
-            let opposite_king = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[!color as usize]).trailing_zeros() as i8;
-            if (opposite_king - *to as i8).abs() < 2 {
-                moves_to_remove.push((*from, *to));
-                continue;
-            } else if (opposite_king - *to as i8).abs() > 6 && (opposite_king - *to as i8).abs() < 10 {
+            let opposite_king = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[!color as usize]).trailing_zeros();
+            if is_next_to(*to, opposite_king) {
                 moves_to_remove.push((*from, *to));
                 continue;
             }
@@ -374,7 +347,15 @@ fn get_legal_moves_from_check(color: Color, pos: &mut Position) -> Vec<(u8, u8)>
 
         attackers_to_update |= game::get_attacking_sliders(&mut new_pos, *from);
 
-        new_pos.make_move(&from, &to);
+        new_pos.make_move(from, to);
+
+        // En passant removes the captured pawn as well, which may be the pawn giving check
+        if is_pawn && pos.en_passant_square == Some(*to) {
+            let captured = if color == Color::White { to - 8 } else { to + 8 };
+            attackers_to_update |= game::get_attacking_sliders(&mut new_pos, captured);
+            new_pos.color_bitboards[!color as usize] ^= BitBoard::from_square(captured);
+            new_pos.piece_bitboards[Piece::PAWN as usize] ^= BitBoard::from_square(captured);
+        }
 
         attackers_to_update |= BitBoard::from_square(*to);
 
@@ -386,7 +367,7 @@ fn get_legal_moves_from_check(color: Color, pos: &mut Position) -> Vec<(u8, u8)>
         }
 
         // If after these updates, the king is in the list of attacked squares, the move is illegal
-        let king_square = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[color as usize]).trailing_zeros() as u8;
+        let king_square = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[color as usize]).trailing_zeros();
         if new_pos.is_square_attacked_by_color(king_square, !color) {
             moves_to_remove.push((*from, *to));
             continue;
@@ -397,6 +378,40 @@ fn get_legal_moves_from_check(color: Color, pos: &mut Position) -> Vec<(u8, u8)>
     moves
 }
 
+/* Drops of the pieces in hand onto empty squares, pawns can't be dropped on the first and last rank.
+/ A drop can't uncover an attack on the own king, so only drops while in check have to block the check. */
+pub fn get_drop_moves(color: Color, pos: &mut Position) -> Vec<(u8, u8)> {
+    let mut moves: Vec<(u8, u8)> = Vec::new();
+    let empty = !pos.all_pieces();
+    for piece in [Piece::QUEEN, Piece::ROOK, Piece::BISHOP, Piece::KNIGHT, Piece::PAWN] {
+        if pos.state.hands[color as usize][piece as usize] == 0 {
+            continue;
+        }
+        let mut targets = if piece == Piece::PAWN { empty & BitBoard::from_u64(0x00FF_FFFF_FFFF_FF00) } else { empty };
+        while !targets.is_empty() {
+            moves.push((DropMove::origin(piece), targets.trailing_zeros()));
+            targets.clear_lsb();
+        }
+    }
+
+    if pos.check {
+        moves.retain(|&(from, to)| {
+            let mut new_pos = pos.clone();
+            new_pos.make_move(&from, &to);
+            let attackers_to_update = BitBoard::from_square(to) | game::get_attacking_sliders(&mut new_pos, to);
+            game::update_attackers(&mut new_pos, attackers_to_update);
+            let king_square = (new_pos.piece_bitboards[4] & new_pos.color_bitboards[color as usize]).trailing_zeros();
+            !new_pos.is_square_attacked_by_color(king_square, !color)
+        });
+    }
+    moves
+}
+
+// Whether two squares touch, a king may never move next to the other king
+fn is_next_to(square: u8, other: u8) -> bool {
+    (square % 8).abs_diff(other % 8) <= 1 && (square / 8).abs_diff(other / 8) <= 1
+}
+
 pub fn get_all_captures_for_color(color: Color, pos: &mut Position) -> Vec<(u8, u8)> {
     let mut moves = get_all_legal_moves_for_color(color, pos);
     moves.retain(|&(_from, to)| pos.is_capture(&to));
@@ -1,37 +1,46 @@
 use std::{cmp,
-    time::Instant,
+    time::{Duration, Instant},
     collections::HashMap,
+    fmt,
+    mem,
+    ops::AddAssign,
     sync::{
+        Arc,
         Mutex,
-        atomic::AtomicBool,
+        MutexGuard,
+        PoisonError,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     },
 };
-use rayon::prelude::*;
-use rand::seq::SliceRandom;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use lazy_static::lazy_static;
-use indicatif::{ProgressBar, ProgressStyle};
 use crate::{
-    evaluation, game, movegen
+    book::Book,
+    evaluation::{self, EvalProfile, ImbalanceCache, MATERIAL_VALUES_MIDGAME}, game, movegen,
+    syzygy::{self, Tablebases},
+    variants,
+};
+use types::{
+    position::Position,
+    variant::Variant,
+    Piece,
 };
-use types::position::Position;
 use precompute::rng;
 
 const NUM_PIECE_TYPES: usize = 12;
 const NUM_SQUARES: usize = 64;
 
-// Define the Zobrist keys as a global variable
+// The Zobrist keys are the same for every engine and never change after they are generated
 lazy_static! {
     static ref ZOBRIST_KEYS: [[u64; NUM_SQUARES]; NUM_PIECE_TYPES] = initialize_zobrist_keys();
 }
 
-// Define the transposition table as a global variable
-lazy_static! {
-    static ref TRANSPOSITION_TABLE: Mutex<HashMap<u64, TranspositionEntry>> = Mutex::new(HashMap::new());
-}
+// The deadline is only checked every this many nodes to keep the search fast
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
 
-lazy_static! {
-    static ref MATE_IN_ONE_FOUND: AtomicBool = AtomicBool::new(false);
-}
+// Seed of the move ordering of deterministic searches
+const DETERMINISTIC_SEED: u64 = 0x5EED_A115_C0FF_EE42;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Flag {
@@ -48,6 +57,8 @@ struct TranspositionEntry {
     validity: bool,
 }
 
+type TranspositionTable = Mutex<HashMap<u64, TranspositionEntry>>;
+
 struct SearchResult {
     score: i32,
     best_move: (u8, u8),
@@ -59,20 +70,251 @@ struct SearchParameters{
     depth: u8,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SearchLimits {
+    // Depth the root moves are searched to, the maximum depth when searching with a move time
+    pub depth: u8,
+    pub move_time: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SearchOutcome {
+    // None if the side to move has no legal moves
+    pub best_move: Option<(u8, u8)>,
+    pub score: i32,
+    // Depth of the deepest iteration that finished
+    pub depth: u8,
+    pub nodes: u64,
+    pub time: Duration,
+    pub stats: SearchStats,
+}
+
+/* Counters of a search to see where its nodes go and how well moves are ordered. Every thread counts on its own and
+/ the counts are summed when an iteration finishes. A beta cutoff by the first move searched means the best move was
+/ ordered first, good move ordering cuts off with the first move most of the time. There are no success rates of
+/ null-move pruning and late move reductions: the search has neither, the statistics say so instead. */
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SearchStats {
+    // Nodes of the main search and the quiescence search
+    pub nodes: u64,
+    pub qnodes: u64,
+    // Lookups in the transposition table, lookups that found the position and lookups that ended the search of it
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    pub tt_cutoffs: u64,
+    pub beta_cutoffs: u64,
+    pub first_move_cutoffs: u64,
+    // Positions scored by the tablebases, every root move ranked by them counts as well
+    pub tb_hits: u64,
+    // Every finished iteration on its own, the deepest last
+    pub iterations: Vec<IterationStats>,
+}
+
+// The depth of an iteration, the number of plies below the root moves, with the nodes it searched and the time it took
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct IterationStats {
+    pub depth: u8,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl IterationStats {
+    /* Effective branching factor: the number of moves per ply that would give the nodes of the iteration in a tree of
+    / uniform width. The root moves count as a ply, so a depth 1 iteration searches two plies. */
+    pub fn branching_factor(&self) -> f64 {
+        (self.nodes as f64).powf(1.0 / (self.depth as f64 + 1.0))
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    part as f64 * 100.0 / total.max(1) as f64
+}
+
+impl SearchStats {
+    pub fn tt_hit_rate(&self) -> f64 {
+        percent(self.tt_hits, self.tt_probes)
+    }
+
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        percent(self.first_move_cutoffs, self.beta_cutoffs)
+    }
+
+    // The deepest finished iteration, an empty one if no iteration finished
+    pub fn last_iteration(&self) -> IterationStats {
+        self.iterations.last().copied().unwrap_or_default()
+    }
+
+    // Effective branching factor of the deepest finished iteration, see IterationStats::branching_factor
+    pub fn branching_factor(&self) -> f64 {
+        self.last_iteration().branching_factor()
+    }
+}
+
+// Sums the counters of several searches, like those of a bench, the iterations of the same depth are summed as well
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: SearchStats) {
+        self.nodes += other.nodes;
+        self.qnodes += other.qnodes;
+        self.tt_probes += other.tt_probes;
+        self.tt_hits += other.tt_hits;
+        self.tt_cutoffs += other.tt_cutoffs;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.tb_hits += other.tb_hits;
+        for iteration in other.iterations {
+            match self.iterations.iter_mut().find(|sum| sum.depth == iteration.depth) {
+                Some(sum) => {
+                    sum.nodes += iteration.nodes;
+                    sum.time += iteration.time;
+                },
+                None => self.iterations.push(iteration),
+            }
+        }
+        self.iterations.sort_by_key(|iteration| iteration.depth);
+    }
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Nodes:              {} ({} in quiescence search, {:.1}%)", self.nodes, self.qnodes, percent(self.qnodes, self.nodes))?;
+        writeln!(f, "Hash table:         {} probes, {} hits ({:.1}%), {} cutoffs ({:.1}%)",
+            self.tt_probes, self.tt_hits, self.tt_hit_rate(), self.tt_cutoffs, percent(self.tt_cutoffs, self.tt_probes))?;
+        writeln!(f, "Beta cutoffs:       {}, {} by the first move ({:.1}%)", self.beta_cutoffs, self.first_move_cutoffs, self.first_move_cutoff_rate())?;
+        writeln!(f, "Tablebase hits:     {}", self.tb_hits)?;
+        write!(f, "Null move, LMR:     not counted, the search has no null-move pruning or late move reductions")?;
+        for iteration in &self.iterations {
+            write!(f, "\nIteration {:>2}:       {} nodes in {:.3}s, branching factor {:.2}",
+                iteration.depth, iteration.nodes, iteration.time.as_secs_f64(), iteration.branching_factor())?;
+        }
+        Ok(())
+    }
+}
+
+// The counters of a single thread, see SearchStats
+#[derive(Default)]
+struct ThreadStats {
+    nodes: AtomicU64,
+    qnodes: AtomicU64,
+    tt_probes: AtomicU64,
+    tt_hits: AtomicU64,
+    tt_cutoffs: AtomicU64,
+    beta_cutoffs: AtomicU64,
+    first_move_cutoffs: AtomicU64,
+    tb_hits: AtomicU64,
+}
+
+// Adds one to a counter of the thread, only the thread itself writes its counters
+fn count(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+// Progress of a running search, passed to the info callback of the engine
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SearchInfo {
+    // A root move of the current iteration has been searched
+    RootMove { depth: u8, searched: usize, total: usize },
+    // An iteration finished, with the result of the search so far
+    Iteration(SearchOutcome),
+}
+
+pub type InfoCallback = Box<dyn Fn(&SearchInfo) + Send + Sync>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct EngineOptions {
+    // Threads the root moves are searched with, 0 for one thread per CPU core
+    pub threads: usize,
+    // Approximate size of the transposition table in MB, new positions aren't stored once it is full
+    pub hash: usize,
+    // Weights of the evaluation terms, for engines with a different playing style
+    pub profile: EvalProfile,
+    /* Searches with one thread and shuffles moves by a fixed seed instead of randomly, so searches limited by depth
+    / find the same move and score with the same number of nodes every time. Searches with a move time still depend on
+    / the speed of the machine. */
+    pub deterministic: bool,
+    // Plays moves from the opening book of the engine before searching, up to book_depth plies into the game
+    pub own_book: bool,
+    pub book_depth: u16,
+    // Always plays the book move with the highest weight instead of choosing randomly by weight
+    pub best_book_move: bool,
+    // Minimum remaining depth to probe positions with the largest piece count of the tablebases
+    pub syzygy_probe_depth: u8,
+    // Positions with more pieces than this are never probed
+    pub syzygy_probe_limit: usize,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            threads: 0,
+            hash: 64,
+            profile: EvalProfile::default(),
+            deterministic: false,
+            own_book: false,
+            book_depth: u16::MAX,
+            best_book_move: false,
+            syzygy_probe_depth: 1,
+            syzygy_probe_limit: syzygy::TB_PIECES,
+        }
+    }
+}
+
+/* The opening book and the Syzygy tables of an engine. They are loaded once and can be shared by several engines,
+/ the options of each engine decide how it uses them. */
+#[derive(Clone, Default)]
+pub struct Resources {
+    pub book: Option<Arc<Book>>,
+    pub tablebases: Option<Arc<Tablebases>>,
+}
+
+/* A search engine with its own transposition table, thread pool, info callback, opening book and tablebases. Engines
+/ don't share any search state, so several of them can search at the same time, for example both sides of a match. */
+pub struct Engine {
+    options: EngineOptions,
+    resources: Resources,
+    table: TranspositionTable,
+    // Material imbalances of every thread of the pool, by the index of the thread
+    imbalance_caches: Vec<Mutex<ImbalanceCache>>,
+    pool: ThreadPool,
+    info: Option<InfoCallback>,
+    stop: Arc<AtomicBool>,
+}
+
+// State of a single search, shared between the threads searching the root moves
+struct Search<'a> {
+    table: &'a TranspositionTable,
+    // Maximum number of entries in the table
+    capacity: usize,
+    profile: EvalProfile,
+    imbalance_caches: &'a [Mutex<ImbalanceCache>],
+    deterministic: bool,
+    tablebases: Option<&'a Tablebases>,
+    syzygy_probe_depth: u8,
+    syzygy_probe_limit: usize,
+    info: Option<&'a InfoCallback>,
+    mate_in_one_found: AtomicBool,
+    stop: &'a AtomicBool,
+    // Time after which the search is stopped
+    deadline: Mutex<Option<Instant>>,
+    nodes: AtomicU64,
+    searched_root_moves: AtomicUsize,
+    // Counters of every thread of the pool, by the index of the thread
+    thread_stats: Vec<ThreadStats>,
+}
+
 
 // Function to initialize the Zobrist keys
 fn initialize_zobrist_keys() -> [[u64; NUM_SQUARES]; NUM_PIECE_TYPES] {
     let mut rng_instance = rng::Rng::default();
     let mut keys = [[0; NUM_SQUARES]; NUM_PIECE_TYPES];
     let mut dupe_keys = Vec::new();
-    for piece_type in 0..NUM_PIECE_TYPES {
-        for square in 0..NUM_SQUARES {
+    for piece_keys in keys.iter_mut() {
+        for square_key in piece_keys.iter_mut() {
             let key = rng_instance.next_u64();
             if dupe_keys.contains(&key) {
                 panic!("Duplicate key generated: {}", key);
             }
             dupe_keys.push(key);
-            keys[piece_type][square] = key;
+            *square_key = key;
         }
     }
     keys
@@ -85,51 +327,41 @@ fn calculate_hash(pos: &Position) -> u64 {
             hash ^= ZOBRIST_KEYS[piece as usize][square];
         }
     }
-    hash
-}
-
-// Function to get a position's entry from the transposition table
-fn get_entry(hash: u64) -> Option<TranspositionEntry> {
-    match TRANSPOSITION_TABLE.lock() {
-        Ok(table) => {
-            let entry = table.get(&hash).cloned();
-            entry
-        },
-        Err(e) => {
-            println!("Error acquiring lock on transposition table while getting entry: {:?}", e);
-            None
+    // Pawns never stand on the first rank, so their keys there are free for the checks given in Three-check
+    if pos.state.variant == Variant::ThreeCheck {
+        for (color, &checks) in pos.state.checks.iter().enumerate() {
+            hash ^= ZOBRIST_KEYS[Piece::PAWN as usize][color * 4 + checks.min(3) as usize];
         }
     }
-}
-
-// Function to store a position's entry in the transposition table
-fn store_entry(hash: u64, entry: TranspositionEntry) {
-    match TRANSPOSITION_TABLE.lock() {
-        Ok(mut table) => {
-            // Check for hash collision
-            if let Some(old_entry) = table.get(&hash) {
-                if entry.depth > old_entry.depth {
-                    // println!("During hash collision, replacing entry because new entry has depth {} and old entry has depth {}.", entry.depth, old_entry.depth);
-                    table.insert(hash, entry);
-                } else {
-                    return;
-                }
-            } else {
-                table.insert(hash, entry);
+    /* Only the first six piece types are used for the board, the keys of the others are used for Crazyhouse: one key
+    / for every count of every piece type in the hand of each color and a key for every square with a promoted piece. */
+    if pos.state.variant == Variant::Crazyhouse {
+        for (color, hand) in pos.state.hands.iter().enumerate() {
+            for (piece, &count) in hand.iter().enumerate().filter(|&(_, &count)| count > 0) {
+                hash ^= ZOBRIST_KEYS[6 + piece][color * 32 + cmp::min(count, 31) as usize];
             }
-        },
-        Err(_) => {
-            println!("Error acquiring lock on transposition table while storing entry.");
+        }
+        let mut promoted = pos.state.promoted;
+        while !promoted.is_empty() {
+            hash ^= ZOBRIST_KEYS[6 + Piece::KING as usize][promoted.trailing_zeros() as usize];
+            promoted.clear_lsb();
         }
     }
+    hash
 }
 
-// Returns all legal moves for the current position ordered by rough likelihood of being played
-fn order_moves(mut moves: Vec<(u8, u8)>, pos: &mut Position) -> Vec<(u8, u8)> {
-    moves.shuffle(&mut rand::thread_rng());
-    moves.sort_by_key(|&(start, end)| {
+/* Returns all legal moves for the current position ordered by rough likelihood of being played. Moves of the same kind
+/ are shuffled, in deterministic searches by a seed derived from the position, so the order doesn't depend on which
+/ nodes were searched before. */
+fn order_moves(mut moves: Vec<(u8, u8)>, pos: &mut Position, deterministic: bool) -> Vec<(u8, u8)> {
+    if deterministic {
+        moves.shuffle(&mut StdRng::seed_from_u64(DETERMINISTIC_SEED ^ calculate_hash(pos)));
+    } else {
+        moves.shuffle(&mut rand::thread_rng());
+    }
+    moves.sort_by_cached_key(|&(start, end)| {
         match () {
-            () if game::would_give_check(pos, start, end) => 0, 
+            () if game::would_give_check(pos, start, end) => 0,
             () if pos.is_promotion(&start, &end) => 1,
             () if pos.is_capture(&end) => 2,
             _ => 3,
@@ -138,179 +370,436 @@ fn order_moves(mut moves: Vec<(u8, u8)>, pos: &mut Position) -> Vec<(u8, u8)> {
     moves
 }
 
-fn negamax(pos: &mut Position, params: &mut SearchParameters) -> i32 {
+/* Orders captures by the value of the captured piece, most valuable victim first, and among those by the value of the
+/ capturing piece, least valuable attacker first. En passant captures a pawn. */
+fn order_captures(mut captures: Vec<(u8, u8)>, pos: &Position) -> Vec<(u8, u8)> {
+    let value = |square: u8| MATERIAL_VALUES_MIDGAME[pos.piece_type_at(square).unwrap_or(Piece::PAWN) as usize];
+    captures.sort_by_key(|&(from, to)| (cmp::Reverse(value(to)), value(from)));
+    captures
+}
 
-    // If the position has already been evaluated to the desired depth, return the stored score
-    let hash = calculate_hash(pos);
+// Scores a position that has been won or lost by a rule of the variant from the view of the side to move
+fn variant_result(pos: &mut Position) -> Option<i32> {
+    let winner = variants::winner(pos)?;
+    Some(if winner == pos.state.active_player { variants::WIN_SCORE } else { variants::LOSS_SCORE })
+}
 
-    let mut alpha = params.alpha;
-    let mut beta = params.beta;
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(EngineOptions::default())
+    }
+}
 
-    if let Some(entry) = get_entry(hash) {
-        if entry.validity && entry.depth >= params.depth {
-            if entry.flag == Flag::Exact {
-                return entry.score;
-            } else if entry.flag == Flag::LowerBound {
-                alpha = cmp::max(alpha, entry.score);
-            } else if entry.flag == Flag::UpperBound {
-                beta = cmp::min(beta, entry.score);
-            }
-            if alpha >= beta {
-                return entry.score;
-            }
+impl Engine {
+    pub fn new(options: EngineOptions) -> Engine {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(if options.deterministic { 1 } else { options.threads })
+            .build()
+            .expect("Could not create the thread pool of the engine");
+        Engine {
+            options,
+            resources: Resources::default(),
+            table: Mutex::new(HashMap::new()),
+            imbalance_caches: (0..pool.current_num_threads()).map(|_| Mutex::default()).collect(),
+            pool,
+            info: None,
+            stop: Arc::new(AtomicBool::new(false)),
         }
-    } 
+    }
+
+    pub fn options(&self) -> EngineOptions {
+        self.options
+    }
 
-    if MATE_IN_ONE_FOUND.load(std::sync::atomic::Ordering::Relaxed) { return i32::MAX - 1; }
+    // Number of threads the engine searches with
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
 
-    // If we have reached a terminal node (game result), return the evaluation
-    if game::is_in_checkmate(pos) {
-        // println!("FEN {} is in checkmate, returning highest possible score.", fen_from_pos(pos));
-        return i32::MIN + 2;
-    } else if params.depth == 0 {
-        if game::is_quiet_position(pos) {
-            return evaluation::main_evaluation(pos);
-        } else {
-            return quiescence_search(pos, alpha, beta);
-        }
+    pub fn set_resources(&mut self, resources: Resources) {
+        self.resources = resources;
+    }
+
+    // Returns a move from the opening book if the engine plays from its book, see Book::book_move
+    pub fn book_move(&self, pos: &mut Position) -> Option<(u8, u8)> {
+        let book = self.resources.book.as_ref().filter(|_| self.options.own_book)?;
+        book.book_move(pos, self.options.book_depth, self.options.best_book_move)
     }
 
-    // Retrieve and order all legal moves
-    let mut legal_moves =
-        movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
-    legal_moves = order_moves(legal_moves, pos);
+    // Sets the function that is called with the progress of every search, replacing the previous one
+    pub fn set_info_callback(&mut self, callback: impl Fn(&SearchInfo) + Send + Sync + 'static) {
+        self.info = Some(Box::new(callback));
+    }
 
-    let mut score = i32::MIN + 1;
+    pub fn clear_hash(&mut self) {
+        self.table.get_mut().unwrap_or_else(PoisonError::into_inner).clear();
+    }
 
-    // Iterate over all legal moves
-    for (from, to) in legal_moves.iter() {
-        let mut new_pos = pos.clone();
-        game::apply_move(&mut new_pos, *from, *to);
+    /* Returns the flag that stops the running search of the engine when it is set, for example by the UCI stop command
+    / from another thread. The search then returns the result of its deepest finished iteration and clears the flag. */
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
 
-        score = cmp::max(score, -negamax(&mut new_pos, &mut SearchParameters {
-            alpha: -beta,
-            beta: -alpha,
-            depth: params.depth - 1,
-        }));
+    /* Searches the position and returns the best move found. With a move time, iterative deepening runs until the time
+    / is up or the depth limit is reached and the result of the deepest finished iteration is returned. The first
+    / iteration always finishes unless the search is stopped through the stop handle, so there is a move even when the
+    / time is very short. */
+    pub fn search(&mut self, pos: &Position, limits: SearchLimits) -> SearchOutcome {
+        let start_time = Instant::now();
+        self.clear_hash();
+
+        let search = Search {
+            table: &self.table,
+            capacity: self.options.hash * 1024 * 1024 / mem::size_of::<(u64, TranspositionEntry)>(),
+            profile: self.options.profile,
+            imbalance_caches: &self.imbalance_caches,
+            deterministic: self.options.deterministic,
+            tablebases: self.resources.tablebases.as_deref(),
+            syzygy_probe_depth: self.options.syzygy_probe_depth,
+            syzygy_probe_limit: self.options.syzygy_probe_limit,
+            info: self.info.as_ref(),
+            mate_in_one_found: AtomicBool::new(false),
+            stop: &self.stop,
+            deadline: Mutex::new(None),
+            nodes: AtomicU64::new(0),
+            searched_root_moves: AtomicUsize::new(0),
+            thread_stats: (0..self.pool.current_num_threads()).map(|_| ThreadStats::default()).collect(),
+        };
+        let mut pos = pos.clone();
+        let outcome = self.pool.install(|| search.iterative_deepening(&mut pos, limits, start_time));
+        self.stop.store(false, Ordering::Relaxed);
+        outcome
+    }
+}
 
-        alpha = cmp::max(alpha, score);
+impl Search<'_> {
+    fn iterative_deepening(&self, pos: &mut Position, limits: SearchLimits, start_time: Instant) -> SearchOutcome {
+        let mut root_moves = order_moves(self.root_moves(pos), pos, self.deterministic);
+        let mut outcome = SearchOutcome {
+            best_move: root_moves.first().copied(),
+            score: 0,
+            depth: 0,
+            nodes: 0,
+            time: Duration::ZERO,
+            stats: SearchStats::default(),
+        };
+        let mut iterations = Vec::new();
+
+        if root_moves.len() > 1 {
+            let first_depth = if limits.move_time.is_some() { 1 } else { limits.depth };
+            for depth in first_depth..=limits.depth {
+                if depth > first_depth {
+                    *self.deadline.lock().unwrap() = limits.move_time.map(|move_time| start_time + move_time);
+                }
+                let iteration_start = Instant::now();
+                let nodes_before = self.nodes.load(Ordering::Relaxed);
+                let Some(result) = self.search_root(pos, &root_moves, depth) else {
+                    break;
+                };
+                outcome.best_move = Some(result.best_move);
+                outcome.score = result.score;
+                outcome.depth = depth;
+                outcome.nodes = self.nodes.load(Ordering::Relaxed);
+                outcome.time = start_time.elapsed();
+                iterations.push(IterationStats { depth, nodes: outcome.nodes - nodes_before, time: iteration_start.elapsed() });
+                outcome.stats = self.stats(&iterations);
+                if let Some(info) = self.info {
+                    info(&SearchInfo::Iteration(outcome.clone()));
+                }
+                // Nothing beats a mate in one, searching deeper would only waste the remaining time
+                if result.score == i32::MAX {
+                    break;
+                }
+                if limits.move_time.is_some_and(|move_time| start_time.elapsed() >= move_time) {
+                    break;
+                }
+                // Search the best move first in the next iteration
+                root_moves.retain(|&root_move| root_move != result.best_move);
+                root_moves.insert(0, result.best_move);
+            }
+        }
 
-        // Beta-cutoff
-        if alpha >= beta {
-            break;
+        outcome.nodes = self.nodes.load(Ordering::Relaxed);
+        outcome.time = start_time.elapsed();
+        outcome.stats = self.stats(&iterations);
+        outcome
+    }
+
+    // Returns the legal moves to search at the root, only keeping the moves that preserve the result in tablebase positions
+    fn root_moves(&self, pos: &mut Position) -> Vec<(u8, u8)> {
+        let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+        let tablebase_moves = self.tablebases.and_then(|tablebases| tablebases.filter_root_moves(pos, &legal_moves, self.syzygy_probe_limit));
+        match tablebase_moves {
+            Some(moves) => {
+                self.thread_stats().tb_hits.fetch_add(legal_moves.len() as u64, Ordering::Relaxed);
+                moves
+            },
+            None => legal_moves,
         }
     }
 
-    let flag = if score <= params.alpha {
-        Flag::UpperBound
-    } else if score >= beta {
-        Flag::LowerBound
-    } else {
-        Flag::Exact
-    };
-    let validity = true;
-
-    store_entry(hash, TranspositionEntry {
-        depth: params.depth,
-        score,
-        flag,
-        validity,
-    });
+    // The counters of the calling thread
+    fn thread_stats(&self) -> &ThreadStats {
+        let index = rayon::current_thread_index().filter(|&index| index < self.thread_stats.len());
+        &self.thread_stats[index.unwrap_or(0)]
+    }
 
-    // Return the best score found (or the cutoff if no improvement was made)
-    score
+    // Evaluates the position with the imbalance cache of the calling thread
+    fn evaluate(&self, pos: &mut Position) -> i32 {
+        let index = rayon::current_thread_index().filter(|&index| index < self.imbalance_caches.len());
+        let mut cache = self.imbalance_caches[index.unwrap_or(0)].lock().unwrap_or_else(PoisonError::into_inner);
+        evaluation::evaluate(pos, &self.profile, &mut cache)
+    }
 
-}
+    // Sums the counters of all threads, with the iterations finished so far
+    fn stats(&self, iterations: &[IterationStats]) -> SearchStats {
+        let mut stats = SearchStats { iterations: iterations.to_vec(), ..SearchStats::default() };
+        for thread in &self.thread_stats {
+            stats.nodes += thread.nodes.load(Ordering::Relaxed);
+            stats.qnodes += thread.qnodes.load(Ordering::Relaxed);
+            stats.tt_probes += thread.tt_probes.load(Ordering::Relaxed);
+            stats.tt_hits += thread.tt_hits.load(Ordering::Relaxed);
+            stats.tt_cutoffs += thread.tt_cutoffs.load(Ordering::Relaxed);
+            stats.beta_cutoffs += thread.beta_cutoffs.load(Ordering::Relaxed);
+            stats.first_move_cutoffs += thread.first_move_cutoffs.load(Ordering::Relaxed);
+            stats.tb_hits += thread.tb_hits.load(Ordering::Relaxed);
+        }
+        stats
+    }
+
+    // Searches the root moves in parallel, returns None if the search was stopped before all moves were searched
+    fn search_root(&self, pos: &mut Position, root_moves: &[(u8, u8)], depth: u8) -> Option<SearchResult> {
+        let alpha = i32::MIN + 1;
+        let beta = i32::MAX - 1;
+        self.searched_root_moves.store(0, Ordering::Relaxed);
+
+        let results: Vec<SearchResult> = root_moves.par_iter().
+            map(|&(from, to)| {
+                let mut new_pos = pos.clone();
+                game::apply_move(&mut new_pos, from, to);
+                if game::is_in_checkmate(&mut new_pos) || variants::winner(&mut new_pos) == Some(pos.state.active_player) {
+                    self.mate_in_one_found.store(true, Ordering::Relaxed);
+                    return SearchResult {
+                        score: i32::MAX,
+                        best_move: (from, to),
+                    };
+                }
+                let score = -self.negamax(&mut new_pos, &mut SearchParameters {
+                    alpha,
+                    beta,
+                    depth,
+                });
+
+                let searched = self.searched_root_moves.fetch_add(1, Ordering::Relaxed) + 1;
+                if let Some(info) = self.info {
+                    info(&SearchInfo::RootMove { depth, searched, total: root_moves.len() });
+                }
+                SearchResult {
+                    score,
+                    best_move: (from, to),
+                }
+            }).collect();
 
-fn quiescence_search(pos: &mut Position, mut alpha: i32, beta: i32) -> i32 {
-    let stand_pat = evaluation::main_evaluation(pos);
-    if stand_pat >= beta {
-        return beta;
+        if self.stop.load(Ordering::Relaxed) {
+            return None;
+        }
+        results.into_iter().max_by_key(|r| r.score)
     }
-    if alpha < stand_pat {
-        alpha = stand_pat;
+
+    /* Locks the transposition table. A thread that panicked while holding the lock can't have left an entry half
+    / written, so the table of a poisoned lock is used as it is. */
+    fn lock_table(&self) -> MutexGuard<'_, HashMap<u64, TranspositionEntry>> {
+        self.table.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
-    // Delta pruning - get the highest possible score swing for any move. If it's less than the current alpha, return alpha.
-    let delta = 1400;
-    if stand_pat < alpha - delta { 
-        // println!("Delta pruning during quiescence search.");
-        return alpha;
+    // Function to get a position's entry from the transposition table
+    fn get_entry(&self, hash: u64) -> Option<TranspositionEntry> {
+        self.lock_table().get(&hash).cloned()
     }
 
-    let all_captures = movegen::get_all_captures_for_color(pos.state.active_player, pos);
-    for (from, to) in all_captures {
-        let mut new_pos = pos.clone();
-        game::apply_move(&mut new_pos, from, to);
-        let score = -quiescence_search(&mut new_pos, -beta, -alpha);
-        if score >= beta {
-            return beta;
+    // Function to store a position's entry in the transposition table
+    fn store_entry(&self, hash: u64, entry: TranspositionEntry) {
+        let mut table = self.lock_table();
+        // Check for hash collision
+        if let Some(old_entry) = table.get(&hash) {
+            if entry.depth > old_entry.depth {
+                table.insert(hash, entry);
+            }
+        } else if table.len() < self.capacity {
+            table.insert(hash, entry);
         }
-        if score > alpha {
-            alpha = score;
+    }
+
+    // Counts a node and stops the search if it ran past its deadline
+    fn visit_node(&self) {
+        count(&self.thread_stats().nodes);
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed);
+        if nodes.is_multiple_of(DEADLINE_CHECK_INTERVAL) {
+            if let Some(deadline) = *self.deadline.lock().unwrap() {
+                if Instant::now() >= deadline {
+                    self.stop.store(true, Ordering::Relaxed);
+                }
+            }
         }
     }
-    alpha
-}
 
-pub fn find_best_move(pos: &mut Position, depth: u8) -> (u8, u8) {
-    let start_time = Instant::now();
+    fn negamax(&self, pos: &mut Position, params: &mut SearchParameters) -> i32 {
+        self.visit_node();
+        // The result of a stopped search is thrown away
+        if self.stop.load(Ordering::Relaxed) {
+            return 0;
+        }
+
+        // If the position has already been evaluated to the desired depth, return the stored score
+        let hash = calculate_hash(pos);
+
+        let mut alpha = params.alpha;
+        let mut beta = params.beta;
+
+        let stats = self.thread_stats();
+        count(&stats.tt_probes);
+        if let Some(entry) = self.get_entry(hash) {
+            count(&stats.tt_hits);
+            if entry.validity && entry.depth >= params.depth {
+                if entry.flag == Flag::Exact {
+                    count(&stats.tt_cutoffs);
+                    return entry.score;
+                } else if entry.flag == Flag::LowerBound {
+                    alpha = cmp::max(alpha, entry.score);
+                } else if entry.flag == Flag::UpperBound {
+                    beta = cmp::min(beta, entry.score);
+                }
+                if alpha >= beta {
+                    count(&stats.tt_cutoffs);
+                    return entry.score;
+                }
+            }
+        }
+
+        if self.mate_in_one_found.load(Ordering::Relaxed) { return i32::MAX - 1; }
 
-    // Clear transposition table
-    TRANSPOSITION_TABLE.lock().unwrap().clear();
+        // Games decided by a rule of the variant are terminal nodes as well
+        if let Some(score) = variant_result(pos) {
+            return score;
+        }
 
-    MATE_IN_ONE_FOUND.store(false, std::sync::atomic::Ordering::Relaxed);
+        // If we have reached a terminal node (game result), return the evaluation
+        if game::is_in_checkmate(pos) {
+            return i32::MIN + 2;
+        }
 
-    println!("Running search at depth {} with {} threads", depth, rayon::current_num_threads());
-    
-    let mut legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
-    if legal_moves.len() == 1 {
-        return legal_moves[0];
-    }
+        // Positions in the endgame tablebases are scored by their result
+        let tablebase_score = self.tablebases
+            .and_then(|tablebases| tablebases.probe_search(pos, params.depth, self.syzygy_probe_depth, self.syzygy_probe_limit));
+        if let Some(score) = tablebase_score {
+            count(&self.thread_stats().tb_hits);
+            return score;
+        }
+
+        if params.depth == 0 {
+            if game::is_quiet_position(pos) {
+                return self.evaluate(pos);
+            } else {
+                return self.quiescence_search(pos, alpha, beta);
+            }
+        }
+
+        // Retrieve and order all legal moves
+        let mut legal_moves =
+            movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+
+        // No legal moves without being in checkmate is a stalemate
+        if legal_moves.is_empty() {
+            return 0;
+        }
+        legal_moves = order_moves(legal_moves, pos, self.deterministic);
+
+        let mut score = i32::MIN + 1;
+
+        // Iterate over all legal moves
+        for (index, (from, to)) in legal_moves.iter().enumerate() {
+            let mut new_pos = pos.clone();
+            game::apply_move(&mut new_pos, *from, *to);
+
+            score = cmp::max(score, -self.negamax(&mut new_pos, &mut SearchParameters {
+                alpha: -beta,
+                beta: -alpha,
+                depth: params.depth - 1,
+            }));
+
+            alpha = cmp::max(alpha, score);
+
+            // Beta-cutoff
+            if alpha >= beta {
+                count(&stats.beta_cutoffs);
+                if index == 0 {
+                    count(&stats.first_move_cutoffs);
+                }
+                break;
+            }
+        }
+
+        // Scores of a stopped search are incomplete and mustn't end up in the transposition table
+        if self.stop.load(Ordering::Relaxed) {
+            return 0;
+        }
 
-    println!("Evaluating {} legal moves", legal_moves.len());
+        let flag = if score <= params.alpha {
+            Flag::UpperBound
+        } else if score >= beta {
+            Flag::LowerBound
+        } else {
+            Flag::Exact
+        };
+        let validity = true;
 
-    let bar = ProgressBar::new(legal_moves.len() as u64);
-    bar.set_style(ProgressStyle::with_template("Move {pos}/{len} [{bar:40.cyan/blue}] {elapsed_precise}").
-        unwrap().
-        progress_chars("#>-"));
-    bar.inc(0);
-    legal_moves = order_moves(legal_moves, pos);
+        self.store_entry(hash, TranspositionEntry {
+            depth: params.depth,
+            score,
+            flag,
+            validity,
+        });
 
-    let alpha = i32::MIN + 1;
-    let beta = i32::MAX - 1;
+        // Return the best score found (or the cutoff if no improvement was made)
+        score
 
-    let results: Vec<SearchResult> = legal_moves.par_iter().
-        map(|&(from, to)| {
+    }
+
+    fn quiescence_search(&self, pos: &mut Position, mut alpha: i32, beta: i32) -> i32 {
+        let stats = self.thread_stats();
+        count(&stats.nodes);
+        count(&stats.qnodes);
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+        if let Some(score) = variant_result(pos) {
+            return score;
+        }
+        let stand_pat = self.evaluate(pos);
+        if stand_pat >= beta {
+            return beta;
+        }
+        if alpha < stand_pat {
+            alpha = stand_pat;
+        }
+
+        // Delta pruning - get the highest possible score swing for any move. If it's less than the current alpha, return alpha.
+        let delta = 1400;
+        if stand_pat < alpha.saturating_sub(delta) {
+            return alpha;
+        }
+
+        let all_captures = order_captures(movegen::get_all_captures_for_color(pos.state.active_player, pos), pos);
+        for (from, to) in all_captures {
             let mut new_pos = pos.clone();
             game::apply_move(&mut new_pos, from, to);
-            if game::is_in_checkmate(&mut new_pos) {
-                MATE_IN_ONE_FOUND.store(true, std::sync::atomic::Ordering::Relaxed);
-                return SearchResult {
-                    score: i32::MAX,
-                    best_move: (from, to),
-                };
+            let score = -self.quiescence_search(&mut new_pos, -beta, -alpha);
+            if score >= beta {
+                return beta;
             }
-            let score = -negamax(&mut new_pos, &mut SearchParameters {
-                alpha,
-                beta,
-                depth,
-            });
-
-            bar.inc(1);
-            SearchResult {
-                score,
-                best_move: (from, to),
+            if score > alpha {
+                alpha = score;
             }
-        }).collect();
-    let best_result = results.into_iter().max_by_key(|r| r.score).unwrap_or(SearchResult {
-        score: i32::MIN,
-        best_move: (0, 0),
-    });
-    
-    bar.finish();
-    let duration = start_time.elapsed();
-    println!("Search completed in {} seconds", duration.as_secs_f32());
-
-    best_result.best_move
-}
\ No newline at end of file
+        }
+        alpha
+    }
+}
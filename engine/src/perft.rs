@@ -0,0 +1,36 @@
+use rayon::prelude::*;
+use types::position::Position;
+use crate::{game, movegen};
+
+/* Perft counts the leaf nodes of the tree of legal moves to a given depth. Comparing the counts with known values
+/ finds bugs in the move generation, divide lists the count below every root move to narrow them down.
+/ Pawns only promote to queens, so the counts of positions with promotions are lower than the published ones. */
+
+pub fn perft(pos: &mut Position, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+    if depth == 1 {
+        return legal_moves.len() as u64;
+    }
+    legal_moves.iter()
+        .map(|&(from, to)| {
+            let mut new_pos = pos.clone();
+            game::apply_move(&mut new_pos, from, to);
+            perft(&mut new_pos, depth - 1)
+        })
+        .sum()
+}
+
+// Returns the perft count below every root move, the root moves are counted in parallel
+pub fn divide(pos: &mut Position, depth: u8) -> Vec<((u8, u8), u64)> {
+    let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+    legal_moves.par_iter()
+        .map(|&(from, to)| {
+            let mut new_pos = pos.clone();
+            game::apply_move(&mut new_pos, from, to);
+            ((from, to), perft(&mut new_pos, depth.saturating_sub(1)))
+        })
+        .collect()
+}
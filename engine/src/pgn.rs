@@ -0,0 +1,434 @@
+use std::{
+    fs,
+    io,
+    iter::Peekable,
+    str::Chars,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use types::{
+    fen::FenError,
+    position::Position,
+    state::GameResult,
+    types_utils::fen_from_pos,
+    variant::Variant,
+    Results,
+};
+use crate::{game, san, variants};
+
+/* Reading and writing of games in the Portable Game Notation. A game consists of its tag pairs, the moves of the
+/ main line in Standard Algebraic Notation and the result. Every move can carry numeric annotation glyphs, a comment
+/ and variations, which are alternatives to the move they are attached to.
+/ Comments in front of the first move of a variation are dropped when reading. */
+
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Exported movetext lines are kept below 80 characters
+const MAX_LINE_LENGTH: usize = 79;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PgnMove {
+    pub san: String,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+impl PgnMove {
+    pub fn new(san: &str) -> Self {
+        Self { san: san.to_string(), ..Self::default() }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PgnGame {
+    pub headers: Vec<(String, String)>,
+    // Comment in front of the first move
+    pub comment: Option<String>,
+    pub moves: Vec<PgnMove>,
+    pub result: String,
+}
+
+impl PgnGame {
+    // Creates a game from moves played from the given position, converting them to SAN
+    pub fn from_moves(start: &Position, moves: &[(u8, u8)]) -> Self {
+        let mut game = Self { result: "*".to_string(), ..Self::default() };
+        let fen = fen_from_pos(start);
+        if fen != START_FEN {
+            game.set_header("SetUp", "1");
+            game.set_header("FEN", &fen);
+        }
+
+        let mut pos = start.clone();
+        for &(from, to) in moves {
+            game.moves.push(PgnMove::new(&san::move_to_san(&mut pos, from, to)));
+            game::apply_move(&mut pos, from, to);
+        }
+        game
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self.headers.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old_value)) => *old_value = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    // Sets the result and its tag from the game result of a position
+    pub fn set_result(&mut self, game_result: GameResult) {
+        self.result = match game_result.0 {
+            Results::WHITE_VICTORY => "1-0",
+            Results::BLACK_VICTORY => "0-1",
+            Results::DRAW | Results::STALEMATE => "1/2-1/2",
+            _ => "*",
+        }.to_string();
+        let result = self.result.clone();
+        self.set_header("Result", &result);
+    }
+
+    /* Returns the position the game starts from, given by the FEN tag or the initial position of the variant.
+    / The Variant tag selects one of the variants of types::variant or Chess960, which is played by the standard rules
+    / and marked in the state of the position. Other variants are played by the standard rules as well. */
+    pub fn start_position(&self) -> Result<Position, FenError> {
+        let variant: Variant = self.header("Variant").and_then(|name| name.parse().ok()).unwrap_or_default();
+        let mut pos = match self.header("FEN") {
+            Some(fen) => Position::from_variant_fen(fen, variant)?,
+            None => variants::start_position(variant),
+        };
+        pos.state.chess960 = self.header("Variant").is_some_and(|name| name.eq_ignore_ascii_case("chess960"));
+        Ok(pos)
+    }
+
+    // Plays the main line from the start position and returns the final position with its move history
+    pub fn replay(&self) -> Result<Position, String> {
+        let mut pos = self.start_position().map_err(|e| format!("Invalid FEN tag: {}", e))?;
+        for (ply, pgn_move) in self.moves.iter().enumerate() {
+            let (from, to) = san::parse_san(&mut pos, &pgn_move.san)
+                .ok_or(format!("Illegal or unsupported move {} at ply {}", pgn_move.san, ply + 1))?;
+            game::apply_move(&mut pos, from, to);
+            pos.move_history.push((from, to));
+        }
+        Ok(pos)
+    }
+
+    // Formats the game as PGN with the Seven Tag Roster first and the movetext wrapped below 80 characters
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        for (tag, default) in SEVEN_TAG_ROSTER {
+            let value = match (tag, self.header(tag)) {
+                (_, Some(value)) => value,
+                ("Result", None) if !self.result.is_empty() => &self.result,
+                _ => default,
+            };
+            pgn.push_str(&format_header(tag, value));
+        }
+        for (tag, value) in &self.headers {
+            if !SEVEN_TAG_ROSTER.iter().any(|(roster_tag, _)| roster_tag == tag) {
+                pgn.push_str(&format_header(tag, value));
+            }
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        if let Some(comment) = &self.comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        movetext_tokens(&self.moves, self.start_ply(), &mut tokens);
+        tokens.push(if self.result.is_empty() { "*".to_string() } else { self.result.clone() });
+
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + 1 + token.len() > MAX_LINE_LENGTH {
+                pgn.push('\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                pgn.push(' ');
+                line_length += 1;
+            }
+            pgn.push_str(&token);
+            line_length += token.len();
+        }
+        pgn.push_str("\n\n");
+        pgn
+    }
+
+    // Number of plies played before the start position, taken from the side to move and move number of the FEN tag
+    fn start_ply(&self) -> usize {
+        let Some(fen) = self.header("FEN") else {
+            return 0;
+        };
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let black_to_move = fields.get(1) == Some(&"b");
+        let full_moves = fields.get(5).and_then(|field| field.parse::<usize>().ok()).unwrap_or(1).max(1);
+        2 * (full_moves - 1) + black_to_move as usize
+    }
+
+    fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.moves.is_empty() && self.comment.is_none()
+    }
+}
+
+fn format_header(tag: &str, value: &str) -> String {
+    format!("[{} \"{}\"]\n", tag, value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Appends the movetext of a line, move numbers are repeated for black after comments and variations
+fn movetext_tokens(moves: &[PgnMove], start_ply: usize, tokens: &mut Vec<String>) {
+    let mut needs_number = true;
+    for (i, pgn_move) in moves.iter().enumerate() {
+        let ply = start_ply + i;
+        if ply.is_multiple_of(2) {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        } else if needs_number {
+            tokens.push(format!("{}...", ply / 2 + 1));
+        }
+        tokens.push(pgn_move.san.clone());
+        for nag in &pgn_move.nags {
+            tokens.push(format!("${}", nag));
+        }
+        if let Some(comment) = &pgn_move.comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+
+        for variation in &pgn_move.variations {
+            let mut variation_tokens = Vec::new();
+            movetext_tokens(variation, ply, &mut variation_tokens);
+            if let Some(first) = variation_tokens.first_mut() {
+                first.insert(0, '(');
+                variation_tokens.last_mut().unwrap().push(')');
+                tokens.extend(variation_tokens);
+            }
+        }
+        needs_number = pgn_move.comment.is_some() || !pgn_move.variations.is_empty();
+    }
+}
+
+pub fn read_file(path: &str) -> io::Result<Vec<PgnGame>> {
+    // PGN files are supposed to be ASCII, invalid bytes are replaced instead of rejecting the whole file
+    let bytes = fs::read(path)?;
+    Ok(read_games(&String::from_utf8_lossy(&bytes)))
+}
+
+pub fn write_file(path: &str, games: &[PgnGame]) -> io::Result<()> {
+    fs::write(path, games.iter().map(PgnGame::to_pgn).collect::<String>())
+}
+
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    VariationStart,
+    VariationEnd,
+    Nag(u8),
+    Result(String),
+    Move(String),
+}
+
+// Splits a PGN text into its games
+pub fn read_games(text: &str) -> Vec<PgnGame> {
+    let mut parser = Parser::default();
+    let mut chars = text.chars().peekable();
+    let mut at_line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Lines starting with % are escaped and ignored
+            '%' if at_line_start => {
+                read_until(&mut chars, |c| c == '\n');
+            },
+            '{' => {
+                let comment = read_until(&mut chars, |c| c == '}');
+                chars.next();
+                parser.token(Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" ")));
+            },
+            ';' => {
+                let comment = read_until(&mut chars, |c| c == '\n');
+                parser.token(Token::Comment(comment.trim().to_string()));
+            },
+            '[' => {
+                if let Some((tag, value)) = read_tag(&mut chars) {
+                    parser.token(Token::Tag(tag, value));
+                }
+            },
+            '(' => parser.token(Token::VariationStart),
+            ')' => parser.token(Token::VariationEnd),
+            _ if c.is_whitespace() => {},
+            _ => {
+                let mut word = c.to_string();
+                word.push_str(&read_until(&mut chars, |c| c.is_whitespace() || "{};()[".contains(c)));
+                for token in word_tokens(&word) {
+                    parser.token(token);
+                }
+            },
+        }
+        at_line_start = c == '\n' || c == '%' || c == ';';
+    }
+
+    parser.finish_game();
+    parser.games
+}
+
+fn read_until(chars: &mut Peekable<Chars>, end: impl Fn(char) -> bool) -> String {
+    let mut text = String::new();
+    while let Some(&c) = chars.peek() {
+        if end(c) {
+            break;
+        }
+        text.push(c);
+        chars.next();
+    }
+    text
+}
+
+// Reads a tag pair like [Event "Casual game"], the opening bracket has already been read
+fn read_tag(chars: &mut Peekable<Chars>) -> Option<(String, String)> {
+    let mut inner = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in chars.by_ref() {
+        match c {
+            ']' if !in_quotes => break,
+            '"' if !escaped => in_quotes = !in_quotes,
+            _ => {},
+        }
+        escaped = c == '\\' && !escaped;
+        inner.push(c);
+    }
+
+    let (tag, value) = inner.trim().split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((tag.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+// Converts a word of the movetext to tokens: move numbers are dropped and move suffixes like "!?" become NAGs
+fn word_tokens(word: &str) -> Vec<Token> {
+    if matches!(word, "1-0" | "0-1" | "1/2-1/2" | "*") {
+        return vec![Token::Result(word.to_string())];
+    }
+    if let Some(nag) = word.strip_prefix('$') {
+        return nag.parse().map(Token::Nag).into_iter().collect();
+    }
+
+    // Move numbers may be glued to the move, as in "12.e4" or "12...e5", castling may be written with zeros
+    let word = if word.starts_with("0-0") {
+        word
+    } else {
+        word.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start_matches('.')
+    };
+    let san = word.trim_end_matches(['!', '?']);
+    let mut tokens = Vec::new();
+    if !san.is_empty() {
+        tokens.push(Token::Move(san.to_string()));
+    }
+    let nag = match &word[san.len()..] {
+        "!" => 1,
+        "?" => 2,
+        "!!" => 3,
+        "??" => 4,
+        "!?" => 5,
+        "?!" => 6,
+        _ => return tokens,
+    };
+    tokens.push(Token::Nag(nag));
+    tokens
+}
+
+#[derive(Default)]
+struct Parser {
+    games: Vec<PgnGame>,
+    game: PgnGame,
+    // The variations that are currently open, innermost last
+    variations: Vec<Vec<PgnMove>>,
+}
+
+impl Parser {
+    fn current_line(&mut self) -> &mut Vec<PgnMove> {
+        self.variations.last_mut().unwrap_or(&mut self.game.moves)
+    }
+
+    fn token(&mut self, token: Token) {
+        match token {
+            Token::Tag(tag, value) => {
+                // A tag pair after the moves of a game starts the next game
+                if !self.game.moves.is_empty() || !self.game.result.is_empty() {
+                    self.finish_game();
+                }
+                self.game.headers.push((tag, value));
+            },
+            Token::Comment(comment) => {
+                let in_variation = !self.variations.is_empty();
+                let target = match self.current_line().last_mut() {
+                    Some(pgn_move) => &mut pgn_move.comment,
+                    None if !in_variation => &mut self.game.comment,
+                    None => return,
+                };
+                *target = Some(match target.take() {
+                    Some(previous) => format!("{} {}", previous, comment),
+                    None => comment,
+                });
+            },
+            Token::VariationStart => self.variations.push(Vec::new()),
+            Token::VariationEnd => self.close_variation(),
+            Token::Nag(nag) => {
+                if let Some(pgn_move) = self.current_line().last_mut() {
+                    pgn_move.nags.push(nag);
+                }
+            },
+            Token::Result(result) => {
+                self.game.result = result;
+                self.finish_game();
+            },
+            Token::Move(san) => self.current_line().push(PgnMove::new(&san)),
+        }
+    }
+
+    // Attaches the innermost variation to the last move of the enclosing line
+    fn close_variation(&mut self) {
+        let Some(variation) = self.variations.pop() else {
+            return;
+        };
+        if let Some(pgn_move) = self.current_line().last_mut() {
+            if !variation.is_empty() {
+                pgn_move.variations.push(variation);
+            }
+        }
+    }
+
+    fn finish_game(&mut self) {
+        while !self.variations.is_empty() {
+            self.close_variation();
+        }
+        let game = std::mem::take(&mut self.game);
+        if !game.is_empty() {
+            self.games.push(game);
+        }
+    }
+}
+
+// Returns the current date in the PGN date format
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs() / 86400).unwrap_or(0) as i64;
+    // Converts days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
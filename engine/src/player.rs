@@ -0,0 +1,454 @@
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+use types::{position::Position, variant::Variant};
+use crate::{
+    clock::Clock,
+    negamax::{Engine, EngineOptions, Resources, SearchLimits},
+    san,
+    uci,
+};
+
+/* The players of the sides of a game. Every side is configured on its own: a human entering moves, this engine with
+/ its own search limits, hash table and evaluation profile, or an external engine that is started as a subprocess and
+/ spoken to over UCI. This allows hot-seat games of two humans as well as games between two engine configurations. */
+
+// Time an external engine gets to quit before it is killed
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Time an external engine gets to answer after its time on the clock or its move time is over, before it is killed
+const MOVE_GRACE: Duration = Duration::from_secs(1);
+
+// Engines only accept a draw with an equal score once the game phase has dropped to this, see get_phase_value
+const DRAWISH_PHASE: u32 = 64;
+
+/* When an engine resigns, offers a draw or accepts one, by the scores of its searches in centipawns from its own view.
+/ It resigns when its score has been below -resign_score for resign_moves moves in a row and offers a draw when its
+/ score has been within draw_score of zero for draw_moves moves in a row, 0 moves turn resigning or offering off. */
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Thresholds {
+    pub resign_score: i32,
+    pub resign_moves: usize,
+    pub draw_score: i32,
+    pub draw_moves: usize,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            resign_score: 1000,
+            resign_moves: 5,
+            draw_score: 10,
+            draw_moves: 20,
+        }
+    }
+}
+
+impl Thresholds {
+    // Whether the engine resigns after the scores of its moves, the latest score last
+    pub fn should_resign(&self, scores: &[i32]) -> bool {
+        self.resign_moves > 0 && scores.len() >= self.resign_moves
+            && scores[scores.len() - self.resign_moves..].iter().all(|&score| score < -self.resign_score)
+    }
+
+    // Whether the engine offers a draw after the scores of its moves, it offers again when declined after as many moves
+    pub fn should_offer_draw(&self, scores: &[i32]) -> bool {
+        let equal_moves = scores.iter().rev().take_while(|score| score.abs() <= self.draw_score).count();
+        self.draw_moves > 0 && equal_moves > 0 && equal_moves.is_multiple_of(self.draw_moves)
+    }
+
+    /* Whether the engine accepts a draw offer with the score from its view and the phase of the game: it accepts when
+    / it is worse, or when the score is equal and there isn't enough material left to play for a win. */
+    pub fn accepts_draw(&self, score: i32, phase: u32) -> bool {
+        score < -self.draw_score || (score <= self.draw_score && phase <= DRAWISH_PHASE)
+    }
+}
+
+// This engine as a player: the options of its engine, the limits of its searches and when it resigns or offers draws
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct EngineSettings {
+    pub options: EngineOptions,
+    pub limits: SearchLimits,
+    pub thresholds: Thresholds,
+}
+
+// An external UCI engine as a player
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UciSettings {
+    // Path of the executable, it is started without arguments
+    pub command: String,
+    pub limits: SearchLimits,
+    // Applied to the scores the engine reports in its info lines
+    pub thresholds: Thresholds,
+    // UCI options set before the game, as name and value
+    pub options: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PlayerConfig {
+    Human,
+    Engine(EngineSettings),
+    Uci(UciSettings),
+}
+
+impl PlayerConfig {
+    /* Parses a player from "human", "engine" or "uci:<path>". Engines take settings after a colon and uci engines after
+    / the path, separated by commas, like "engine:depth=6,profile=aggressive" or "uci:/usr/bin/stockfish,movetime=500".
+    / Both know depth, movetime in milliseconds and the thresholds resignscore, resignmoves, drawscore and drawmoves.
+    / This engine also knows threads, hash, profile and the weights of the profile (material, psqt, mobility and
+    / pieces), other settings of uci engines are set as UCI options.
+    / Settings that aren't given are taken from the defaults. */
+    pub fn parse(spec: &str, defaults: EngineSettings) -> Result<PlayerConfig, String> {
+        let (kind, settings) = spec.split_once(':').unwrap_or((spec, ""));
+        let mut settings = settings.split(',').map(str::trim).filter(|setting| !setting.is_empty());
+        match kind {
+            "human" if settings.next().is_none() => Ok(PlayerConfig::Human),
+            "engine" => {
+                let mut engine = defaults;
+                let mut depth = None;
+                let mut move_time = None;
+                for setting in settings {
+                    let (name, value) = setting.split_once('=').ok_or(format!("Invalid engine setting {}, expected name=value", setting))?;
+                    let number = |value: &str| value.parse::<usize>().map_err(|_| format!("Invalid value for {}: {}", name, value));
+                    let weight = |value: &str| value.parse::<i32>().map_err(|_| format!("Invalid weight for {}: {}", name, value));
+                    if set_threshold(&mut engine.thresholds, name, value)? {
+                        continue;
+                    }
+                    match name {
+                        "depth" => depth = Some(number(value)?),
+                        "movetime" => move_time = Some(number(value)?),
+                        "threads" => engine.options.threads = number(value)?,
+                        "hash" => engine.options.hash = number(value)?.max(1),
+                        "profile" => engine.options.profile = value.parse()?,
+                        "material" => engine.options.profile.material = weight(value)?,
+                        "psqt" => engine.options.profile.psqt = weight(value)?,
+                        "mobility" => engine.options.profile.mobility = weight(value)?,
+                        "pieces" => engine.options.profile.pieces = weight(value)?,
+                        _ => return Err(format!("Unknown engine setting {}", name)),
+                    }
+                }
+                engine.limits = limits(defaults.limits, depth, move_time)?;
+                Ok(PlayerConfig::Engine(engine))
+            },
+            "uci" => {
+                let command = settings.next().ok_or("Missing path of the uci engine, expected uci:<path>")?.to_string();
+                let mut depth = None;
+                let mut move_time = None;
+                let mut options = Vec::new();
+                let mut thresholds = defaults.thresholds;
+                for setting in settings {
+                    let (name, value) = setting.split_once('=').ok_or(format!("Invalid engine setting {}, expected name=value", setting))?;
+                    let number = |value: &str| value.parse::<usize>().map_err(|_| format!("Invalid value for {}: {}", name, value));
+                    if set_threshold(&mut thresholds, name, value)? {
+                        continue;
+                    }
+                    match name {
+                        "depth" => depth = Some(number(value)?),
+                        "movetime" => move_time = Some(number(value)?),
+                        _ => options.push((name.to_string(), value.to_string())),
+                    }
+                }
+                let limits = limits(defaults.limits, depth, move_time)?;
+                Ok(PlayerConfig::Uci(UciSettings { command, limits, thresholds, options }))
+            },
+            _ => Err(format!("Invalid player {}, expected human, engine[:settings] or uci:<path>[,settings]", spec)),
+        }
+    }
+}
+
+// Sets a threshold from a player setting, returns false if the setting isn't a threshold
+fn set_threshold(thresholds: &mut Thresholds, name: &str, value: &str) -> Result<bool, String> {
+    let number = || value.parse::<usize>().map_err(|_| format!("Invalid value for {}: {}", name, value));
+    match name {
+        "resignscore" => thresholds.resign_score = number()?.min(i32::MAX as usize) as i32,
+        "resignmoves" => thresholds.resign_moves = number()?,
+        "drawscore" => thresholds.draw_score = number()?.min(i32::MAX as usize) as i32,
+        "drawmoves" => thresholds.draw_moves = number()?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// Search limits from the settings of a player, a move time without a depth searches as deep as the time allows
+fn limits(defaults: SearchLimits, depth: Option<usize>, move_time: Option<usize>) -> Result<SearchLimits, String> {
+    if depth == Some(0) {
+        return Err("The depth must be at least 1".to_string());
+    }
+    let depth = depth.map(|depth| depth.min(uci::MAX_DEPTH as usize) as u8);
+    Ok(match (depth, move_time) {
+        (Some(depth), None) => SearchLimits { depth, move_time: None },
+        (depth, Some(move_time)) => SearchLimits {
+            depth: depth.unwrap_or(uci::MAX_DEPTH),
+            move_time: Some(Duration::from_millis(move_time as u64)),
+        },
+        (None, None) => defaults,
+    })
+}
+
+// A player of a game with the engine it searches with
+pub enum Player {
+    Human,
+    Engine { engine: Engine, limits: SearchLimits, thresholds: Thresholds },
+    Uci(UciEngine),
+}
+
+impl Player {
+    /* Creates the engine of the player, this engine gets the book and tablebases of the resources. External engines
+    / are started and set up for the variant of the games from the start position. */
+    pub fn new(config: &PlayerConfig, resources: &Resources, start: &Position) -> Result<Player, String> {
+        Ok(match config {
+            PlayerConfig::Human => Player::Human,
+            PlayerConfig::Engine(settings) => {
+                let mut engine = Engine::new(settings.options);
+                engine.set_resources(resources.clone());
+                Player::Engine { engine, limits: settings.limits, thresholds: settings.thresholds }
+            },
+            PlayerConfig::Uci(settings) => Player::Uci(UciEngine::start(settings, start)?),
+        })
+    }
+
+    pub fn is_human(&self) -> bool {
+        matches!(self, Player::Human)
+    }
+
+    // Prepares the engine of the player for a new game
+    pub fn new_game(&mut self) -> Result<(), String> {
+        match self {
+            Player::Human => Ok(()),
+            Player::Engine { engine, .. } => {
+                engine.clear_hash();
+                Ok(())
+            },
+            Player::Uci(uci_engine) => uci_engine.new_game(),
+        }
+    }
+
+    // When the engine of the player resigns and offers or accepts draws, humans decide themselves
+    pub fn thresholds(&self) -> Option<Thresholds> {
+        match self {
+            Player::Human => None,
+            Player::Engine { thresholds, .. } => Some(*thresholds),
+            Player::Uci(uci_engine) => Some(uci_engine.thresholds()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Player::Human => "Human",
+            Player::Engine { .. } => "AmselChess",
+            Player::Uci(uci_engine) => uci_engine.name(),
+        }
+    }
+}
+
+/* Why an external engine didn't make a move. An engine that doesn't answer in time loses on time and one that fails
+/ loses by abandoning the game, but a legal move that can't be played here, like an underpromotion, leaves the game
+/ unterminated. */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MoveError {
+    Timeout(String),
+    Unsupported(String),
+    Failed(String),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::Timeout(message) | MoveError::Unsupported(message) | MoveError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/* An external engine running as a subprocess, it is asked for moves with the position and go commands. Its output is
+/ read by a thread of its own, so waiting for a move can time out. */
+pub struct UciEngine {
+    // The name the engine reports with "id name", the command until it did
+    name: String,
+    settings: UciSettings,
+    // The variant and Chess960 mode the engine was set up for, it is set up again after being killed
+    variant: Variant,
+    chess960: bool,
+    process: Child,
+    input: ChildStdin,
+    output: Receiver<String>,
+    // Whether the engine was killed because it didn't move in time
+    killed: bool,
+}
+
+impl UciEngine {
+    // Starts the engine, waits for it to speak UCI and sets its options, the variant and Chess960 mode of the start position
+    pub fn start(settings: &UciSettings, start: &Position) -> Result<UciEngine, String> {
+        UciEngine::spawn(settings, start.state.variant, start.state.chess960)
+    }
+
+    fn spawn(settings: &UciSettings, variant: Variant, chess960: bool) -> Result<UciEngine, String> {
+        let mut process = Command::new(&settings.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Could not start {}: {}", settings.command, e))?;
+        let input = process.stdin.take().expect("The input of the engine is piped");
+        let output = read_lines(process.stdout.take().expect("The output of the engine is piped"));
+        let mut engine = UciEngine {
+            name: settings.command.clone(),
+            settings: settings.clone(),
+            variant,
+            chess960,
+            process,
+            input,
+            output,
+            killed: false,
+        };
+
+        engine.send("uci")?;
+        loop {
+            let line = engine.read_line()?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if line == "uciok" {
+                break;
+            }
+        }
+        for (name, value) in &settings.options {
+            engine.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        if variant != Variant::Standard {
+            engine.send(&format!("setoption name UCI_Variant value {}", variant.uci_name()))?;
+        }
+        if chess960 {
+            engine.send("setoption name UCI_Chess960 value true")?;
+        }
+        engine.new_game()?;
+        Ok(engine)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        self.settings.thresholds
+    }
+
+    // Tells the engine that a new game starts and waits until it is ready, an engine that was killed is started again
+    pub fn new_game(&mut self) -> Result<(), String> {
+        if self.killed {
+            *self = UciEngine::spawn(&self.settings, self.variant, self.chess960)?;
+            return Ok(());
+        }
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        while self.read_line()? != "readyok" {}
+        Ok(())
+    }
+
+    /* Asks the engine for its move in the game from the start position to the position, the move must be legal. With
+    / a clock the engine gets the remaining times and plans its time itself, its depth and move time still apply.
+    / An engine that hasn't moved when its time on the clock or its move time is over, plus MOVE_GRACE, is killed.
+    / Returns the move with the last score the engine reported, if it did. */
+    pub fn best_move(&mut self, start: &Position, pos: &Position, clock: Option<&Clock>) -> Result<((u8, u8), Option<i32>), MoveError> {
+        let limits = self.settings.limits;
+        self.send(&uci::position_command(start, &pos.move_history)).map_err(MoveError::Failed)?;
+        let go = match clock {
+            Some(clock) => {
+                let mut go = format!("go {}", clock.go_parameters(pos.state.active_player));
+                if limits.depth < uci::MAX_DEPTH {
+                    go.push_str(&format!(" depth {}", limits.depth));
+                }
+                if let Some(move_time) = limits.move_time {
+                    go.push_str(&format!(" movetime {}", move_time.as_millis()));
+                }
+                go
+            },
+            None => uci::go_command(limits),
+        };
+        // Searches limited only by depth may take as long as they need
+        let time = clock.map(|clock| clock.remaining(pos.state.active_player)).or(limits.move_time);
+        let deadline = time.map(|time| Instant::now() + time + MOVE_GRACE);
+        self.send(&go).map_err(MoveError::Failed)?;
+
+        let mut score = None;
+        loop {
+            let line = match deadline {
+                Some(deadline) => match self.output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(line) => line,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.kill();
+                        return Err(MoveError::Timeout(format!("{} didn't move in time", self.name)));
+                    },
+                    Err(RecvTimeoutError::Disconnected) => return Err(MoveError::Failed(format!("{} has quit", self.name))),
+                },
+                None => self.read_line().map_err(MoveError::Failed)?,
+            };
+            if line.starts_with("info") {
+                score = uci::parse_info_score(&line).or(score);
+            } else if let Some(best_move) = line.strip_prefix("bestmove") {
+                let text = best_move.split_whitespace().next().unwrap_or("");
+                if let Some(best_move) = san::parse_uci(&mut pos.clone(), text) {
+                    return Ok((best_move, score));
+                }
+                // Pawns always promote to queens, other promotions are legal but can't be played
+                let promotion = text.get(4..).filter(|piece| ["r", "b", "n"].contains(piece));
+                if promotion.is_some() && text.get(..4).and_then(|text| san::parse_uci(&mut pos.clone(), text)).is_some() {
+                    return Err(MoveError::Unsupported(format!("{} played the underpromotion {}, which isn't supported", self.name, text)));
+                }
+                return Err(MoveError::Failed(format!("{} played an illegal move: {}", self.name, text)));
+            }
+        }
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.input, "{}", command)
+            .and_then(|_| self.input.flush())
+            .map_err(|e| format!("Could not send {} to {}: {}", command, self.name, e))
+    }
+
+    fn read_line(&mut self) -> Result<String, String> {
+        self.output.recv().map_err(|_| format!("{} has quit", self.name))
+    }
+
+    fn kill(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        self.killed = true;
+    }
+}
+
+// Reads the output of an engine line by line in a thread, the receiver is disconnected when the engine quits
+fn read_lines(output: ChildStdout) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if sender.send(line.trim().to_string()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// Engines are asked to quit and killed if they don't
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let mut waited = Duration::ZERO;
+        while let Ok(None) = self.process.try_wait() {
+            if waited >= QUIT_TIMEOUT {
+                let _ = self.process.kill();
+                let _ = self.process.wait();
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            waited += Duration::from_millis(10);
+        }
+    }
+}
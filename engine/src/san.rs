@@ -0,0 +1,257 @@
+use types::{
+    position::Position,
+    Castling,
+    DropMove,
+    Piece,
+};
+use crate::{game, movegen};
+
+/* Standard Algebraic Notation: the piece letter (none for pawns), the origin file, rank or square if another piece
+/ of the same type can reach the target square, "x" for captures, the target square, "=Q" for promotions and "+" or
+/ "#" for checks and mates. Castling is written as "O-O" and "O-O-O".
+/ Moves can also be read in Long Algebraic Notation ("Ng1-f3", "e4xd5", "e2 e4") and in the UCI format ("e7e8q"),
+/ all of them are checked against the legal moves of the position. In UCI, castling can also be written as the king
+/ capturing its own rook ("e1h1"), which is how castling is written with UCI_Chess960 enabled.
+/ Crazyhouse drops are written the same way in all formats, as the piece letter, "@" and the target square ("N@f3"),
+/ the letter may be left out for pawns when reading. */
+
+const PIECE_LETTERS: [&str; 6] = ["R", "N", "B", "Q", "K", ""];
+const DROP_LETTERS: [&str; 6] = ["R", "N", "B", "Q", "K", "P"];
+
+// Formats a legal move in Standard Algebraic Notation
+pub fn move_to_san(pos: &mut Position, from: u8, to: u8) -> String {
+    let mut san = if let Some(piece) = DropMove::piece(from) {
+        drop_to_string(piece, to)
+    } else if let Some(right) = pos.castling_right_of(from, to) {
+        if Castling::is_king_side(right) { "O-O".to_string() } else { "O-O-O".to_string() }
+    } else {
+        let piece = pos.piece_type_at(from).expect("move_to_san called on empty square");
+        let is_capture = pos.is_capture(&to) || piece == Piece::PAWN && pos.en_passant_square == Some(to);
+        let mut san = PIECE_LETTERS[piece as usize].to_string();
+        if piece == Piece::PAWN {
+            if is_capture {
+                san.push(file_char(from));
+            }
+        } else {
+            san.push_str(&disambiguation(pos, piece, from, to));
+        }
+        if is_capture {
+            san.push('x');
+        }
+        san.push(file_char(to));
+        san.push(rank_char(to));
+        if pos.is_promotion(&from, &to) {
+            san.push_str("=Q");
+        }
+        san
+    };
+
+    let mut new_pos = pos.clone();
+    game::apply_move(&mut new_pos, from, to);
+    if new_pos.check {
+        san.push(if game::is_in_checkmate(&mut new_pos) { '#' } else { '+' });
+    }
+    san
+}
+
+// Returns the file, rank or square needed to tell the move apart from moves of other pieces of the same type
+fn disambiguation(pos: &mut Position, piece: u8, from: u8, to: u8) -> String {
+    let others: Vec<u8> = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos)
+        .into_iter()
+        .filter(|&(other_from, other_to)| other_to == to && other_from != from && pos.piece_type_at(other_from) == Some(piece))
+        .map(|(other_from, _)| other_from)
+        .collect();
+
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|&other| other % 8 != from % 8) {
+        file_char(from).to_string()
+    } else if others.iter().all(|&other| other / 8 != from / 8) {
+        rank_char(from).to_string()
+    } else {
+        format!("{}{}", file_char(from), rank_char(from))
+    }
+}
+
+/* Formats a move in the UCI format, promotions get the letter of the piece appended. In Chess960 games, castling is
+/ written as the king capturing its own rook. */
+pub fn move_to_uci(pos: &Position, from: u8, to: u8) -> String {
+    if let Some(piece) = DropMove::piece(from) {
+        return drop_to_string(piece, to);
+    }
+    let to = match pos.castling_right_of(from, to) {
+        Some(right) if pos.state.chess960 => pos.castling_squares(right).rook_from,
+        _ => to,
+    };
+    let mut uci = format!("{}{}{}{}", file_char(from), rank_char(from), file_char(to), rank_char(to));
+    if pos.piece_type_at(from) == Some(Piece::PAWN) && pos.is_promotion(&from, &to) {
+        uci.push('q');
+    }
+    uci
+}
+
+// Finds the legal move described in UCI, LAN or SAN, in this order
+pub fn parse_move(pos: &mut Position, text: &str) -> Result<(u8, u8), String> {
+    let text = text.trim();
+    parse_uci(pos, text)
+        .or_else(|| parse_lan(pos, text))
+        .or_else(|| parse_san(pos, text))
+        .ok_or(format!("Not a legal move: {}", text))
+}
+
+// Finds the legal move described by a move in the UCI format like "g1f3" or "e7e8q"
+pub fn parse_uci(pos: &mut Position, uci: &str) -> Option<(u8, u8)> {
+    if uci.contains('@') {
+        return parse_drop(pos, uci);
+    }
+    let chars: Vec<char> = uci.chars().collect();
+    if chars.len() != 4 && chars.len() != 5 {
+        return None;
+    }
+    let from = square_from_chars(chars[0], chars[1])?;
+    let to = square_from_chars(chars[2], chars[3])?;
+    // Pawns always promote to queens, a missing promotion piece is accepted as well
+    if chars.len() == 5 && (chars[4] != 'q' || pos.piece_type_at(from) != Some(Piece::PAWN)) {
+        return None;
+    }
+    if let Some(right) = pos.castling_right_by_rook(from, to) {
+        let (from, to) = pos.castling_move(right);
+        return find_legal_move(pos, from, to);
+    }
+    find_legal_move(pos, from, to)
+}
+
+// Finds the legal move described by a move in Long Algebraic Notation like "Ng1-f3", "e4xd5", "e7-e8=Q" or "e2 e4"
+pub fn parse_lan(pos: &mut Position, lan: &str) -> Option<(u8, u8)> {
+    let lan = lan.trim_end_matches(['+', '#', '!', '?']);
+    let lan = match lan.strip_suffix("=Q") {
+        Some(lan) => lan,
+        None if lan.contains('=') => return None,
+        None => lan,
+    };
+
+    let mut chars: Vec<char> = lan.chars().collect();
+    let piece = match chars.first()? {
+        'N' => Some(Piece::KNIGHT),
+        'B' => Some(Piece::BISHOP),
+        'R' => Some(Piece::ROOK),
+        'Q' => Some(Piece::QUEEN),
+        'K' => Some(Piece::KING),
+        'P' => Some(Piece::PAWN),
+        _ => None,
+    };
+    if piece.is_some() {
+        chars.remove(0);
+    }
+    if chars.len() != 5 || !matches!(chars[2], '-' | 'x' | ' ') {
+        return None;
+    }
+    let from = square_from_chars(chars[0], chars[1])?;
+    let to = square_from_chars(chars[3], chars[4])?;
+    if piece.is_some() && pos.piece_type_at(from) != piece {
+        return None;
+    }
+    find_legal_move(pos, from, to)
+}
+
+fn find_legal_move(pos: &mut Position, from: u8, to: u8) -> Option<(u8, u8)> {
+    movegen::get_all_legal_moves_for_color(pos.state.active_player, pos)
+        .into_iter()
+        .find(|&legal_move| legal_move == (from, to))
+}
+
+/* Finds the legal move described by a move in Standard Algebraic Notation, for example "Nbd2", "exd5", "O-O" or
+/ "e8=Q+". Returns None if the move isn't legal or if it's an underpromotion, which the engine can't play. */
+pub fn parse_san(pos: &mut Position, san: &str) -> Option<(u8, u8)> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    if san.contains('@') {
+        return parse_drop(pos, san);
+    }
+    let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+    let is_king_side = match san {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None,
+    };
+    if let Some(is_king_side) = is_king_side {
+        return legal_moves.into_iter().find(|&(from, to)| {
+            pos.castling_right_of(from, to).is_some_and(|right| Castling::is_king_side(right) == is_king_side)
+        });
+    }
+
+    let (san, promotion) = match san.split_once('=') {
+        Some((san, promotion)) => (san, Some(promotion)),
+        None => (san, None),
+    };
+    if promotion.is_some_and(|promotion| promotion != "Q") {
+        return None;
+    }
+
+    let mut chars: Vec<char> = san.chars().filter(|&c| c != 'x' && c != '-').collect();
+    let piece = match chars.first()? {
+        'N' => Piece::KNIGHT,
+        'B' => Piece::BISHOP,
+        'R' => Piece::ROOK,
+        'Q' => Piece::QUEEN,
+        'K' => Piece::KING,
+        _ => Piece::PAWN,
+    };
+    if piece != Piece::PAWN {
+        chars.remove(0);
+    }
+    if chars.len() < 2 {
+        return None;
+    }
+    let target = square_from_chars(chars[chars.len() - 2], chars[chars.len() - 1])?;
+    let disambiguation = &chars[..chars.len() - 2];
+
+    let mut candidates = legal_moves.into_iter().filter(|&(from, to)| {
+        to == target
+            && pos.piece_type_at(from) == Some(piece)
+            && disambiguation.iter().all(|&c| match c {
+                'a'..='h' => from % 8 == c as u8 - b'a',
+                '1'..='8' => from / 8 == c as u8 - b'1',
+                _ => false,
+            })
+    });
+    let found = candidates.next()?;
+    // An ambiguous move doesn't describe a single legal move
+    if candidates.next().is_some() {
+        return None;
+    }
+    Some(found)
+}
+
+fn drop_to_string(piece: u8, to: u8) -> String {
+    format!("{}@{}{}", DROP_LETTERS[piece as usize], file_char(to), rank_char(to))
+}
+
+// Finds the legal drop described like "N@f3", "P@e4" or "@e4"
+fn parse_drop(pos: &mut Position, text: &str) -> Option<(u8, u8)> {
+    let (letter, target) = text.split_once('@')?;
+    let piece = match letter {
+        "" | "P" => Piece::PAWN,
+        letter => DROP_LETTERS.iter().position(|&drop_letter| drop_letter == letter)? as u8,
+    };
+    let mut target = target.chars();
+    let to = square_from_chars(target.next()?, target.next()?)?;
+    if target.next().is_some() {
+        return None;
+    }
+    find_legal_move(pos, DropMove::origin(piece), to)
+}
+
+fn file_char(square: u8) -> char {
+    (b'a' + square % 8) as char
+}
+
+fn rank_char(square: u8) -> char {
+    (b'1' + square / 8) as char
+}
+
+fn square_from_chars(file: char, rank: char) -> Option<u8> {
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some((rank as u8 - b'1') * 8 + file as u8 - b'a')
+}
@@ -0,0 +1,96 @@
+/* Statistics of engine matches: the Elo difference estimated from the score of a match and the sequential probability
+/ ratio test (SPRT), which stops a match as soon as it is clear enough whether a change gains at least elo1 (H1) or at
+/ most elo0 (H0) Elo. The log-likelihood ratio uses the normal approximation of the trinomial distribution of game
+/ results, like the testing frameworks of most engines. alpha and beta are the accepted rates of false positives and
+/ false negatives. */
+
+// Number of standard errors of the 95% confidence interval of the Elo difference
+const CONFIDENCE_95: f64 = 1.959964;
+
+// Wins, draws and losses of the first engine of a match
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // Share of the points, a draw is half a point
+    pub fn ratio(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    // Variance of the points of a single game
+    fn variance(&self) -> f64 {
+        let games = self.games().max(1) as f64;
+        let ratio = self.ratio();
+        (self.wins as f64 * (1.0 - ratio).powi(2) + self.draws as f64 * (0.5 - ratio).powi(2) + self.losses as f64 * ratio.powi(2)) / games
+    }
+
+    /* Estimated Elo difference and the margin of its 95% confidence interval. Scores of 0 or 100% have no finite
+    / estimate and return infinities. */
+    pub fn elo(&self) -> (f64, f64) {
+        let ratio = self.ratio();
+        let error = CONFIDENCE_95 * (self.variance() / self.games().max(1) as f64).sqrt();
+        let upper = ratio_to_elo((ratio + error).min(1.0));
+        let lower = ratio_to_elo((ratio - error).max(0.0));
+        (ratio_to_elo(ratio), (upper - lower) / 2.0)
+    }
+}
+
+pub fn ratio_to_elo(ratio: f64) -> f64 {
+    400.0 * (ratio / (1.0 - ratio)).log10()
+}
+
+pub fn elo_to_ratio(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SprtResult {
+    AcceptH0,
+    AcceptH1,
+    Continue,
+}
+
+impl Sprt {
+    // The bounds of the log-likelihood ratio, H0 is accepted below the lower and H1 above the upper bound
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    // Log-likelihood ratio of H1 against H0 after the score, 0 while the variance is unknown
+    pub fn llr(&self, score: &Score) -> f64 {
+        let variance = score.variance();
+        if score.games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (ratio0, ratio1) = (elo_to_ratio(self.elo0), elo_to_ratio(self.elo1));
+        score.games() as f64 * (ratio1 - ratio0) * (2.0 * score.ratio() - ratio0 - ratio1) / (2.0 * variance)
+    }
+
+    pub fn result(&self, score: &Score) -> SprtResult {
+        let llr = self.llr(score);
+        let (lower, upper) = self.bounds();
+        if llr <= lower {
+            SprtResult::AcceptH0
+        } else if llr >= upper {
+            SprtResult::AcceptH1
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
@@ -0,0 +1,1160 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/* Ported from the Syzygy tablebase code of Stockfish (src/syzygy/tbprobe.cpp),
+/ Copyright (C) The Stockfish developers, see the AUTHORS file of https://github.com/official-stockfish/Stockfish
+/ This file is licensed under the GNU General Public License, version 3 or any later version,
+/ see LICENSES/GPL-3.0-or-later.txt and the License section of the README. */
+
+use std::{
+    cmp,
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    sync::{
+        Arc,
+        OnceLock,
+    },
+};
+use lazy_static::lazy_static;
+use types::{
+    position::Position,
+    bitboard::BitBoard,
+    variant::Variant,
+    Castling,
+};
+use crate::{
+    evaluation,
+    game,
+    movegen,
+};
+
+/* Probing code for Syzygy endgame tablebases. WDL tables (.rtbw) store whether a position is won, drawn or lost,
+/ DTZ tables (.rtbz) store the distance to the next capture or pawn move (zeroing move) that keeps the result.
+/ The file format and the probing logic follow the tablebase code of Stockfish, see the license notice above.
+/ Tables are found by scanning the directories of the SyzygyPath for file names like KRPvKR.
+/ The contents of a table are only read from disk the first time it is probed. */
+
+// Score of a tablebase win, above all regular evaluations but below mate scores
+pub const TB_WIN: i32 = 20000;
+
+// WDL results from the view of the side to move. Cursed wins and blessed losses are draws by the 50 move rule
+pub const WDL_LOSS: i32 = -2;
+pub const WDL_BLESSED_LOSS: i32 = -1;
+pub const WDL_DRAW: i32 = 0;
+pub const WDL_CURSED_WIN: i32 = 1;
+pub const WDL_WIN: i32 = 2;
+
+// Most pieces on the board of any table
+pub const TB_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// Flags of the table headers
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+// Flags of the PairsData records
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// Piece codes used by the tables (pawn 1 to king 6, +8 for black), indexed by the piece indices of this engine
+const TB_PIECE_CODES: [u8; 6] = [4, 2, 3, 5, 6, 1];
+
+lazy_static! {
+    static ref ENCODING: Encoding = Encoding::new();
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+enum TableResult {
+    Value(i32),
+    ChangeStm,
+}
+
+/* The tables found in the directories of a SyzygyPath. They can be shared by several engines, each engine decides
+/ with its own SyzygyProbeDepth and SyzygyProbeLimit when it probes them. */
+#[derive(Default)]
+pub struct Tablebases {
+    // Tables by the material keys of both colors
+    tables: HashMap<u64, Arc<TableEntry>>,
+    count: usize,
+    max_pieces: usize,
+}
+
+impl Tablebases {
+    /* Finds the tables of a SyzygyPath: a list of directories separated by ':' (';' on Windows).
+    / An empty path or "<empty>" gives no tables. The headers of the tables are checked right away, a table that
+    / isn't a Syzygy table is an error. A truncated table is only noticed when it is first probed and then ignored. */
+    pub fn open(path: &str) -> io::Result<Tablebases> {
+        let mut tablebases = Tablebases::default();
+        if path.is_empty() || path == "<empty>" {
+            return Ok(tablebases);
+        }
+
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        for directory in path.split(separator) {
+            let Ok(entries) = fs::read_dir(directory) else {
+                continue;
+            };
+            for file in entries.flatten() {
+                let file_path = file.path();
+                if file_path.extension().and_then(|e| e.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                let Some(name) = file_path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                if let Some(info) = TableInfo::from_name(name, directory.into()) {
+                    if tablebases.tables.contains_key(&info.key) {
+                        continue;
+                    }
+                    check_header(&info, TableKind::Wdl)?;
+                    check_header(&info, TableKind::Dtz)?;
+                    tablebases.max_pieces = cmp::max(tablebases.max_pieces, info.piece_count);
+                    let entry = Arc::new(TableEntry { info, wdl: OnceLock::new(), dtz: OnceLock::new() });
+                    tablebases.tables.insert(entry.info.key, entry.clone());
+                    tablebases.tables.insert(entry.info.key2, entry);
+                    tablebases.count += 1;
+                }
+            }
+        }
+        Ok(tablebases)
+    }
+
+    // Number of WDL tables found
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // The largest number of pieces on the board of any table
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // The largest number of pieces on the board that is probed with the SyzygyProbeLimit
+    pub fn cardinality(&self, probe_limit: usize) -> usize {
+        cmp::min(self.max_pieces, probe_limit)
+    }
+
+    /* Returns the WDL result of the position from the view of the side to move or None if a required table is missing.
+    / Tables only store positions of standard chess without castling rights. */
+    pub fn probe_wdl(&self, pos: &mut Position) -> Option<i32> {
+        if pos.state.castling_rights.0 != Castling::NO_CASTLING || pos.state.variant != Variant::Standard {
+            return None;
+        }
+        self.search(pos, false).map(|(wdl, _)| wdl)
+    }
+
+    /* Returns the distance to zero in plies: positive if the side to move wins, negative if it loses and 0 for draws.
+    / A value of 1 or -1 means the next move is zeroing, values above 100 in absolute terms are cursed wins and
+    / blessed losses. */
+    pub fn probe_dtz(&self, pos: &mut Position) -> Option<i32> {
+        if pos.state.castling_rights.0 != Castling::NO_CASTLING || pos.state.variant != Variant::Standard {
+            return None;
+        }
+        self.dtz(pos)
+    }
+
+    /* Probes the tablebases during search. Positions right after a capture or pawn move are probed if they have fewer
+    / pieces than the cardinality, or exactly as many and at least probe_depth remaining depth. Returns the score for the
+    / side to move. */
+    pub fn probe_search(&self, pos: &mut Position, depth: u8, probe_depth: u8, probe_limit: usize) -> Option<i32> {
+        let piece_count = pos.all_pieces().count_ones() as usize;
+        let cardinality = self.cardinality(probe_limit);
+        if piece_count > cardinality
+            || piece_count == cardinality && depth < probe_depth
+            || pos.state.half_move_counter != 0 {
+            return None;
+        }
+
+        let wdl = self.probe_wdl(pos)?;
+        Some(match wdl {
+            WDL_WIN => TB_WIN,
+            WDL_LOSS => -TB_WIN,
+            _ => wdl,
+        })
+    }
+
+    /* Filters the root moves down to the moves that keep the tablebase result. When winning, only the moves with the
+    / shortest distance to zero are kept, so the engine makes progress even if the search doesn't see the win.
+    / When losing, the moves that hold out the longest are kept. Falls back to the WDL tables if a DTZ table is missing.
+    / Returns None if the position can't be probed. */
+    pub fn filter_root_moves(&self, pos: &mut Position, moves: &[(u8, u8)], probe_limit: usize) -> Option<Vec<(u8, u8)>> {
+        if pos.all_pieces().count_ones() as usize > self.cardinality(probe_limit) || moves.is_empty() {
+            return None;
+        }
+        let ranks = self.rank_root_moves_dtz(pos, moves).or_else(|| self.rank_root_moves_wdl(pos, moves))?;
+
+        let best_rank = *ranks.iter().max()?;
+        Some(moves.iter().zip(ranks).filter(|&(_, rank)| rank == best_rank).map(|(&m, _)| m).collect())
+    }
+
+    fn rank_root_moves_dtz(&self, pos: &mut Position, moves: &[(u8, u8)]) -> Option<Vec<i32>> {
+        let mut ranks = Vec::with_capacity(moves.len());
+        for &(from, to) in moves {
+            let mut new_pos = pos.clone();
+            game::apply_move(&mut new_pos, from, to);
+
+            // Zeroing moves have a distance to zero of one ply, otherwise count the ply of the move itself
+            let mut dtz = if new_pos.state.half_move_counter == 0 {
+                dtz_before_zeroing(-self.probe_wdl(&mut new_pos)?)
+            } else {
+                let dtz = -self.probe_dtz(&mut new_pos)?;
+                dtz + dtz.signum()
+            };
+
+            // Make sure that a mating move is assigned a distance of 1
+            if dtz == 2 && game::is_in_checkmate(&mut new_pos) {
+                dtz = 1;
+            }
+
+            ranks.push(match dtz.cmp(&0) {
+                cmp::Ordering::Greater => 1000 - dtz,
+                cmp::Ordering::Less => -1000 - dtz,
+                cmp::Ordering::Equal => 0,
+            });
+        }
+        Some(ranks)
+    }
+
+    fn rank_root_moves_wdl(&self, pos: &mut Position, moves: &[(u8, u8)]) -> Option<Vec<i32>> {
+        let mut ranks = Vec::with_capacity(moves.len());
+        for &(from, to) in moves {
+            let mut new_pos = pos.clone();
+            game::apply_move(&mut new_pos, from, to);
+            ranks.push(-self.probe_wdl(&mut new_pos)?);
+        }
+        Some(ranks)
+    }
+
+    /* Tables treat positions where the side to move has a winning capture as "don't care" and may store any value
+    / that compresses well, and positions with a drawing capture may be stored as losses. So all captures have to be
+    / searched as well and the best result of the captures and the stored value is the real result.
+    / With check_zeroing_moves, pawn moves are searched as well, which is needed before probing DTZ tables.
+    / Returns the result and whether the best move is a zeroing move. */
+    fn search(&self, pos: &mut Position, check_zeroing_moves: bool) -> Option<(i32, bool)> {
+        let legal_moves = movegen::get_all_legal_moves_for_color(pos.state.active_player, pos);
+        if legal_moves.is_empty() {
+            return Some((if pos.check { WDL_LOSS } else { WDL_DRAW }, true));
+        }
+
+        let mut best_value = WDL_LOSS;
+        let mut move_count = 0;
+        for &(from, to) in legal_moves.iter() {
+            let is_pawn_move = pos.piece_bitboards[5].contains(from);
+            if !is_capture(pos, from, to) && (!check_zeroing_moves || !is_pawn_move) {
+                continue;
+            }
+            move_count += 1;
+
+            let mut new_pos = pos.clone();
+            game::apply_move(&mut new_pos, from, to);
+            let value = -self.search(&mut new_pos, false)?.0;
+
+            if value > best_value {
+                best_value = value;
+                if value >= WDL_WIN {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // If all legal moves were searched, the stored value isn't needed and might not even be valid
+        let no_more_moves = move_count == legal_moves.len();
+        let value = if no_more_moves {
+            best_value
+        } else {
+            match self.probe_table(pos, TableKind::Wdl, WDL_DRAW)? {
+                TableResult::Value(value) => value,
+                TableResult::ChangeStm => return None,
+            }
+        };
+
+        if move_count > 0 && best_value >= value {
+            return Some((best_value, best_value > WDL_DRAW || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    fn dtz(&self, pos: &mut Position) -> Option<i32> {
+        let (wdl, zeroing) = self.search(pos, true)?;
+        if wdl == WDL_DRAW {
+            return Some(0);
+        }
+        // The best move is zeroing, the stored value can't be trusted
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        match self.probe_table(pos, TableKind::Dtz, wdl)? {
+            TableResult::Value(dtz) => {
+                let cursed = wdl == WDL_CURSED_WIN || wdl == WDL_BLESSED_LOSS;
+                Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum())
+            },
+            // The table only stores the other side to move, so find the best distance with a one ply search
+            TableResult::ChangeStm => {
+                let mut min_dtz = 0xFFFF;
+                for (from, to) in movegen::get_all_legal_moves_for_color(pos.state.active_player, pos) {
+                    let zeroing = is_capture(pos, from, to) || pos.piece_bitboards[5].contains(from);
+                    let mut new_pos = pos.clone();
+                    game::apply_move(&mut new_pos, from, to);
+
+                    // For zeroing moves we want the distance before the move, the search only gives us the sign
+                    let mut dtz = if zeroing {
+                        -dtz_before_zeroing(self.search(&mut new_pos, false)?.0)
+                    } else {
+                        -self.dtz(&mut new_pos)?
+                    };
+
+                    if dtz == 1 && game::is_in_checkmate(&mut new_pos) {
+                        min_dtz = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                        min_dtz = dtz;
+                    }
+                }
+                Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+            },
+        }
+    }
+
+    fn probe_table(&self, pos: &Position, kind: TableKind, wdl: i32) -> Option<TableResult> {
+        // King against king
+        if pos.all_pieces().count_ones() == 2 {
+            return Some(TableResult::Value(WDL_DRAW));
+        }
+
+        let key = evaluation::material_key(pos);
+        let entry = self.tables.get(&key)?;
+        let table = entry.table(kind)?;
+        Some(table.probe(&entry.info, pos, key, wdl))
+    }
+}
+
+// Captures including en passant
+fn is_capture(pos: &Position, from: u8, to: u8) -> bool {
+    pos.is_capture(&to) || pos.piece_bitboards[5].contains(from) && pos.en_passant_square == Some(to)
+}
+
+// The distance to zero of the move before a zeroing move, depending on the result after it
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        WDL_WIN => 1,
+        WDL_CURSED_WIN => 101,
+        WDL_BLESSED_LOSS => -101,
+        WDL_LOSS => -1,
+        _ => 0,
+    }
+}
+
+// Squares on the a1-h8 diagonal return 0, squares below it a negative number
+fn off_a1h8(square: u8) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+        None => 0,
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        None => 0,
+    }
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> u32 {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        None => 0,
+    }
+}
+
+/* Lookup tables for the encoding of positions into table indices. Pieces are mirrored so the leading piece is in
+/ the a1-d1-d4 triangle, the kings are encoded together and pawns are encoded by file. */
+struct Encoding {
+    map_pawns: [usize; 64],
+    map_b1h1h7: [u64; 64],
+    map_a1d1d4: [u64; 64],
+    map_kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; TB_PIECES],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+impl Encoding {
+    fn new() -> Self {
+        let mut encoding = Self {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; TB_PIECES],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        // Squares below the a1-h8 diagonal are encoded as 0 to 27
+        let mut code = 0;
+        for square in 0..64 {
+            if off_a1h8(square) < 0 {
+                encoding.map_b1h1h7[square as usize] = code;
+                code += 1;
+            }
+        }
+
+        // Squares of the a1-d1-d4 triangle are encoded as 0 to 9, with the diagonal squares last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for square in 0..28 {
+            if off_a1h8(square) < 0 && square % 8 <= 3 {
+                encoding.map_a1d1d4[square as usize] = code;
+                code += 1;
+            } else if off_a1h8(square) == 0 && square % 8 <= 3 {
+                diagonal.push(square);
+            }
+        }
+        for square in diagonal {
+            encoding.map_a1d1d4[square as usize] = code;
+            code += 1;
+        }
+
+        /* All 462 legal placements of two kings with the first one in the a1-d1-d4 triangle. If the first king is on
+        / the diagonal, the second one can't be above it. Placements with both kings on the diagonal are encoded last. */
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for first in 0..28u8 {
+                if encoding.map_a1d1d4[first as usize] != idx || (idx == 0 && first != 1) {
+                    continue;
+                }
+                for second in 0..64u8 {
+                    let distance = cmp::max((first % 8).abs_diff(second % 8), (first / 8).abs_diff(second / 8));
+                    if distance <= 1 || off_a1h8(first) == 0 && off_a1h8(second) > 0 {
+                        continue;
+                    }
+                    if off_a1h8(first) == 0 && off_a1h8(second) == 0 {
+                        both_on_diagonal.push((idx, second));
+                    } else {
+                        encoding.map_kk[idx as usize][second as usize] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            encoding.map_kk[idx as usize][second as usize] = code;
+            code += 1;
+        }
+
+        // Binomial coefficients: the number of ways to choose k elements from a set of n elements
+        encoding.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..cmp::min(TB_PIECES, n + 1) {
+                encoding.binomial[k][n] = if k > 0 { encoding.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { encoding.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        /* Pawns on a2-h7 are encoded as 0 to 47, so that the pawn with the highest value is the leading pawn:
+        / the one nearest to the edge and, on the same file, the one with the lowest rank. */
+        for lead_pawns_count in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns_count == 1 {
+                        let available_squares = 47 - 2 * (file * 6 + rank - 1);
+                        encoding.map_pawns[square] = available_squares;
+                        encoding.map_pawns[square ^ 7] = available_squares - 1;
+                    }
+                    encoding.lead_pawn_idx[lead_pawns_count][square] = idx;
+                    idx += encoding.binomial[lead_pawns_count - 1][encoding.map_pawns[square]];
+                }
+                encoding.lead_pawns_size[lead_pawns_count][file] = idx;
+            }
+        }
+
+        encoding
+    }
+}
+
+// Material information of a table, known from its file name
+struct TableInfo {
+    name: String,
+    directory: PathBuf,
+    key: u64,
+    key2: u64,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // Pawns of the leading color and of the other color
+    pawn_count: [u8; 2],
+}
+
+impl TableInfo {
+    fn path(&self, kind: TableKind) -> PathBuf {
+        let extension = match kind {
+            TableKind::Wdl => "rtbw",
+            TableKind::Dtz => "rtbz",
+        };
+        self.directory.join(format!("{}.{}", self.name, extension))
+    }
+
+    // Parses table names like KRPvKR, the first side is white in key and black in key2
+    fn from_name(name: &str, directory: PathBuf) -> Option<Self> {
+        let (first, second) = name.split_once('v')?;
+        if !first.starts_with('K') || !second.starts_with('K') {
+            return None;
+        }
+
+        let mut counts = [[0u8; 6]; 2];
+        for (side, pieces) in [first, second].iter().enumerate() {
+            for piece in pieces.chars() {
+                let index = match piece {
+                    'R' => 0,
+                    'N' => 1,
+                    'B' => 2,
+                    'Q' => 3,
+                    'K' => 4,
+                    'P' => 5,
+                    _ => return None,
+                };
+                counts[side][index] += 1;
+            }
+            if counts[side][4] != 1 {
+                return None;
+            }
+        }
+
+        let piece_count = first.len() + second.len();
+        if piece_count > TB_PIECES {
+            return None;
+        }
+
+        let key_for = |white: usize, black: usize| {
+            let mut key = 0;
+            for (color, side) in [white, black].iter().enumerate() {
+                for piece in [0, 1, 2, 3, 5] {
+                    key |= (counts[*side][piece] as u64) << (4 * (color * 6 + piece));
+                }
+            }
+            key
+        };
+
+        let has_unique_pieces = counts.iter().any(|side| [0, 1, 2, 3, 5].iter().any(|&piece| side[piece] == 1));
+
+        // The leading color is the one with fewer pawns, which compresses better
+        let (white_pawns, black_pawns) = (counts[0][5], counts[1][5]);
+        let white_leads = black_pawns == 0 || white_pawns > 0 && black_pawns >= white_pawns;
+        let pawn_count = if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] };
+
+        Some(Self {
+            name: name.to_string(),
+            directory,
+            key: key_for(0, 1),
+            key2: key_for(1, 0),
+            piece_count,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count,
+        })
+    }
+}
+
+/* Reads the header of a table file: the magic number of its kind and the flags for pawns and for tables split by the
+/ side to move, which have to match the name of the table. A missing DTZ table is fine, the WDL table is probed alone. */
+fn check_header(info: &TableInfo, kind: TableKind) -> io::Result<()> {
+    let mut header = Vec::new();
+    match fs::File::open(info.path(kind)) {
+        Ok(file) => file.take(5).read_to_end(&mut header)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && kind == TableKind::Dtz => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !valid_header(info, kind, &header) {
+        let message = format!("Corrupted Syzygy table {}", info.path(kind).display());
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    Ok(())
+}
+
+fn valid_header(info: &TableInfo, kind: TableKind, data: &[u8]) -> bool {
+    let magic = match kind {
+        TableKind::Wdl => WDL_MAGIC,
+        TableKind::Dtz => DTZ_MAGIC,
+    };
+    let Some(&header) = data.get(4) else {
+        return false;
+    };
+    data[0..4] == magic && (header & HAS_PAWNS != 0) == info.has_pawns && (header & SPLIT != 0) == (info.key != info.key2)
+}
+
+// A WDL and DTZ table pair. The tables are loaded on first access, None if the file is missing or invalid
+struct TableEntry {
+    info: TableInfo,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl TableEntry {
+    fn table(&self, kind: TableKind) -> Option<&Table> {
+        let cell = match kind {
+            TableKind::Wdl => &self.wdl,
+            TableKind::Dtz => &self.dtz,
+        };
+        cell.get_or_init(|| {
+            let data = fs::read(self.info.path(kind)).ok()?;
+            Table::new(&self.info, kind, data)
+        }).as_ref()
+    }
+}
+
+/* Low level indexing information for one subtable of a file. Pawnless tables have one subtable per side to move,
+/ tables with pawns have one for each file of the leading pawn as well. All offsets point into the table data. */
+#[derive(Debug, Default, Clone)]
+struct PairsData {
+    flags: u8,
+    max_sym_len: u8,
+    min_sym_len: u8,
+    num_blocks: u32,
+    block_size: usize,
+    span: usize,
+    // lowest_sym[l] is the symbol of length l with the lowest value
+    lowest_sym: usize,
+    // btree[sym] stores the left and right symbols that expand sym
+    btree: usize,
+    // Number of stored values (minus one) for each block
+    block_length: usize,
+    block_length_size: usize,
+    // Partial indices into block_length
+    sparse_index: usize,
+    sparse_index_size: usize,
+    // Start of the Huffman compressed data
+    data: usize,
+    // base64[l - min_sym_len] is the 64 bit padded lowest symbol of length l
+    base64: Vec<u64>,
+    // Number of values (minus one) represented by a symbol
+    symlen: Vec<u8>,
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    // Offsets into the DTZ value map for wins, losses, cursed wins and blessed losses
+    map_idx: [u16; 4],
+}
+
+struct Table {
+    kind: TableKind,
+    data: Vec<u8>,
+    map: usize,
+    // [side to move][file of the leading pawn]
+    items: [[PairsData; 4]; 2],
+}
+
+impl Table {
+    // Parses the header of a table file, None if the file changed since it was opened or is truncated
+    fn new(info: &TableInfo, kind: TableKind, data: Vec<u8>) -> Option<Self> {
+        if !valid_header(info, kind, &data) {
+            return None;
+        }
+
+        let mut table = Self {
+            kind,
+            data,
+            map: 0,
+            items: Default::default(),
+        };
+        let data = &table.data;
+
+        let sides = if kind == TableKind::Wdl && info.key != info.key2 { 2 } else { 1 };
+        let max_file = if info.has_pawns { 3 } else { 0 };
+        let both_have_pawns = info.has_pawns && info.pawn_count[1] > 0;
+        let byte = |offset: usize| data.get(offset).copied().unwrap_or(0);
+
+        let mut offset = 5;
+        for file in 0..=max_file {
+            let order = [
+                [byte(offset) & 0xF, if both_have_pawns { byte(offset + 1) & 0xF } else { 0xF }],
+                [byte(offset) >> 4, if both_have_pawns { byte(offset + 1) >> 4 } else { 0xF }],
+            ];
+            offset += 1 + both_have_pawns as usize;
+
+            for k in 0..info.piece_count {
+                for side in 0..sides {
+                    table.items[side][file].pieces[k] = if side == 1 { byte(offset) >> 4 } else { byte(offset) & 0xF };
+                }
+                offset += 1;
+            }
+            for (side, &side_order) in order.iter().enumerate().take(sides) {
+                set_groups(info, &mut table.items[side][file], side_order, file);
+            }
+        }
+        offset += offset & 1;
+
+        for file in 0..=max_file {
+            for side in 0..sides {
+                offset = set_sizes(&mut table.items[side][file], data, offset);
+            }
+        }
+
+        if kind == TableKind::Dtz {
+            table.map = offset;
+            for file in 0..=max_file {
+                let pairs = &mut table.items[0][file];
+                if pairs.flags & MAPPED == 0 {
+                    continue;
+                }
+                if pairs.flags & WIDE != 0 {
+                    offset += offset & 1;
+                    for i in 0..4 {
+                        pairs.map_idx[i] = ((offset - table.map) / 2 + 1) as u16;
+                        offset += 2 * read_u16(data, offset) as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        pairs.map_idx[i] = (offset - table.map + 1) as u16;
+                        offset += byte(offset) as usize + 1;
+                    }
+                }
+            }
+            offset += offset & 1;
+        }
+
+        for file in 0..=max_file {
+            for side in 0..sides {
+                let pairs = &mut table.items[side][file];
+                pairs.sparse_index = offset;
+                offset += pairs.sparse_index_size * 6;
+            }
+        }
+        for file in 0..=max_file {
+            for side in 0..sides {
+                let pairs = &mut table.items[side][file];
+                pairs.block_length = offset;
+                offset += pairs.block_length_size * 2;
+            }
+        }
+        for file in 0..=max_file {
+            for side in 0..sides {
+                let pairs = &mut table.items[side][file];
+                offset = (offset + 0x3F) & !0x3F;
+                pairs.data = offset;
+                offset += pairs.num_blocks as usize * pairs.block_size;
+                if pairs.num_blocks > 0 && offset > data.len() {
+                    return None;
+                }
+            }
+        }
+
+        Some(table)
+    }
+
+    fn pairs(&self, side_to_move: usize, file: usize) -> &PairsData {
+        let side = if self.kind == TableKind::Wdl { side_to_move } else { 0 };
+        &self.items[side][file]
+    }
+
+    /* Computes the index of the position in the table and decompresses the stored value.
+    / Tables are stored with white as the stronger side, so positions where black is stronger (or symmetric positions
+    / with black to move) are looked up with colors switched and the board flipped. */
+    fn probe(&self, info: &TableInfo, pos: &Position, key: u64, wdl: i32) -> TableResult {
+        let encoding = &*ENCODING;
+        let active_player = pos.state.active_player as usize;
+        let symmetric_black_to_move = info.key == info.key2 && active_player == 1;
+        let flip = symmetric_black_to_move || key != info.key;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let side_to_move = flip as usize ^ active_player;
+
+        let mut squares = [0u8; TB_PIECES];
+        let mut pieces = [0u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = BitBoard::empty();
+        let mut lead_pawns_count = 0;
+        let mut tb_file = 0;
+
+        // Tables with pawns are split by the file of the leading pawn, which is mirrored to the files a to d
+        if info.has_pawns {
+            let lead_color = ((self.items[0][0].pieces[0] ^ flip_color) >> 3) as usize;
+            lead_pawns = pos.piece_bitboards[5] & pos.color_bitboards[lead_color];
+            for square in lead_pawns.squares_from_bb() {
+                squares[size] = square ^ flip_squares;
+                size += 1;
+            }
+            lead_pawns_count = size;
+
+            let lead = (0..lead_pawns_count).max_by_key(|&i| encoding.map_pawns[squares[i] as usize]).unwrap();
+            squares.swap(0, lead);
+            tb_file = cmp::min(squares[0] % 8, 7 - squares[0] % 8) as usize;
+        }
+
+        // DTZ tables only store one side to move
+        if self.kind == TableKind::Dtz {
+            let flags = self.pairs(side_to_move, tb_file).flags;
+            if (flags & STM) as usize != side_to_move && (info.key != info.key2 || info.has_pawns) {
+                return TableResult::ChangeStm;
+            }
+        }
+
+        for square in (pos.all_pieces() ^ lead_pawns).squares_from_bb() {
+            let (piece, color) = pos.piece_at(square).unwrap();
+            squares[size] = square ^ flip_squares;
+            pieces[size] = (TB_PIECE_CODES[piece as usize] + 8 * color as u8) ^ flip_color;
+            size += 1;
+        }
+
+        let pairs = self.pairs(side_to_move, tb_file);
+
+        // Reorder the pieces to the sequence stored in the table
+        for i in lead_pawns_count..size - 1 {
+            for j in i + 1..size {
+                if pairs.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // Mirror the board so the leading piece is on the files a to d
+        if squares[0] % 8 > 3 {
+            for square in squares[..size].iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx;
+        if info.has_pawns {
+            idx = encoding.lead_pawn_idx[lead_pawns_count][squares[0] as usize];
+            squares[1..lead_pawns_count].sort_by_key(|&square| encoding.map_pawns[square as usize]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawns_count).skip(1) {
+                idx += encoding.binomial[i][encoding.map_pawns[square as usize]];
+            }
+        } else {
+            // Without pawns, also mirror the board so the leading piece is below the 5th rank and the a1-h8 diagonal
+            if squares[0] / 8 > 3 {
+                for square in squares[..size].iter_mut() {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..pairs.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for square in squares[i..size].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if info.has_unique_pieces {
+                let (first, second, third) = (squares[0] as u64, squares[1] as u64, squares[2] as u64);
+                let adjust1 = (second > first) as u64;
+                let adjust2 = (third > first) as u64 + (third > second) as u64;
+                let (rank1, rank2, rank3) = (first / 8, second / 8, third / 8);
+
+                idx = if off_a1h8(squares[0]) != 0 {
+                    (encoding.map_a1d1d4[first as usize] * 63 + (second - adjust1)) * 62 + third - adjust2
+                } else if off_a1h8(squares[1]) != 0 {
+                    (6 * 63 + rank1 * 28 + encoding.map_b1h1h7[second as usize]) * 62 + third - adjust2
+                } else if off_a1h8(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank1 * 7 * 28 + (rank2 - adjust1) * 28
+                        + encoding.map_b1h1h7[third as usize]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank1 * 7 * 6 + (rank2 - adjust1) * 6 + (rank3 - adjust2)
+                };
+            } else {
+                idx = encoding.map_kk[encoding.map_a1d1d4[squares[0] as usize] as usize][squares[1] as usize];
+            }
+        }
+
+        // Encode the remaining groups, every square is mapped down by the number of squares taken by earlier groups
+        idx *= pairs.group_idx[0];
+        let mut group_start = pairs.group_len[0];
+        let mut remaining_pawns = info.has_pawns && info.pawn_count[1] > 0;
+        let mut next = 1;
+        while pairs.group_len[next] != 0 {
+            let group_len = pairs.group_len[next];
+            squares[group_start..group_start + group_len].sort();
+
+            let mut n = 0;
+            for i in 0..group_len {
+                let square = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|&&earlier| square > earlier).count();
+                n += encoding.binomial[i + 1][square as usize - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+
+            remaining_pawns = false;
+            idx += n * pairs.group_idx[next];
+            group_start += group_len;
+            next += 1;
+        }
+
+        let value = self.decompress_pairs(pairs, idx);
+        TableResult::Value(self.map_score(tb_file, value, wdl))
+    }
+
+    /* The values are compressed with Recursive Pairing, every symbol stands for a pair of other symbols or a value,
+    / and the symbols are stored with a canonical Huffman code in blocks. A sparse index points to the block that
+    / contains a given index, then the symbols of the block are decoded until the one containing the index is found. */
+    fn decompress_pairs(&self, pairs: &PairsData, idx: u64) -> i32 {
+        if pairs.flags & SINGLE_VALUE != 0 {
+            return pairs.min_sym_len as i32;
+        }
+        let data = &self.data;
+
+        let k = (idx / pairs.span as u64) as usize;
+        let mut block = read_u32(data, pairs.sparse_index + 6 * k) as usize;
+        let mut offset = read_u16(data, pairs.sparse_index + 6 * k + 4) as i64;
+        offset += (idx % pairs.span as u64) as i64 - (pairs.span / 2) as i64;
+
+        let block_length = |block: usize| read_u16(data, pairs.block_length + 2 * block) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        let mut pointer = pairs.data + block * pairs.block_size;
+        let mut buffer = (read_u32_be(data, pointer) as u64) << 32 | read_u32_be(data, pointer + 4) as u64;
+        pointer += 8;
+        let mut buffer_size = 64;
+        let lowest_sym = |len: usize| read_u16(data, pairs.lowest_sym + 2 * len) as usize;
+
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buffer < pairs.base64[len] {
+                len += 1;
+            }
+            sym = ((buffer - pairs.base64[len]) >> (64 - len - pairs.min_sym_len as usize)) as usize;
+            sym += lowest_sym(len);
+
+            if offset < pairs.symlen[sym] as i64 + 1 {
+                break;
+            }
+            offset -= pairs.symlen[sym] as i64 + 1;
+            len += pairs.min_sym_len as usize;
+            buffer <<= len;
+            buffer_size -= len;
+
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (read_u32_be(data, pointer) as u64) << (64 - buffer_size);
+                pointer += 4;
+            }
+        }
+
+        // Expand the symbol into its children until reaching the leaf that holds the value
+        while pairs.symlen[sym] != 0 {
+            let left = self.btree_left(pairs, sym);
+            if offset < pairs.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= pairs.symlen[left] as i64 + 1;
+                sym = self.btree_right(pairs, sym);
+            }
+        }
+        self.btree_left(pairs, sym) as i32
+    }
+
+    // Each btree entry stores two 12 bit symbols in 3 bytes
+    fn btree_left(&self, pairs: &PairsData, sym: usize) -> usize {
+        btree_left(&self.data, pairs.btree, sym)
+    }
+
+    fn btree_right(&self, pairs: &PairsData, sym: usize) -> usize {
+        btree_right(&self.data, pairs.btree, sym)
+    }
+
+    /* WDL values are stored as 0 to 4. DTZ values are sorted by frequency for each WDL result and stored in moves
+    / or plies, so they are mapped back and converted to plies. */
+    fn map_score(&self, file: usize, value: i32, wdl: i32) -> i32 {
+        if self.kind == TableKind::Wdl {
+            return value - 2;
+        }
+
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        let pairs = &self.items[0][file];
+        let mut value = value;
+        if pairs.flags & MAPPED != 0 {
+            let idx = pairs.map_idx[WDL_MAP[(wdl + 2) as usize]] as usize + value as usize;
+            value = if pairs.flags & WIDE != 0 {
+                read_u16(&self.data, self.map + 2 * idx) as i32
+            } else {
+                self.data.get(self.map + idx).copied().unwrap_or(0) as i32
+            };
+        }
+
+        if wdl == WDL_WIN && pairs.flags & WIN_PLIES == 0
+            || wdl == WDL_LOSS && pairs.flags & LOSS_PLIES == 0
+            || wdl == WDL_CURSED_WIN
+            || wdl == WDL_BLESSED_LOSS {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+fn btree_left(data: &[u8], btree: usize, sym: usize) -> usize {
+    let byte = |i: usize| data.get(btree + 3 * sym + i).copied().unwrap_or(0) as usize;
+    ((byte(1) & 0xF) << 8) | byte(0)
+}
+
+fn btree_right(data: &[u8], btree: usize, sym: usize) -> usize {
+    let byte = |i: usize| data.get(btree + 3 * sym + i).copied().unwrap_or(0) as usize;
+    (byte(2) << 4) | (byte(1) >> 4)
+}
+
+/* Pieces are encoded in groups: the leading group is formed by the leading pawns, by three unique pieces or by the
+/ two kings, then every group holds pieces of the same type and color, e.g. KRvKN -> KRK + N.
+/ The order in which the groups are encoded is stored in the table. */
+fn set_groups(info: &TableInfo, pairs: &mut PairsData, order: [u8; 2], file: usize) {
+    let encoding = &*ENCODING;
+    let mut n = 0;
+    let mut first_len: i32 = if info.has_pawns { 0 } else if info.has_unique_pieces { 3 } else { 2 };
+    pairs.group_len[0] = 1;
+    for i in 1..info.piece_count {
+        first_len -= 1;
+        if first_len > 0 || pairs.pieces[i] == pairs.pieces[i - 1] {
+            pairs.group_len[n] += 1;
+        } else {
+            n += 1;
+            pairs.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    pairs.group_len[n] = 0;
+
+    let both_have_pawns = info.has_pawns && info.pawn_count[1] > 0;
+    let mut next = if both_have_pawns { 2 } else { 1 };
+    let mut free_squares = 64 - pairs.group_len[0] - if both_have_pawns { pairs.group_len[1] } else { 0 };
+    let mut idx: u64 = 1;
+
+    let mut k = 0;
+    while next < n || k == order[0] || k == order[1] {
+        if k == order[0] {
+            // Leading pawns or pieces
+            pairs.group_idx[0] = idx;
+            idx *= if info.has_pawns {
+                encoding.lead_pawns_size[pairs.group_len[0]][file]
+            } else if info.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] {
+            // Remaining pawns
+            pairs.group_idx[1] = idx;
+            idx *= encoding.binomial[pairs.group_len[1]][48 - pairs.group_len[0]];
+        } else {
+            // Remaining pieces
+            pairs.group_idx[next] = idx;
+            idx *= encoding.binomial[pairs.group_len[next]][free_squares];
+            free_squares -= pairs.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    pairs.group_idx[n] = idx;
+}
+
+// Reads the Huffman code and block layout of a subtable, returns the offset after it
+fn set_sizes(pairs: &mut PairsData, data: &[u8], mut offset: usize) -> usize {
+    let byte = |offset: usize| data.get(offset).copied().unwrap_or(0);
+
+    pairs.flags = byte(offset);
+    offset += 1;
+
+    // All positions store the same value, which is kept in min_sym_len
+    if pairs.flags & SINGLE_VALUE != 0 {
+        pairs.min_sym_len = byte(offset);
+        return offset + 1;
+    }
+
+    let table_size = pairs.group_idx[pairs.group_len.iter().position(|&len| len == 0).unwrap_or(TB_PIECES)];
+    pairs.block_size = 1 << byte(offset);
+    pairs.span = 1 << byte(offset + 1);
+    pairs.sparse_index_size = table_size.div_ceil(pairs.span as u64) as usize;
+    let padding = byte(offset + 2) as usize;
+    pairs.num_blocks = read_u32(data, offset + 3);
+    // Padded so the sparse index never points out of range
+    pairs.block_length_size = pairs.num_blocks as usize + padding;
+    pairs.max_sym_len = byte(offset + 7);
+    pairs.min_sym_len = byte(offset + 8);
+    offset += 9;
+    pairs.lowest_sym = offset;
+
+    /* The canonical code is ordered so that longer symbols have lower values. base64[] holds the lowest symbol of
+    / each length padded to 64 bits, so the length of a symbol at the start of a 64 bit buffer is the first length
+    / whose base64[] entry is below the buffer. */
+    let lengths = (pairs.max_sym_len as usize + 1).saturating_sub(pairs.min_sym_len as usize);
+    pairs.base64 = vec![0; lengths];
+    for i in (0..lengths.saturating_sub(1)).rev() {
+        let lowest = read_u16(data, offset + 2 * i) as u64;
+        let next_lowest = read_u16(data, offset + 2 * (i + 1)) as u64;
+        pairs.base64[i] = pairs.base64[i + 1].wrapping_add(lowest).wrapping_sub(next_lowest) / 2;
+    }
+    for (i, base) in pairs.base64.iter_mut().enumerate() {
+        *base = base.checked_shl((64 - i - pairs.min_sym_len as usize) as u32).unwrap_or(0);
+    }
+    offset += lengths * 2;
+
+    let symbols = read_u16(data, offset) as usize;
+    offset += 2;
+    pairs.btree = offset;
+    pairs.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            let len = set_symlen(data, pairs.btree, &mut pairs.symlen, sym, &mut visited);
+            pairs.symlen[sym] = len;
+        }
+    }
+
+    offset + symbols * 3 + (symbols & 1)
+}
+
+// Computes the number of values (minus one) a symbol expands into by expanding its children
+fn set_symlen(data: &[u8], btree: usize, symlen: &mut [u8], sym: usize, visited: &mut [bool]) -> u8 {
+    visited[sym] = true;
+    let right = btree_right(data, btree, sym);
+    if right == 0xFFF {
+        return 0;
+    }
+    let left = btree_left(data, btree, sym);
+    if left >= symlen.len() || right >= symlen.len() {
+        return 0;
+    }
+
+    if !visited[left] {
+        symlen[left] = set_symlen(data, btree, symlen, left, visited);
+    }
+    if !visited[right] {
+        symlen[right] = set_symlen(data, btree, symlen, right, visited);
+    }
+    symlen[left].wrapping_add(symlen[right]).wrapping_add(1)
+}
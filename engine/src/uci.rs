@@ -0,0 +1,445 @@
+use std::{
+    io::{self, BufRead},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use types::{
+    position::Position,
+    variant::Variant,
+    Color,
+};
+use crate::{
+    book::Book,
+    chess960,
+    game,
+    negamax::{Engine, EngineOptions, Resources, SearchInfo, SearchLimits, SearchOutcome, SearchStats},
+    san,
+    syzygy::Tablebases,
+    variants,
+};
+
+/* The Universal Chess Interface: commands are read from stdin and answered on stdout, so the engine can be used by
+/ chess GUIs and match runners. Searches run in their own thread while commands are read, which lets "stop" end a
+/ search and "isready" be answered during one. Other commands wait for the running search to finish. */
+
+const ENGINE_NAME: &str = "AmselChess";
+const ENGINE_AUTHOR: &str = "the AmselChess developers";
+
+// Deepest iteration of searches limited by time
+pub const MAX_DEPTH: u8 = 64;
+
+// Move time of "go infinite" and "go" without limits, these searches run until they are stopped
+const INFINITE: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+
+// Number of moves the remaining time is split over if the time control doesn't say
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+// Time in milliseconds kept back for the communication with the GUI
+const MOVE_OVERHEAD: u64 = 50;
+
+// Mates are only known as mates in one, longer mates and tablebase wins are reported as scores up to this
+const MAX_CENTIPAWNS: i32 = 30000;
+
+// Runs a UCI session until "quit" is received or stdin is closed
+pub fn run(options: EngineOptions) {
+    let mut uci = Uci::new(options);
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !uci.execute(&line) {
+            break;
+        }
+    }
+    uci.stop_search();
+}
+
+// A UCI session, the commands are passed to execute() one line at a time
+pub struct Uci {
+    // The engine is moved into the search thread while it searches
+    engine: Option<Engine>,
+    options: EngineOptions,
+    // The opening book and tablebases loaded with BookFile and SyzygyPath
+    resources: Resources,
+    // Variant of new games and positions, see UCI_Variant
+    variant: Variant,
+    // Whether castling moves are sent the Chess960 way, see UCI_Chess960
+    chess960: bool,
+    position: Position,
+    search: Option<JoinHandle<(Engine, SearchOutcome)>>,
+    // Result of the last search that finished
+    outcome: Option<SearchOutcome>,
+    stop: Arc<AtomicBool>,
+    // With "debug on" the statistics of every iteration are sent as info strings
+    debug: bool,
+}
+
+impl Uci {
+    pub fn new(options: EngineOptions) -> Uci {
+        let engine = Engine::new(options);
+        Uci {
+            stop: engine.stop_handle(),
+            engine: Some(engine),
+            options,
+            resources: Resources::default(),
+            variant: Variant::Standard,
+            chess960: false,
+            position: Position::new(),
+            search: None,
+            outcome: None,
+            debug: false,
+        }
+    }
+
+    // Executes a command, returns false if the session should end
+    pub fn execute(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        let (command, args) = line.split_once(char::is_whitespace).map_or((line, ""), |(command, args)| (command, args.trim()));
+        let result = match command {
+            "uci" => {
+                self.identify();
+                Ok(())
+            },
+            "isready" => {
+                println!("readyok");
+                Ok(())
+            },
+            "debug" => match args {
+                "on" | "off" => {
+                    self.debug = args == "on";
+                    Ok(())
+                },
+                _ => Err(format!("debug needs on or off, not {}", args)),
+            },
+            "setoption" => {
+                self.wait_for_search();
+                self.set_option(args)
+            },
+            "ucinewgame" => {
+                self.wait_for_search();
+                self.engine().clear_hash();
+                self.position = variants::start_position(self.variant);
+                self.position.state.chess960 = self.chess960;
+                Ok(())
+            },
+            "position" => {
+                self.wait_for_search();
+                parse_position(args, self.variant, self.chess960).map(|pos| self.position = pos)
+            },
+            "go" => {
+                self.wait_for_search();
+                self.go(args);
+                Ok(())
+            },
+            "stop" => {
+                self.stop_search();
+                Ok(())
+            },
+            // Not part of the protocol, but handy when talking to the engine by hand
+            "d" => {
+                self.position.print_position();
+                println!("FEN: {}", chess960::fen(&self.position));
+                Ok(())
+            },
+            "quit" => return false,
+            "" => Ok(()),
+            _ => Err(format!("Unknown command: {}", command)),
+        };
+        if let Err(e) = result {
+            println!("info string Error: {}", e);
+        }
+        true
+    }
+
+    fn identify(&self) {
+        println!("id name {}", ENGINE_NAME);
+        println!("id author {}", ENGINE_AUTHOR);
+        println!();
+        let threads = self.engine.as_ref().map_or(self.options.threads, Engine::threads);
+        println!("option name Threads type spin default {} min 1 max 512", threads);
+        println!("option name Hash type spin default {} min 1 max 65536", self.options.hash);
+        println!("option name Clear Hash type button");
+        println!("option name Deterministic type check default {}", self.options.deterministic);
+        println!("option name OwnBook type check default false");
+        println!("option name BookFile type string default <empty>");
+        println!("option name BookDepth type spin default {} min 0 max {}", u16::MAX, u16::MAX);
+        println!("option name BestBookMove type check default false");
+        println!("option name SyzygyPath type string default <empty>");
+        println!("option name SyzygyProbeDepth type spin default 1 min 1 max 100");
+        println!("option name SyzygyProbeLimit type spin default 7 min 0 max 7");
+        println!("option name UCI_Chess960 type check default false");
+        let variants: Vec<String> = Variant::ALL.iter().map(|variant| format!("var {}", variant.uci_name())).collect();
+        println!("option name UCI_Variant type combo default {} {}", Variant::Standard.uci_name(), variants.join(" "));
+        println!("uciok");
+    }
+
+    // Sets an option from "name <name> value <value>", option names are case insensitive
+    fn set_option(&mut self, args: &str) -> Result<(), String> {
+        let args = args.strip_prefix("name").ok_or("setoption needs a name")?.trim();
+        let (name, value) = args.split_once(" value ").map_or((args, ""), |(name, value)| (name.trim(), value.trim()));
+        let number = |value: &str| value.parse::<usize>().map_err(|_| format!("Invalid value for {}: {}", name, value));
+        let check = |value: &str| value.parse::<bool>().map_err(|_| format!("Invalid value for {}: {}", name, value));
+        match name.to_ascii_lowercase().as_str() {
+            "threads" => {
+                self.options.threads = number(value)?.max(1);
+                self.new_engine();
+            },
+            "hash" => {
+                self.options.hash = number(value)?.max(1);
+                self.new_engine();
+            },
+            "clear hash" => self.engine().clear_hash(),
+            "deterministic" => {
+                self.options.deterministic = check(value)?;
+                self.new_engine();
+            },
+            "ownbook" => {
+                self.options.own_book = check(value)?;
+                self.new_engine();
+            },
+            "bookfile" if value.is_empty() || value == "<empty>" => {
+                self.resources.book = None;
+                self.new_engine();
+            },
+            "bookfile" => {
+                let book = Book::open(value).map_err(|e| format!("Could not load opening book {}: {}", value, e))?;
+                println!("info string Loaded opening book with {} entries", book.len());
+                self.resources.book = Some(Arc::new(book));
+                self.new_engine();
+            },
+            "bookdepth" => {
+                self.options.book_depth = number(value)?.min(u16::MAX as usize) as u16;
+                self.new_engine();
+            },
+            "bestbookmove" => {
+                self.options.best_book_move = check(value)?;
+                self.new_engine();
+            },
+            "syzygypath" => {
+                let tablebases = Tablebases::open(value).map_err(|e| format!("Could not load Syzygy tables {}: {}", value, e))?;
+                let pieces = tablebases.cardinality(self.options.syzygy_probe_limit);
+                println!("info string Found {} Syzygy tables with up to {} pieces", tablebases.len(), pieces);
+                self.resources.tablebases = Some(Arc::new(tablebases)).filter(|tablebases| !tablebases.is_empty());
+                self.new_engine();
+            },
+            "syzygyprobedepth" => {
+                self.options.syzygy_probe_depth = number(value)?.min(u8::MAX as usize) as u8;
+                self.new_engine();
+            },
+            "syzygyprobelimit" => {
+                self.options.syzygy_probe_limit = number(value)?;
+                self.new_engine();
+            },
+            "uci_chess960" => {
+                self.chess960 = check(value)?;
+                self.position.state.chess960 = self.chess960;
+            },
+            "uci_variant" => self.variant = value.parse().map_err(|e| format!("{}", e))?,
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn engine(&mut self) -> &mut Engine {
+        self.engine.as_mut().expect("The engine is only taken away during a search")
+    }
+
+    // Replaces the engine after one of its options, the book or the tablebases changed
+    fn new_engine(&mut self) {
+        let mut engine = Engine::new(self.options);
+        engine.set_resources(self.resources.clone());
+        self.stop = engine.stop_handle();
+        self.engine = Some(engine);
+    }
+
+    // Plays from the opening book or starts a search, the best move is printed when it ends
+    fn go(&mut self, args: &str) {
+        let mut pos = self.position.clone();
+        if let Some((from, to)) = self.engine().book_move(&mut pos) {
+            println!("bestmove {}", san::move_to_uci(&pos, from, to));
+            return;
+        }
+
+        let limits = parse_go(args, pos.state.active_player);
+        let mut engine = self.engine.take().expect("The engine is only taken away during a search");
+        let info_pos = pos.clone();
+        let debug = self.debug;
+        engine.set_info_callback(move |info| {
+            if let SearchInfo::Iteration(outcome) = info {
+                print_info(&info_pos, outcome);
+                if debug {
+                    print_stats(&outcome.stats);
+                }
+            }
+        });
+        // A stop that arrived after the previous search had already finished must not end this one
+        self.stop.store(false, Ordering::Relaxed);
+        self.search = Some(thread::spawn(move || {
+            let outcome = engine.search(&pos, limits);
+            match outcome.best_move {
+                Some((from, to)) => println!("bestmove {}", san::move_to_uci(&pos, from, to)),
+                None => println!("bestmove 0000"),
+            }
+            (engine, outcome)
+        }));
+    }
+
+    // Returns true while a search is running, a search that has printed its best move no longer counts
+    pub fn searching(&self) -> bool {
+        self.search.as_ref().is_some_and(|search| !search.is_finished())
+    }
+
+    // Waits for the running search and returns the result of the last search of the session
+    pub fn last_outcome(&mut self) -> Option<SearchOutcome> {
+        self.wait_for_search();
+        self.outcome.clone()
+    }
+
+    // Waits until the running search, if any, has printed its best move and takes the engine back
+    fn wait_for_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            let (engine, outcome) = search.join().expect("The search thread panicked");
+            self.engine = Some(engine);
+            self.outcome = Some(outcome);
+        }
+    }
+
+    fn stop_search(&mut self) {
+        if self.search.is_some() {
+            self.stop.store(true, Ordering::Relaxed);
+            self.wait_for_search();
+        }
+    }
+}
+
+/* Sets up the position of a "position" command: "startpos" or "fen <fen>", optionally followed by "moves" and the
+/ moves played from there in the UCI format. FENs are read in the given variant, with chess960 castling moves are read
+/ the Chess960 way. */
+pub fn parse_position(args: &str, variant: Variant, chess960: bool) -> Result<Position, String> {
+    let (setup, moves) = args.split_once("moves").unwrap_or((args, ""));
+    let setup = setup.trim();
+    let mut pos = if setup == "startpos" {
+        variants::start_position(variant)
+    } else if let Some(fen) = setup.strip_prefix("fen") {
+        Position::from_variant_fen(fen.trim(), variant).map_err(|e| format!("Invalid FEN: {}", e))?
+    } else {
+        return Err(format!("Invalid position: {}", args));
+    };
+    pos.state.chess960 = chess960;
+
+    for text in moves.split_whitespace() {
+        let (from, to) = san::parse_uci(&mut pos, text).ok_or(format!("Illegal move: {}", text))?;
+        game::play_move(&mut pos, from, to);
+    }
+    Ok(pos)
+}
+
+/* Turns the parameters of a "go" command into search limits. With wtime and btime, the move time is the remaining
+/ time of the side to move split over the moves to go plus most of the increment. Without any limit the search runs
+/ until it is stopped. Other parameters like nodes or ponder aren't supported and ignored. */
+pub fn parse_go(args: &str, active_player: Color) -> SearchLimits {
+    let mut depth = None;
+    let mut move_time = None;
+    let mut times = [None; 2];
+    let mut increments = [0; 2];
+    let mut moves_to_go = None;
+
+    let mut tokens = args.split_whitespace();
+    while let Some(token) = tokens.next() {
+        // Some GUIs send negative times once the flag has fallen
+        let mut value = || tokens.next().and_then(|value| value.parse::<i64>().ok()).map(|value| value.max(0) as u64);
+        match token {
+            "depth" => depth = value().map(|depth| depth.clamp(1, MAX_DEPTH as u64) as u8),
+            "movetime" => move_time = value().map(Duration::from_millis),
+            "wtime" => times[Color::White as usize] = value(),
+            "btime" => times[Color::Black as usize] = value(),
+            "winc" => increments[Color::White as usize] = value().unwrap_or(0),
+            "binc" => increments[Color::Black as usize] = value().unwrap_or(0),
+            "movestogo" => moves_to_go = value(),
+            _ => {},
+        }
+    }
+
+    let active = active_player as usize;
+    let move_time = move_time.or_else(|| times[active].map(|time| time_for_move(time, increments[active], moves_to_go)));
+    match (depth, move_time) {
+        (Some(depth), None) => SearchLimits { depth, move_time: None },
+        (depth, Some(move_time)) => SearchLimits { depth: depth.unwrap_or(MAX_DEPTH), move_time: Some(move_time) },
+        (None, None) => SearchLimits { depth: MAX_DEPTH, move_time: Some(INFINITE) },
+    }
+}
+
+// The "position" command sending a game to another engine: its start position and the moves played since
+pub fn position_command(start: &Position, moves: &[(u8, u8)]) -> String {
+    let mut command = format!("position fen {}", chess960::fen(start));
+    if !moves.is_empty() {
+        command.push_str(" moves");
+        let mut pos = start.clone();
+        for &(from, to) in moves {
+            command.push(' ');
+            command.push_str(&san::move_to_uci(&pos, from, to));
+            game::play_move(&mut pos, from, to);
+        }
+    }
+    command
+}
+
+// The "go" command asking another engine for a move within the search limits
+pub fn go_command(limits: SearchLimits) -> String {
+    match limits.move_time {
+        Some(move_time) if limits.depth < MAX_DEPTH => format!("go depth {} movetime {}", limits.depth, move_time.as_millis()),
+        Some(move_time) => format!("go movetime {}", move_time.as_millis()),
+        None => format!("go depth {}", limits.depth),
+    }
+}
+
+// Move time from the remaining time and increment in milliseconds, never more than half of the remaining time
+fn time_for_move(time: u64, increment: u64, moves_to_go: Option<u64>) -> Duration {
+    let available = time.saturating_sub(MOVE_OVERHEAD);
+    let moves_to_go = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+    Duration::from_millis((available / moves_to_go + increment * 3 / 4).min(available / 2))
+}
+
+// Formats a search score for the side to move as "cp <centipawns>" or "mate <moves>"
+pub fn score_to_uci(score: i32) -> String {
+    if score == i32::MAX {
+        "mate 1".to_string()
+    } else {
+        format!("cp {}", score.clamp(-MAX_CENTIPAWNS, MAX_CENTIPAWNS))
+    }
+}
+
+/* Reads the score of an info line of another engine for the side to move, the reverse of score_to_uci: mates are
+/ scored as mate in one for the side that mates. */
+pub fn parse_info_score(line: &str) -> Option<i32> {
+    let mut tokens = line.split_whitespace().skip_while(|&token| token != "score").skip(1);
+    let kind = tokens.next()?;
+    let value = tokens.next()?.parse::<i32>().ok()?;
+    match kind {
+        "cp" => Some(value),
+        "mate" if value > 0 => Some(i32::MAX),
+        "mate" => Some(i32::MIN + 2),
+        _ => None,
+    }
+}
+
+// Sends the statistics of an iteration, UCI has no fields for most of them
+fn print_stats(stats: &SearchStats) {
+    println!("info string qnodes {} ttprobes {} tthits {:.1}% ttcutoffs {} cutoffs {} firstmove {:.1}% ebf {:.2} iterationtime {}",
+        stats.qnodes, stats.tt_probes, stats.tt_hit_rate(), stats.tt_cutoffs, stats.beta_cutoffs, stats.first_move_cutoff_rate(),
+        stats.branching_factor(), stats.last_iteration().time.as_millis());
+}
+
+fn print_info(pos: &Position, outcome: &SearchOutcome) {
+    let Some((from, to)) = outcome.best_move else {
+        return;
+    };
+    let nodes_per_second = outcome.nodes as f64 / outcome.time.as_secs_f64().max(0.001);
+    println!("info depth {} score {} nodes {} nps {:.0} tbhits {} time {} pv {}",
+        outcome.depth, score_to_uci(outcome.score), outcome.nodes, nodes_per_second, outcome.stats.tb_hits,
+        outcome.time.as_millis(), san::move_to_uci(pos, from, to));
+}
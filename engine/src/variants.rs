@@ -0,0 +1,133 @@
+use types::{
+    bitboard::BitBoard,
+    position::Position,
+    variant::Variant,
+    Castling,
+    Color,
+    Piece,
+};
+use crate::movegen;
+
+/* Rules of the chess variants, see types::variant. Standard chess is played by the rest of the engine, this module
+/ adds the win conditions and move rules of the other variants and their evaluation terms. The variant of a game is
+/ stored in the state of its positions, the UCI_Variant option selects the variant of new games. */
+
+// Scores for the side to move when the game has been won or lost by the rules of the variant
+pub const WIN_SCORE: i32 = i32::MAX - 1;
+pub const LOSS_SCORE: i32 = i32::MIN + 2;
+
+// d4, e4, d5 and e5
+const CENTER: BitBoard = BitBoard(0x0000_0018_1800_0000);
+
+// Bonus for the king by its distance to the center in King of the Hill
+const HILL_DISTANCE_BONUS: [i32; 8] = [0, 300, 120, 40, 0, 0, 0, 0];
+
+// Bonus by the number of checks given in Three-check
+const CHECKS_GIVEN_BONUS: [i32; 4] = [0, 250, 700, 0];
+
+/* Values of the pieces in Crazyhouse hands. They are material like the pieces on the board and worth a bit more,
+/ since they can be dropped onto any empty square. */
+const HAND_PIECE_VALUES: [i32; 6] = [1400, 950, 950, 2700, 0, 260];
+
+// Piece values in Antichess, where losing material is the goal and the king is a weak piece
+const ANTICHESS_PIECE_VALUES: [i32; 6] = [60, 30, 30, 90, 15, 20];
+
+// Returns the start position of a variant, Antichess is played without castling
+pub fn start_position(variant: Variant) -> Position {
+    let mut pos = Position::new();
+    pos.state.variant = variant;
+    if variant == Variant::Antichess {
+        pos.state.castling_rights.0 = Castling::NO_CASTLING;
+        pos.rebuild_attack_maps();
+    }
+    pos
+}
+
+/* Returns the winner if the game has been decided by a rule of the variant. Checkmate is handled by the game and
+/ search like in standard chess, it can't happen in Antichess. */
+pub fn winner(pos: &mut Position) -> Option<Color> {
+    let mover = !pos.state.active_player;
+    match pos.state.variant {
+        Variant::Standard | Variant::Crazyhouse => None,
+        Variant::KingOfTheHill => {
+            let king = pos.piece_bitboards[Piece::KING as usize] & pos.color_bitboards[mover as usize];
+            (!(king & CENTER).is_empty()).then_some(mover)
+        },
+        Variant::ThreeCheck => (pos.state.checks[mover as usize] >= 3).then_some(mover),
+        Variant::Antichess => {
+            let active_player = pos.state.active_player;
+            let no_pieces = pos.color_bitboards[active_player as usize].is_empty();
+            (no_pieces || antichess_moves(active_player, pos).is_empty()).then_some(active_player)
+        },
+    }
+}
+
+/* Returns the Antichess moves of a color: every piece, including the king, moves like in standard chess without
+/ regard to check. If a capture is possible, only captures may be played. */
+pub fn antichess_moves(color: Color, pos: &mut Position) -> Vec<(u8, u8)> {
+    let mut moves = Vec::new();
+    let mut squares = pos.color_bitboards[color as usize];
+    while !squares.is_empty() {
+        let square = squares.trailing_zeros();
+        let mut targets = match pos.piece_type_at(square).unwrap() {
+            Piece::KNIGHT => movegen::get_knight_moves(square, pos),
+            Piece::KING => movegen::get_king_moves(square, pos),
+            Piece::PAWN => movegen::get_pawn_moves(square, pos),
+            piece => movegen::slider_moves(piece, square, pos),
+        };
+        while !targets.is_empty() {
+            moves.push((square, targets.trailing_zeros()));
+            targets.clear_lsb();
+        }
+        squares.clear_lsb();
+    }
+
+    let is_capture = |&(from, to): &(u8, u8)| {
+        pos.color_bitboards[!color as usize].contains(to)
+            || pos.piece_type_at(from) == Some(Piece::PAWN) && pos.en_passant_square == Some(to)
+    };
+    if moves.iter().any(is_capture) {
+        moves.retain(is_capture);
+    }
+    moves
+}
+
+// Evaluation terms of King of the Hill, Three-check and Crazyhouse from white's point of view
+pub fn evaluation(pos: &Position) -> i32 {
+    let mut evaluation = 0;
+    for (color, sign) in [(Color::White, 1), (Color::Black, -1)] {
+        match pos.state.variant {
+            Variant::KingOfTheHill => {
+                let king = pos.piece_bitboards[Piece::KING as usize] & pos.color_bitboards[color as usize];
+                evaluation += sign * HILL_DISTANCE_BONUS[center_distance(king.trailing_zeros()) as usize];
+            },
+            Variant::ThreeCheck => {
+                evaluation += sign * CHECKS_GIVEN_BONUS[pos.state.checks[color as usize].min(3) as usize];
+            },
+            Variant::Crazyhouse => {
+                let hand = pos.state.hands[color as usize];
+                evaluation += sign * hand.iter().zip(HAND_PIECE_VALUES).map(|(&count, value)| count as i32 * value).sum::<i32>();
+            },
+            _ => {},
+        }
+    }
+    evaluation
+}
+
+// Evaluates an Antichess position for the side to move by material, having less of it is better
+pub fn antichess_evaluation(pos: &Position) -> i32 {
+    let active_player = pos.state.active_player;
+    let mut evaluation = 0;
+    for (pieces, value) in pos.piece_bitboards.iter().zip(ANTICHESS_PIECE_VALUES) {
+        let own = (*pieces & pos.color_bitboards[active_player as usize]).count_ones() as i32;
+        let other = (*pieces & pos.color_bitboards[!active_player as usize]).count_ones() as i32;
+        evaluation += (other - own) * value;
+    }
+    evaluation
+}
+
+// Number of king moves from a square to the nearest center square
+fn center_distance(square: u8) -> u8 {
+    let distance = |coordinate: u8| if coordinate < 4 { 3 - coordinate } else { coordinate - 4 };
+    distance(square % 8).max(distance(square / 8))
+}
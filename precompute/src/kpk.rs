@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/* Ported from the KPK bitbase of Stockfish (src/bitbase.cpp),
+/ Copyright (C) The Stockfish developers, see the AUTHORS file of https://github.com/official-stockfish/Stockfish
+/ This file is licensed under the GNU General Public License, version 3 or any later version,
+/ see LICENSES/GPL-3.0-or-later.txt and the License section of the README. */
+
+use std::io::prelude::*;
+use types::{
+    bitboard::BitBoard,
+    types_utils::*,
+};
+
+/* This module generates a bitbase for all king and pawn versus king positions by retrograde analysis.
+/ Positions are always seen from the side with the pawn as white, and the pawn is mirrored onto the files a to d,
+/ which leaves 24 pawn squares, 64 squares for each king and the side to move: 2 * 24 * 64 * 64 positions.
+/ The approach follows the KPK bitbase of Stockfish, see the license notice above. */
+
+pub const KPK_MAX_INDEX: usize = 2 * 24 * 64 * 64;
+pub const KPK_BITBASE_SIZE: usize = KPK_MAX_INDEX / 32;
+
+// Results of the classification, stored as bit flags so the results of all children can be ORed together
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+/* Returns the bitbase index of a position. Expects the pawn to be on files a to d and on ranks 2 to 7,
+/ side_to_move is 0 for white and 1 for black. */
+pub fn kpk_index(side_to_move: usize, black_king: u8, white_king: u8, pawn: u8) -> usize {
+    white_king as usize
+        | (black_king as usize) << 6
+        | side_to_move << 12
+        | (pawn as usize % 8) << 13
+        | (6 - pawn as usize / 8) << 15
+}
+
+fn king_attacks(square: u8) -> BitBoard {
+    let mut attacks = BitBoard::empty();
+    for &(dx, dy) in &[(1, 1), (1, 0), (1, -1), (0, 1), (0, -1), (-1, 1), (-1, 0), (-1, -1)] {
+        if let Some(offset_square) = try_square_offset(square, dx, dy) {
+            attacks |= BitBoard::from_square(offset_square);
+        }
+    }
+    attacks
+}
+
+fn white_pawn_attacks(square: u8) -> BitBoard {
+    let mut attacks = BitBoard::empty();
+    for dx in [-1, 1] {
+        if let Some(offset_square) = try_square_offset(square, dx, 1) {
+            attacks |= BitBoard::from_square(offset_square);
+        }
+    }
+    attacks
+}
+
+fn distance(a: u8, b: u8) -> u8 {
+    std::cmp::max((a % 8).abs_diff(b % 8), (a / 8).abs_diff(b / 8))
+}
+
+struct KpkPosition {
+    side_to_move: usize,
+    white_king: u8,
+    black_king: u8,
+    pawn: u8,
+    result: u8,
+}
+
+impl KpkPosition {
+    fn new(index: usize) -> Self {
+        let white_king = (index & 0x3F) as u8;
+        let black_king = ((index >> 6) & 0x3F) as u8;
+        let side_to_move = (index >> 12) & 0x01;
+        let pawn = (8 * (6 - ((index >> 15) & 0x07)) + ((index >> 13) & 0x03)) as u8;
+
+        let mut position = Self { side_to_move, white_king, black_king, pawn, result: UNKNOWN };
+        position.result = position.initial_result();
+        position
+    }
+
+    // Classifies everything that can be decided without looking at the children of the position
+    fn initial_result(&self) -> u8 {
+        let (white_king, black_king, pawn) = (self.white_king, self.black_king, self.pawn);
+        let promotion_square = pawn + 8;
+
+        // Overlapping or adjacent kings, or the black king in check with white to move
+        if distance(white_king, black_king) <= 1
+            || white_king == pawn
+            || black_king == pawn
+            || self.side_to_move == 0 && white_pawn_attacks(pawn).contains(black_king) {
+            return INVALID;
+        }
+
+        // The pawn promotes without being captured
+        if self.side_to_move == 0
+            && pawn / 8 == 6
+            && white_king != promotion_square
+            && (distance(black_king, promotion_square) > 1 || distance(white_king, promotion_square) == 1) {
+            return WIN;
+        }
+
+        // The black king is stalemated or can capture an undefended pawn
+        if self.side_to_move == 1 {
+            let black_king_moves = king_attacks(black_king);
+            let white_control = king_attacks(white_king) | white_pawn_attacks(pawn);
+            if (black_king_moves & !white_control).is_empty()
+                || (black_king_moves & !king_attacks(white_king)).contains(pawn) {
+                return DRAW;
+            }
+        }
+
+        UNKNOWN
+    }
+
+    // Classifies a position by the results of all positions reachable in one move
+    fn classify(&self, database: &[KpkPosition]) -> u8 {
+        let (good, bad) = if self.side_to_move == 0 { (WIN, DRAW) } else { (DRAW, WIN) };
+        let mut result = INVALID;
+
+        if self.side_to_move == 0 {
+            let mut king_moves = king_attacks(self.white_king);
+            while !king_moves.is_empty() {
+                let king_square = king_moves.trailing_zeros();
+                result |= database[kpk_index(1, self.black_king, king_square, self.pawn)].result;
+                king_moves.clear_lsb();
+            }
+            if self.pawn / 8 < 6 {
+                result |= database[kpk_index(1, self.black_king, self.white_king, self.pawn + 8)].result;
+            }
+            if self.pawn / 8 == 1 && self.pawn + 8 != self.white_king && self.pawn + 8 != self.black_king {
+                result |= database[kpk_index(1, self.black_king, self.white_king, self.pawn + 16)].result;
+            }
+        } else {
+            let mut king_moves = king_attacks(self.black_king);
+            while !king_moves.is_empty() {
+                let king_square = king_moves.trailing_zeros();
+                result |= database[kpk_index(0, king_square, self.white_king, self.pawn)].result;
+                king_moves.clear_lsb();
+            }
+        }
+
+        if result & good != 0 {
+            good
+        } else if result & UNKNOWN != 0 {
+            UNKNOWN
+        } else {
+            bad
+        }
+    }
+}
+
+/* Generates the bitbase: one bit per position index, set if white wins.
+/ Unknown positions are classified again and again until no position changes anymore, after which all remaining
+/ unknown positions are draws. */
+pub fn generate_kpk_bitbase() -> Vec<u32> {
+    let mut database: Vec<KpkPosition> = (0..KPK_MAX_INDEX).map(KpkPosition::new).collect();
+
+    let mut repeat = true;
+    while repeat {
+        repeat = false;
+        for index in 0..KPK_MAX_INDEX {
+            if database[index].result == UNKNOWN {
+                let result = database[index].classify(&database);
+                if result != UNKNOWN {
+                    database[index].result = result;
+                    repeat = true;
+                }
+            }
+        }
+    }
+
+    let mut bitbase = vec![0u32; KPK_BITBASE_SIZE];
+    for (index, position) in database.iter().enumerate() {
+        if position.result == WIN {
+            bitbase[index / 32] |= 1 << (index % 32);
+        }
+    }
+    bitbase
+}
+
+pub fn write_kpk_bitbase(bitbase: &[u32], out: &mut impl Write) -> std::io::Result<()> {
+    // The bitbase is the output of the GPL-licensed generator, so it carries the same license
+    writeln!(out, "// SPDX-License-Identifier: GPL-3.0-or-later")?;
+    writeln!(out, "// Generated by the precompute crate, see precompute::kpk for the origin and the license")?;
+    writeln!(out, "#[rustfmt::skip]")?;
+    writeln!(out, "pub const KPK_BITBASE: &[u32; {}] = &[", bitbase.len())?;
+    for chunk in bitbase.chunks(8) {
+        let line: Vec<String> = chunk.iter().map(|entry| format!("0x{:08X}", entry)).collect();
+        writeln!(out, "    {},", line.join(", "))?;
+    }
+    writeln!(out, "];")?;
+    Ok(())
+}
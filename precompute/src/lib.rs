@@ -1,4 +1,5 @@
 pub mod rng;
 pub mod precompute_magics;
 pub mod populate_move_table;
-pub mod magics;
\ No newline at end of file
+pub mod magics;
+pub mod kpk;
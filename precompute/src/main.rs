@@ -3,6 +3,7 @@ use precompute::{
     precompute_magics::precompute_magics,
     rng::Rng,
     magics::*,
+    kpk::{generate_kpk_bitbase, write_kpk_bitbase},
 };
 use std::{
     io::{
@@ -39,13 +40,23 @@ fn main() -> Result<()> {
     out.push("magics.rs");
     let mut out = BufWriter::new(File::create(out).unwrap());
 
-    write!(out, "use types::square::Square;\n")?;
-    write!(out, "use precompute::magics::MagicTableEntry;\n")?;
+    writeln!(out, "use types::square::Square;")?;
+    writeln!(out, "use precompute::magics::MagicTableEntry;")?;
 
     write_magics("ROOK", ROOK_MAGICS, &mut out).unwrap();
     write_magics("BISHOP", BISHOP_MAGICS, &mut out).unwrap();
     write_table("ROOK", &rook_table, &mut out).unwrap();
     write_table("BISHOP", &bishop_table, &mut out).unwrap();
 
+    // Generates the KPK bitbase by retrograde analysis
+    let kpk_bitbase = generate_kpk_bitbase();
+
+    let mut out: PathBuf = "".into();
+    out.push("engine/src/");
+    out.push("kpk_bitbase.rs");
+    let mut out = BufWriter::new(File::create(out).unwrap());
+
+    write_kpk_bitbase(&kpk_bitbase, &mut out).unwrap();
+
     Ok(())
 }
\ No newline at end of file
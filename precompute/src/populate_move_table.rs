@@ -35,8 +35,7 @@ pub fn make_table(
     magics: &[MagicTableEntry; 64],
 ) -> Vec<BitBoard> {
     let mut table = vec![BitBoard::empty(); table_size];
-    for square in 0..64 {
-        let magic_entry = &magics[square];
+    for (square, magic_entry) in magics.iter().enumerate() {
         let mask = BitBoard(magic_entry.mask);
 
         let mut blockers = BitBoard::empty();
@@ -94,9 +94,7 @@ fn magic_index(entry: &MagicEntry, blockers: BitBoard) -> usize {
     // println!("Attemping to find magic index for blockers: {:?} with hash {}", blockers, hash);
 
     // Shift the hash to the right by 64 - that entry's shift value to get the index.
-    let index = (hash >> entry.shift) as usize;
-
-    index
+    (hash >> entry.shift) as usize
 }
 
 // Returns a magic number for a given slider piece and square.
@@ -188,7 +186,7 @@ pub fn precompute_magics(
             pub shift: u8,
             pub offset: u32,
         }\n";
-        write!(output_file, "{}\n", line)?;
+        writeln!(output_file, "{}", line)?;
         for sliding_piece in &[ROOK, BISHOP] {
             let piece_name = if sliding_piece == &ROOK { "rook" } else { "bishop" };
             println!("\nComputing magics for {}", piece_name);
@@ -196,7 +194,7 @@ pub fn precompute_magics(
                 "pub const {}_MAGICS: &[MagicEntry; 64] = &[",
                 piece_name.to_uppercase()
             );
-            write!(output_file, "{}\n", line)?;
+            writeln!(output_file, "{}", line)?;
             let mut table_length = 0;
             for square in 0..64 {
                 let blockers_amount = sliding_piece.blocker_squares(square).count_ones() as u8;
@@ -206,14 +204,14 @@ pub fn precompute_magics(
                     "    MagicEntry {{ mask: 0x{:016X}, magic: 0x{:016X}, shift: {}, offset: {} }},",
                     magic_entry.mask.0, magic_entry.magic, magic_entry.shift, table_length
                 );
-                write!(output_file, "{}\n", line)?;
+                writeln!(output_file, "{}", line)?;
                 print!("\rEntry {} of 64 written to file.", square as usize + 1);
                 io::stdout().flush().unwrap();
                 table_length += magics.len();
             }
 
-            let line = format!("];");
-            write!(output_file, "{}\n", line)?;
+            let line = "];".to_string();
+            writeln!(output_file, "{}", line)?;
             let line = format!(
                 "pub const {}_TABLE_SIZE: usize = {};",
                 piece_name.to_uppercase(), table_length
@@ -102,7 +102,7 @@ impl BitBoard {
     }
 
     pub fn contains(self, square: u8) -> bool {
-        !(self.0 & 1 << square as u64 == 0)
+        self.0 & 1 << square as u64 != 0
     }
 
     pub fn shift_east(&self) -> Self {
@@ -110,27 +110,27 @@ impl BitBoard {
     }
 
     pub fn shift_west(&self) -> Self {
-        Self(self.0 >> 1 & 0x7F7F_7F7F_7F7F_7F)
+        Self(self.0 >> 1 & 0x7F7F_7F7F_7F7F_7F7F)
     }
 
     // Shifts the bits diagonally to the north-east
     pub fn diagonal_north_east(&self) -> Self {
-        Self((self.0 & 0xFEFE_FEFE_FEFE_FEFE) << 9)
+        Self((self.0 & 0x7F7F_7F7F_7F7F_7F7F) << 9)
     }
 
     // Shifts the bits diagonally to the north-west
     pub fn diagonal_north_west(&self) -> Self {
-        Self((self.0 & 0x7F7F_7F7F_7F7F_7F7F) << 7)
+        Self((self.0 & 0xFEFE_FEFE_FEFE_FEFE) << 7)
     }
 
     // Shifts the bits diagonally to the south-east
     pub fn diagonal_south_east(&self) -> Self {
-        Self((self.0 & 0xFEFE_FEFE_FEFE_FEFE) >> 7)
+        Self((self.0 & 0x7F7F_7F7F_7F7F_7F7F) >> 7)
     }
 
     // Shifts the bits diagonally to the south-west
     pub fn diagonal_south_west(&self) -> Self {
-        Self((self.0 & 0x7F7F_7F7F_7F7F_7F7F) >> 9)
+        Self((self.0 & 0xFEFE_FEFE_FEFE_FEFE) >> 9)
     }
 
     pub fn squares_from_bb(&self) -> Vec<u8> {
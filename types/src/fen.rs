@@ -0,0 +1,392 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    bitboard::BitBoard,
+    position::Position,
+    variant::Variant,
+    Castling,
+    Color,
+    Piece,
+};
+
+/* Parsing and validation of positions in the Forsyth-Edwards Notation. Besides the usual KQkq castling field,
+/ Shredder-FEN (the files of the castling rooks, like HAha) and X-FEN (KQkq for the outermost rooks, files for any
+/ other rook) are accepted, which also covers Chess960 positions. The half move clock and full move number may be left out, they default to 0 and 1.
+/ Parsed positions are validated and have their attack maps and check flag set up, ready to generate moves. */
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FenError {
+    // The FEN needs 4 to 6 fields separated by whitespace
+    FieldCount(usize),
+    // Character in the piece placement that is neither a piece, an empty square count nor a rank separator
+    InvalidPiece { index: usize, character: char },
+    // A rank that doesn't have exactly 8 squares
+    InvalidRank { index: usize },
+    // The piece placement doesn't have exactly 8 ranks
+    RankCount { index: usize, ranks: usize },
+    InvalidSideToMove { index: usize },
+    InvalidCastling { index: usize, character: char },
+    InvalidEnPassant { index: usize },
+    InvalidHalfMoveClock { index: usize },
+    InvalidFullMoveNumber { index: usize },
+    // Three-check counters like 3+3 (checks remaining) or +0+0 (checks given) that aren't between 0 and 3
+    InvalidCheckCount { index: usize },
+    // Character in the Crazyhouse pieces in hand that isn't a piece that can be dropped, or unclosed brackets
+    InvalidHolding { index: usize, character: char },
+    // Errors found when validating the parsed position
+    KingCount { color: Color, count: u32 },
+    PawnOnBackRank { square: u8 },
+    OpponentInCheck,
+    InconsistentCastling { right: char },
+    InconsistentEnPassant { square: u8 },
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::FieldCount(count) => write!(f, "expected 4 to 6 fields, found {}", count),
+            FenError::InvalidPiece { index, character } => write!(f, "invalid piece '{}' at index {}", character, index),
+            FenError::InvalidRank { index } => write!(f, "rank ending at index {} doesn't have 8 squares", index),
+            FenError::RankCount { index, ranks } => write!(f, "expected 8 ranks, found {} at index {}", ranks, index),
+            FenError::InvalidSideToMove { index } => write!(f, "invalid side to move at index {}", index),
+            FenError::InvalidCastling { index, character } => write!(f, "invalid castling right '{}' at index {}", character, index),
+            FenError::InvalidEnPassant { index } => write!(f, "invalid en passant square at index {}", index),
+            FenError::InvalidHalfMoveClock { index } => write!(f, "invalid half move clock at index {}", index),
+            FenError::InvalidFullMoveNumber { index } => write!(f, "invalid full move number at index {}", index),
+            FenError::InvalidCheckCount { index } => write!(f, "invalid check count at index {}", index),
+            FenError::InvalidHolding { index, character } => write!(f, "invalid piece in hand '{}' at index {}", character, index),
+            FenError::KingCount { color, count } => write!(f, "{:?} has {} kings instead of one", color, count),
+            FenError::PawnOnBackRank { square } => write!(f, "pawn on back rank square {}", square_name(*square)),
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+            FenError::InconsistentCastling { right } => write!(f, "castling right '{}' without king and rook in place", right),
+            FenError::InconsistentEnPassant { square } => write!(f, "en passant square {} without a pawn that just moved two squares", square_name(*square)),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+impl FromStr for Position {
+    type Err = FenError;
+
+    fn from_str(fen: &str) -> Result<Self, Self::Err> {
+        Position::from_variant_fen(fen, Variant::Standard)
+    }
+}
+
+impl TryFrom<&str> for Position {
+    type Error = FenError;
+
+    fn try_from(fen: &str) -> Result<Self, Self::Error> {
+        fen.parse()
+    }
+}
+
+impl Position {
+    /* Parses the FEN of a position in the given variant. Three-check FENs may have an extra field with the checks
+    / remaining after the en passant square, like 3+3, or with the checks given at the end, like +0+0.
+    / Antichess positions may have any number of kings. Crazyhouse FENs have the pieces in hand in brackets after the
+    / placement, like RNBQKBNR[Qn], or as a ninth rank, like RNBQKBNR/Qn, and promoted pieces followed by a ~. */
+    pub fn from_variant_fen(fen: &str, variant: Variant) -> Result<Position, FenError> {
+        let mut fields = split_fields(fen);
+        let mut checks = [0, 0];
+        if variant == Variant::ThreeCheck {
+            if let Some(offset) = fields.iter().skip(4).position(|(_, field)| field.contains('+')) {
+                let (index, field) = fields.remove(offset + 4);
+                checks = parse_check_counts(field).ok_or(FenError::InvalidCheckCount { index })?;
+            }
+        }
+        if fields.len() < 4 || fields.len() > 6 {
+            return Err(FenError::FieldCount(fields.len()));
+        }
+
+        let mut pos = Position::new();
+        pos.state.variant = variant;
+        pos.state.checks = checks;
+        let placement = if variant == Variant::Crazyhouse {
+            let (placement, holdings) = split_holdings(fields[0])?;
+            if let Some(holdings) = holdings {
+                parse_holdings(&mut pos, holdings)?;
+            }
+            placement
+        } else {
+            fields[0]
+        };
+        parse_placement(&mut pos, placement)?;
+
+        let (index, side_to_move) = fields[1];
+        pos.state.active_player = match side_to_move {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(FenError::InvalidSideToMove { index }),
+        };
+
+        parse_castling(&mut pos, fields[2])?;
+
+        let (index, en_passant) = fields[3];
+        pos.en_passant_square = match en_passant {
+            "-" => None,
+            _ => match en_passant.as_bytes() {
+                &[file @ b'a'..=b'h', rank @ (b'3' | b'6')] => Some((rank - b'1') * 8 + file - b'a'),
+                _ => return Err(FenError::InvalidEnPassant { index }),
+            },
+        };
+
+        if let Some(&(index, half_moves)) = fields.get(4) {
+            pos.state.half_move_counter = half_moves.parse().map_err(|_| FenError::InvalidHalfMoveClock { index })?;
+        }
+        if let Some(&(index, full_moves)) = fields.get(5) {
+            pos.state.full_move_counter = match full_moves.parse() {
+                Ok(full_moves) if full_moves > 0 => full_moves,
+                _ => return Err(FenError::InvalidFullMoveNumber { index }),
+            };
+        }
+
+        pos.rebuild_attack_maps();
+        // There is no check in Antichess
+        if variant == Variant::Antichess {
+            pos.check = false;
+        }
+        pos.validate()?;
+        Ok(pos)
+    }
+
+    /* Checks that the position is legal: one king per side, no pawns on the first or last rank, the side not to
+    / move isn't in check, and castling rights and the en passant square match the pieces on the board. */
+    pub fn validate(&self) -> Result<(), FenError> {
+        // Antichess kings are normal pieces, so any number of them may be on the board and they can be attacked
+        let has_royal_kings = self.state.variant != Variant::Antichess;
+        for color in [Color::White, Color::Black].into_iter().filter(|_| has_royal_kings) {
+            let count = (self.piece_bitboards[Piece::KING as usize] & self.color_bitboards[color as usize]).count_ones();
+            if count != 1 {
+                return Err(FenError::KingCount { color, count });
+            }
+        }
+
+        let back_ranks = BitBoard::from_u64(0xFF00_0000_0000_00FF);
+        let pawns_on_back_ranks = self.piece_bitboards[Piece::PAWN as usize] & back_ranks;
+        if !pawns_on_back_ranks.is_empty() {
+            return Err(FenError::PawnOnBackRank { square: pawns_on_back_ranks.trailing_zeros() });
+        }
+
+        let opponent = !self.state.active_player;
+        let opponent_king = self.piece_bitboards[Piece::KING as usize] & self.color_bitboards[opponent as usize];
+        if has_royal_kings && self.is_square_attacked_by_color(opponent_king.trailing_zeros(), self.state.active_player) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        // Castling rights need the king on the back rank and the rook on its file, on the side of the king it castles to
+        for (right, name) in Castling::RIGHTS.into_iter().zip(['K', 'Q', 'k', 'q']) {
+            if self.state.castling_rights.0 & right == 0 {
+                continue;
+            }
+            let color = Castling::color(right);
+            let squares = self.castling_squares(right);
+            let back_rank = if color == Color::White { 0 } else { 7 };
+            let is_consistent = squares.king_from / 8 == back_rank
+                && self.piece_at(squares.rook_from) == Some((Piece::ROOK, color))
+                && (squares.rook_from > squares.king_from) == Castling::is_king_side(right);
+            if !is_consistent {
+                return Err(FenError::InconsistentCastling { right: name });
+            }
+        }
+
+        // The en passant square must be empty, with the pawn that moved in front of it and its origin square empty
+        if let Some(square) = self.en_passant_square {
+            let (expected_rank, pawn_square, origin_square) = match self.state.active_player {
+                Color::White => (5, square - 8, square + 8),
+                Color::Black => (2, square + 8, square - 8),
+            };
+            if square / 8 != expected_rank
+                || self.piece_at(square).is_some()
+                || self.piece_at(origin_square).is_some()
+                || self.piece_at(pawn_square) != Some((Piece::PAWN, opponent)) {
+                return Err(FenError::InconsistentEnPassant { square });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Splits the FEN into its fields, each with the index of its first character
+fn split_fields(fen: &str) -> Vec<(usize, &str)> {
+    let mut fields = Vec::new();
+    let mut start = None;
+    for (index, c) in fen.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(field_start)) => {
+                fields.push((field_start, &fen[field_start..index]));
+                start = None;
+            },
+            (false, None) => start = Some(index),
+            _ => {},
+        }
+    }
+    if let Some(field_start) = start {
+        fields.push((field_start, &fen[field_start..]));
+    }
+    fields
+}
+
+fn parse_placement(pos: &mut Position, (start, placement): (usize, &str)) -> Result<(), FenError> {
+    pos.color_bitboards = [BitBoard::empty(); 2];
+    pos.piece_bitboards = [BitBoard::empty(); 6];
+
+    let mut rank: usize = 7;
+    let mut file: u8 = 0;
+    for (offset, c) in placement.char_indices() {
+        let index = start + offset;
+        match c {
+            '1'..='8' => file += c as u8 - b'0',
+            // A promoted Crazyhouse piece, marked right after the piece
+            '~' if pos.state.variant == Variant::Crazyhouse
+                && (1..=8).contains(&file)
+                && placement[..offset].ends_with(|c: char| c.is_ascii_alphabetic()) => {
+                pos.state.promoted |= BitBoard::from_square(rank as u8 * 8 + file - 1);
+            },
+            '/' => {
+                if file != 8 {
+                    return Err(FenError::InvalidRank { index });
+                }
+                if rank == 0 {
+                    return Err(FenError::RankCount { index, ranks: 9 });
+                }
+                rank -= 1;
+                file = 0;
+                continue;
+            },
+            _ => {
+                let piece = match c.to_ascii_lowercase() {
+                    'r' => Piece::ROOK,
+                    'n' => Piece::KNIGHT,
+                    'b' => Piece::BISHOP,
+                    'q' => Piece::QUEEN,
+                    'k' => Piece::KING,
+                    'p' => Piece::PAWN,
+                    _ => return Err(FenError::InvalidPiece { index, character: c }),
+                };
+                if file < 8 {
+                    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+                    let square = BitBoard::from_square(rank as u8 * 8 + file);
+                    pos.color_bitboards[color as usize] |= square;
+                    pos.piece_bitboards[piece as usize] |= square;
+                }
+                file += 1;
+            },
+        }
+        if file > 8 {
+            return Err(FenError::InvalidRank { index });
+        }
+    }
+
+    let end = start + placement.len();
+    if rank != 0 {
+        return Err(FenError::RankCount { index: end, ranks: 8 - rank });
+    }
+    if file != 8 {
+        return Err(FenError::InvalidRank { index: end });
+    }
+    Ok(())
+}
+
+// A field of the FEN with the index of its first character
+type Field<'a> = (usize, &'a str);
+
+// Splits the pieces in hand off the placement of a Crazyhouse FEN, they are optional
+fn split_holdings((start, placement): Field) -> Result<(Field, Option<Field>), FenError> {
+    if let Some(open) = placement.find('[') {
+        let Some(holdings) = placement[open + 1..].strip_suffix(']') else {
+            return Err(FenError::InvalidHolding { index: start + open, character: '[' });
+        };
+        return Ok(((start, &placement[..open]), Some((start + open + 1, holdings))));
+    }
+    match placement.rmatch_indices('/').nth(7) {
+        Some(_) => {
+            let last_rank = placement.rfind('/').unwrap();
+            Ok(((start, &placement[..last_rank]), Some((start + last_rank + 1, &placement[last_rank + 1..]))))
+        },
+        None => Ok(((start, placement), None)),
+    }
+}
+
+fn parse_holdings(pos: &mut Position, (start, holdings): (usize, &str)) -> Result<(), FenError> {
+    if holdings == "-" {
+        return Ok(());
+    }
+    for (offset, c) in holdings.char_indices() {
+        let piece = match c.to_ascii_lowercase() {
+            'r' => Piece::ROOK,
+            'n' => Piece::KNIGHT,
+            'b' => Piece::BISHOP,
+            'q' => Piece::QUEEN,
+            'p' => Piece::PAWN,
+            _ => return Err(FenError::InvalidHolding { index: start + offset, character: c }),
+        };
+        let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        let count = &mut pos.state.hands[color as usize][piece as usize];
+        *count = count.saturating_add(1);
+    }
+    Ok(())
+}
+
+/* Reads the castling rights. K, Q, k and q stand for the outermost rook on either side of the king, files stand for
+/ the rook on that file, which is needed in Chess960 when a side has two rooks on the same side of its king.
+/ Rights without a matching king and rook are rejected later by validate(). */
+fn parse_castling(pos: &mut Position, (start, castling): (usize, &str)) -> Result<(), FenError> {
+    pos.state.castling_rights.0 = Castling::NO_CASTLING;
+    if castling == "-" {
+        return Ok(());
+    }
+
+    for (offset, c) in castling.char_indices() {
+        let index = start + offset;
+        let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        let back_rank = if color == Color::White { 0 } else { 56 };
+        let king = pos.piece_bitboards[Piece::KING as usize] & pos.color_bitboards[color as usize] & BitBoard::from_u64(0xFF << back_rank);
+        // Without a king on the back rank, the standard files are assumed so that validate() can report the right
+        let king_file = if king.is_empty() { 4 } else { king.trailing_zeros() % 8 };
+        let is_rook = |file: &u8| pos.piece_at(back_rank + file) == Some((Piece::ROOK, color));
+
+        let (is_king_side, rook_file) = match c.to_ascii_lowercase() {
+            'k' => (true, (king_file + 1..8).rev().find(is_rook).unwrap_or(7)),
+            'q' => (false, (0..king_file).find(is_rook).unwrap_or(0)),
+            file @ 'a'..='h' if file as u8 - b'a' != king_file => {
+                let file = file as u8 - b'a';
+                (file > king_file, file)
+            },
+            _ => return Err(FenError::InvalidCastling { index, character: c }),
+        };
+        let right = match (color, is_king_side) {
+            (Color::White, true) => Castling::WHITE_KING_SIDE,
+            (Color::White, false) => Castling::WHITE_QUEEN_SIDE,
+            (Color::Black, true) => Castling::BLACK_KING_SIDE,
+            (Color::Black, false) => Castling::BLACK_QUEEN_SIDE,
+        };
+        // Every right may only be given once, for example not as both K and H
+        if pos.state.castling_rights.0 & right != 0 {
+            return Err(FenError::InvalidCastling { index, character: c });
+        }
+        pos.state.castling_rights.0 |= right;
+        pos.state.castling_rights.set_rook_file(right, rook_file);
+    }
+    Ok(())
+}
+
+// Reads Three-check counters, either the checks remaining like 3+3 or the checks given like +0+0
+fn parse_check_counts(field: &str) -> Option<[u8; 2]> {
+    let (given, field) = match field.strip_prefix('+') {
+        Some(field) => (true, field),
+        None => (false, field),
+    };
+    let (white, black) = field.split_once('+')?;
+    let counts = [white.parse::<u8>().ok()?, black.parse::<u8>().ok()?];
+    if counts.iter().any(|&count| count > 3) {
+        return None;
+    }
+    Some(if given { counts } else { [3 - counts[0], 3 - counts[1]] })
+}
+
+fn square_name(square: u8) -> String {
+    format!("{}{}", (b'a' + square % 8) as char, (b'1' + square / 8) as char)
+}
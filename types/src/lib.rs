@@ -1,7 +1,9 @@
 use std::ops::Not;
 pub mod bitboard;
+pub mod fen;
 pub mod position;
 pub mod state;
+pub mod variant;
 
 /* Represents a single square on the board.
 / Representation: 0-63, with 0 being a1 and 63 being h8. */
@@ -73,6 +75,34 @@ impl Castling {
     pub const WHITE_CASTLING: u8 = Self::WHITE_KING_SIDE | Self::WHITE_QUEEN_SIDE;
     pub const BLACK_CASTLING: u8 = Self::BLACK_KING_SIDE | Self::BLACK_QUEEN_SIDE;
     pub const ANY_CASTLING: u8 = Self::WHITE_CASTLING | Self::BLACK_CASTLING;
+
+    // The single rights, in the order of their rook files in the castling rights
+    pub const RIGHTS: [u8; 4] = [Self::WHITE_KING_SIDE, Self::WHITE_QUEEN_SIDE, Self::BLACK_KING_SIDE, Self::BLACK_QUEEN_SIDE];
+
+    pub fn color(right: u8) -> Color {
+        if right & Self::WHITE_CASTLING != 0 { Color::White } else { Color::Black }
+    }
+
+    pub fn is_king_side(right: u8) -> bool {
+        right & Self::BOTH_KING_SIDES != 0
+    }
+}
+
+/* Drop moves of Crazyhouse are stored like other moves as an origin and a target square, with the origin square
+/ replaced by OFFSET plus the type of the dropped piece, so it can't be confused with a square on the board. */
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct DropMove;
+impl DropMove {
+    pub const OFFSET: u8 = 64;
+
+    pub fn origin(piece: u8) -> u8 {
+        Self::OFFSET + piece
+    }
+
+    // Returns the dropped piece if the origin square belongs to a drop move
+    pub fn piece(origin: u8) -> Option<u8> {
+        origin.checked_sub(Self::OFFSET)
+    }
 }
 
 pub mod types_utils {
@@ -81,7 +111,7 @@ pub mod types_utils {
     pub fn try_square_offset(square: u8, dx: i8, dy: i8) -> Option<u8> {
         let (file, rank) = (square % 8, square / 8);
         let (new_file, new_rank) = (file as i8 + dx, rank as i8 + dy);
-        if new_file < 0 || new_file > 7 || new_rank < 0 || new_rank > 7 {
+        if !(0..=7).contains(&new_file) || !(0..=7).contains(&new_rank) {
             None
         } else {
             Some((new_rank * 8 + new_file) as u8)
@@ -89,12 +119,59 @@ pub mod types_utils {
     }
 
     pub fn string_from_square(square: u8) -> String {
-        let file = (square % 8) as u8 + 97;
-        let rank = (square / 8) as u8 + 49;
+        let file = (square % 8) + 97;
+        let rank = (square / 8) + 49;
         format!("{}{}", (file as char).to_ascii_uppercase(), (rank as char).to_ascii_uppercase())
     }
 
+    /* Returns the FEN of a position. Castling rights are written in X-FEN, which is the usual KQkq for the outermost
+    / rooks and only uses files for rooks of Chess960 positions with another rook further out on the same side. */
     pub fn fen_from_pos(pos: &Position) -> String {
+        fen_with_castling(pos, false)
+    }
+
+    // Returns the FEN of a position in Shredder-FEN, which gives the files of the castling rooks, like HAha
+    pub fn shredder_fen_from_pos(pos: &Position) -> String {
+        fen_with_castling(pos, true)
+    }
+
+    // Returns the pieces in hand of a Crazyhouse position like QNn, white's first and the most valuable first
+    pub fn holdings(pos: &Position) -> String {
+        let mut holdings = String::new();
+        for color in [crate::Color::White, crate::Color::Black] {
+            for piece in [crate::Piece::QUEEN, crate::Piece::ROOK, crate::Piece::BISHOP, crate::Piece::KNIGHT, crate::Piece::PAWN] {
+                let letter = crate::get_piece_representation(piece + 6 * color as u8 + 1);
+                for _ in 0..pos.state.hands[color as usize][piece as usize] {
+                    holdings.push(letter);
+                }
+            }
+        }
+        holdings
+    }
+
+    fn castling_field(pos: &Position, shredder: bool) -> String {
+        let mut castling = String::new();
+        for right in crate::Castling::RIGHTS {
+            if pos.state.castling_rights.0 & right == 0 {
+                continue;
+            }
+            let color = crate::Castling::color(right);
+            let back_rank = if color == crate::Color::White { 0 } else { 56 };
+            let rook_file = pos.state.castling_rights.rook_file(right);
+            let mut outer_files = if crate::Castling::is_king_side(right) { rook_file + 1..8 } else { 0..rook_file };
+            let is_outermost = !outer_files.any(|file| pos.piece_at(back_rank + file) == Some((crate::Piece::ROOK, color)));
+
+            let letter = match (shredder || !is_outermost, crate::Castling::is_king_side(right)) {
+                (true, _) => (b'a' + rook_file) as char,
+                (false, true) => 'k',
+                (false, false) => 'q',
+            };
+            castling.push(if color == crate::Color::White { letter.to_ascii_uppercase() } else { letter });
+        }
+        castling
+    }
+
+    fn fen_with_castling(pos: &Position, shredder: bool) -> String {
         let mut fen = String::new();
         for rank in (0..8).rev() {
             let mut empty = 0;
@@ -108,6 +185,9 @@ pub mod types_utils {
                             empty = 0;
                         }
                         fen.push(crate::get_piece_representation(piece.0 + 6 * piece.1 as u8 + 1));
+                        if pos.state.promoted.contains(square) {
+                            fen.push('~');
+                        }
                     }
                     None => {
                         empty += 1;
@@ -121,33 +201,29 @@ pub mod types_utils {
                 fen.push('/');
             }
         }
+        // Crazyhouse positions have the pieces in hand in brackets after the placement, promoted pieces are marked with ~
+        if pos.state.variant == crate::variant::Variant::Crazyhouse {
+            fen.push_str(&format!("[{}]", holdings(pos)));
+        }
         fen.push(' ');
         fen.push(if pos.state.active_player == crate::Color::White { 'w' } else { 'b' });
         fen.push(' ');
-        let mut castling = String::new();
-        if pos.state.castling_rights.0 & crate::Castling::WHITE_KING_SIDE != 0 {
-            castling.push('K');
-        }
-        if pos.state.castling_rights.0 & crate::Castling::WHITE_QUEEN_SIDE != 0 {
-            castling.push('Q');
-        }
-        if pos.state.castling_rights.0 & crate::Castling::BLACK_KING_SIDE != 0 {
-            castling.push('k');
-        }
-        if pos.state.castling_rights.0 & crate::Castling::BLACK_QUEEN_SIDE != 0 {
-            castling.push('q');
-        }
-        if castling == "" {
+        let castling = castling_field(pos, shredder);
+        if castling.is_empty() {
             fen.push('-');
         } else {
             fen.push_str(&castling);
         }
         fen.push(' ');
         if let Some(en_passant) = pos.en_passant_square {
-            fen.push_str(&crate::types_utils::string_from_square(en_passant));
+            fen.push_str(&crate::types_utils::string_from_square(en_passant).to_ascii_lowercase());
         } else {
             fen.push('-');
         }
+        // Three-check positions have the remaining checks of both sides after the en passant square
+        if pos.state.variant == crate::variant::Variant::ThreeCheck {
+            fen.push_str(&format!(" {}+{}", 3 - pos.state.checks[0], 3 - pos.state.checks[1]));
+        }
         fen.push(' ');
         fen.push_str(&pos.state.half_move_counter.to_string());
         fen.push(' ');
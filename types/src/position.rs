@@ -1,9 +1,17 @@
-use core::panic;
-
 use crate::{
-    bitboard::BitBoard, get_piece_representation, state::{GameResult, State}, types_utils::{fen_from_pos, string_from_square}, Castling, Color, Piece, Results
+    bitboard::BitBoard, get_piece_representation, state::{GameResult, State}, types_utils::{holdings, try_square_offset},
+    variant::Variant, Castling, Color, DropMove, Piece, Results
 };
 
+// Squares of the king and the rook before and after castling
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CastlingSquares {
+    pub king_from: u8,
+    pub king_to: u8,
+    pub rook_from: u8,
+    pub rook_to: u8,
+}
+
 /* A position contains the minimum amount of information necessary
 / for the engine to calculate moves and evaluate the board state. */ 
 
@@ -33,6 +41,12 @@ pub struct Position {
     pub check: bool,
 }
 
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Position {
 
     pub fn new () -> Position {
@@ -124,207 +138,58 @@ impl Position {
 
     }
 
+    /* Creates a position from a FEN string that is known to be valid and panics otherwise.
+    / Use str::parse or Position::try_from to handle invalid input, see the fen module. */
     pub fn from_fen(fen: String) -> Position {
-        let mut position = Position::new();
-        position.state.castling_rights.0 = Castling::NO_CASTLING;
-        position.color_bitboards = [BitBoard::empty(); 2];
-        position.piece_bitboards = [BitBoard::empty(); 6];
-        let mut rank = 7;
-        let mut file = 0;
-        let mut iter_index = 0;
-        for c in fen.chars().peekable() {
-            match c {
-                '1'..='8' => {
-                    let empty_squares = c.to_digit(10).unwrap();
-                    file += empty_squares as u8;
-                },
-                'p' => {
-                    position.color_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[5] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'n' => {
-                    position.color_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'b' => {
-                    position.color_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[2] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'r' => {
-                    position.color_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'q' => {
-                    position.color_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[3] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'k' => {
-                    position.color_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[4] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'P' => {
-                    position.color_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[5] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'N' => {
-                    position.color_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[1] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'B' => {
-                    position.color_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[2] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'R' => {
-                    position.color_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'Q' => {
-                    position.color_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[3] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                'K' => {
-                    position.color_bitboards[0] |= BitBoard::from_square(rank * 8 + file);
-                    position.piece_bitboards[4] |= BitBoard::from_square(rank * 8 + file);
-                    file += 1;
-                },
-                '/' => {
-                    rank -= 1;
-                    file = 0;
-                },
-                ' ' => {
-                    match fen.chars().nth(iter_index + 1).unwrap() {
-                        'w' => position.state.active_player = Color::White,
-                        'b' => position.state.active_player = Color::Black,
-                        _ => panic!("Unexpected character in FEN string at index {}: {}", iter_index + 1, fen.chars().nth(iter_index + 1).unwrap()),
-                    }
-                    let iter_inner = iter_index + 3;
-                    for i in 0..4 {
-                        match fen.chars().nth(iter_inner + i).unwrap() {
-                            'K' => {
-                                position.state.castling_rights.0 |= Castling::WHITE_KING_SIDE;
-                                iter_index += 1;
-                            },
-                            'Q' => {
-                                position.state.castling_rights.0 |= Castling::WHITE_QUEEN_SIDE;
-                                iter_index += 1;               
-                            },
-                            'k' => {
-                                position.state.castling_rights.0 |= Castling::BLACK_KING_SIDE;
-                                iter_index += 1;                                
-                            },
-                            'q' => {
-                                position.state.castling_rights.0 |= Castling::BLACK_QUEEN_SIDE;
-                                iter_index += 1;
-                            },
-                            '-' => {
-                                position.state.castling_rights.0 |= Castling::NO_CASTLING;
-                                iter_index += 1;
-                            },
-                            ' ' => {
-                                iter_index += 1;
-                                break;
-                            },
-                            _ => panic!("Unexpected character in FEN string at index {}: {}", iter_inner + i, fen.chars().nth(iter_inner + i).unwrap()),
-                        }
-                    }
-                    iter_index += 3;
-                    match fen.chars().nth(iter_index).unwrap() {
-                        '-' => iter_index += 1,
-                        _ => {
-                            let rank_index = fen.chars().nth(iter_index).unwrap() as u8 - 97;
-                            let file_index = fen.chars().nth(iter_index + 1).unwrap().to_digit(10).unwrap() as u8 + 1;
-                            position.en_passant_square = Some(rank_index * 8 + file_index);
-                        }
-                    }
-                    iter_index += 1;
-                    let mut hm_string = "".to_string();
-                    let mut fm_string = "".to_string();
-                    println!("Looking for halfmove clock at index {}", iter_index);
-                    for _ in 0..=2 {
-                        match fen.chars().nth(iter_index) {
-                            Some(a) => {
-                                match a {
-                                    '0'..='9' => {
-                                        hm_string.push_str(&fen.chars().nth(iter_index).unwrap().to_string());
-                                        iter_index += 1;
-                                    },
-                                    _ => {
-                                        iter_index += 1;
-                                        break;
-                                    },
-                                }
-                            },
-                            _ => break,
-                        }
-                    }
-                    println!("Looking for fullmove clock at index {}", iter_index);
-                    for _ in 0..=2 {
-                        match fen.chars().nth(iter_index) {
-                            Some(a) => {
-                                match a {
-                                    '0'..='9' => {
-                                        fm_string.push_str(&fen.chars().nth(iter_index).unwrap().to_string());
-                                        iter_index += 1;
-                                    },
-                                    _ => break,
-                                }
-                            },
-                            _ => break,
-                        }
-                    }
-                    println!("Halfmove counter: {}", hm_string);
-                    println!("Fullmove counter: {}", fm_string);
-                    position.state.half_move_counter = hm_string.parse::<u8>().unwrap();
-                    position.state.full_move_counter = fm_string.parse::<u16>().unwrap();
-                    break;
-                }
-                _ => panic!("Unexpected character in FEN string at index {}: {}", iter_index, c),
-            }
-            iter_index += 1;
+        match fen.parse() {
+            Ok(position) => position,
+            Err(error) => panic!("Invalid FEN {}: {}", fen, error),
         }
-        println!("FEN import successful.");
-        position
     }
 
     // Prints out a visual representation of a given board state.
     pub fn print_position(&self) {
+        self.print_board(false);
+    }
+
+    // Prints the board from white's side, or from black's side if it is flipped
+    pub fn print_board(&self, flipped: bool) {
         let mut board = [[0; 8]; 8];
         for square in 0..64 {
-            match self.piece_at(square) {
-                Some((piece, color)) => {
-                    let x = square as usize % 8;
-                    let y = square as usize / 8;
-                    board[y][x] = match color {
-                        Color::White => piece + 1,
-                        Color::Black => piece + 7,
-                    };
-                },
-                None => ()
+            if let Some((piece, color)) = self.piece_at(square) {
+                let x = square as usize % 8;
+                let y = square as usize / 8;
+                board[y][x] = match color {
+                    Color::White => piece + 1,
+                    Color::Black => piece + 7,
+                };
+            }
+        }
+        if flipped {
+            for row in board.iter_mut() {
+                row.reverse();
             }
+            board.reverse();
         }
         println!("---------------");
         for row in board.iter().rev() {
             for square in row.iter() {
-                print!("{} ", get_piece_representation(*square as u8));
+                print!("{} ", get_piece_representation(*square));
             }
             println!();
         }
+        if self.state.variant == Variant::Crazyhouse {
+            println!("In hand: {}", holdings(self));
+        }
     }
 
     // Returns the piece at a given square or None if the square is empty
     #[inline]
     pub fn piece_at(&self, square: u8) -> Option<(u8, Color)> {
+        // The origin of a drop move isn't a square on the board
+        if square >= 64 {
+            return None;
+        }
         let mask: u64 = 1 << square;
         let color_mask = if self.color_bitboards[0].0 & mask != 0 {
             Color::White
@@ -354,6 +219,15 @@ impl Position {
     }
 
     pub fn make_move(&mut self, from: &u8, to: &u8) {
+        if let Some(piece) = DropMove::piece(*from) {
+            self.make_drop_move(piece, *to);
+            return;
+        }
+        if let Some(right) = self.castling_right_of(*from, *to) {
+            self.make_castling_move(right);
+            return;
+        }
+
         let (piece, color) = self.piece_at(*from).unwrap();
         // Check for captures and update halfmove counter
         if self.piece_at(*to).is_some() {
@@ -361,18 +235,15 @@ impl Position {
             // Remove the captured piece from the color and piece bitboards
             let (captured_piece, captured_color) = self.piece_at(*to).unwrap();
             if captured_piece == 0 {
-                match *to {
-                    56 => self.state.castling_rights.0 &= !Castling::BLACK_QUEEN_SIDE,
-                    63 => self.state.castling_rights.0 &= !Castling::BLACK_KING_SIDE,
-                    0 => self.state.castling_rights.0 &= !Castling::WHITE_QUEEN_SIDE,
-                    7 => self.state.castling_rights.0 &= !Castling::WHITE_KING_SIDE,
-                    _ => (),
-                }
+                self.remove_castling_rights_of_rook(*to);
             }
             // self.was_last_move_capture = Some(captured_piece);
             let to_mask = BitBoard::from_square(*to);
             self.color_bitboards[captured_color as usize] ^= to_mask;
             self.piece_bitboards[captured_piece as usize] ^= to_mask;
+            if self.state.variant == Variant::Crazyhouse {
+                self.put_in_hand(color, captured_piece, *to);
+            }
         } else {
             self.state.half_move_counter += 1;
             // self.halfmove_clock_history.push(self.state.half_move_counter);
@@ -392,22 +263,7 @@ impl Position {
                 self.en_passant_square = None;
             },
             0 => {
-                match color {
-                    Color::Black => {
-                        if *from == 56 {
-                            self.state.castling_rights.0 &= !Castling::BLACK_QUEEN_SIDE;
-                        } else if *from == 63 {
-                            self.state.castling_rights.0 &= !Castling::BLACK_KING_SIDE;
-                        }
-                    },
-                    Color::White => {
-                        if *from == 0 {
-                            self.state.castling_rights.0 &= !Castling::WHITE_QUEEN_SIDE;
-                        } else if *from == 7 {
-                            self.state.castling_rights.0 &= !Castling::WHITE_KING_SIDE;
-                        }
-                    },
-                }
+                self.remove_castling_rights_of_rook(*from);
                 self.en_passant_square = None;
             },
             5 => {
@@ -431,6 +287,9 @@ impl Position {
         self.color_bitboards[color as usize] |= to_mask;
         self.piece_bitboards[piece as usize] ^= from_mask;
         self.piece_bitboards[piece as usize] |= to_mask;
+        if self.state.promoted.contains(*from) {
+            self.state.promoted ^= from_mask | to_mask;
+        }
 
         self.state.switch_active_player();
 
@@ -440,40 +299,138 @@ impl Position {
         }
     }
 
-    pub fn make_castling_move(&mut self, from: &u8, to: &u8) {
-        // println!("Making castling move from {} to {}", from, to);
-        let piece = self.piece_at(*from);
-        match piece {
-            Some((Piece::KING, _color)) => (),
-            Some((Piece::ROOK, _color)) => (),
-            _ => panic!("Trying to make castling move from {} to {} with FEN {:?}",
-                string_from_square(*from), string_from_square(*to), fen_from_pos(self)),
-        }
-        let piece_type = piece.unwrap().0;
-        let color = piece.unwrap().1;
-        let piece_index = match piece_type {
-            Piece::ROOK => 0,
-            Piece::KING => 4,
-            _ => panic!("Invalid piece"),
-        };
+    // Drops a Crazyhouse piece from the hand of the side to move onto an empty square
+    fn make_drop_move(&mut self, piece: u8, to: u8) {
+        let color = self.state.active_player;
+        self.state.hands[color as usize][piece as usize] -= 1;
+        let to_mask = BitBoard::from_square(to);
+        self.color_bitboards[color as usize] |= to_mask;
+        self.piece_bitboards[piece as usize] |= to_mask;
+        self.en_passant_square = None;
+        self.state.half_move_counter += 1;
+        self.state.switch_active_player();
+    }
 
-        match to {
-            // Check if the destination square for the rook is D1, D8, F1 or F8
-            3 | 5 | 59 | 61  => {
-                let rook_to_mask = BitBoard::from_square(*to);
-                let rook_mask = BitBoard::from_square(*from);
-                self.color_bitboards[color as usize] ^= rook_mask;
-                self.color_bitboards[color as usize] |= rook_to_mask;
-                self.piece_bitboards[piece_index] ^= rook_mask;
-                self.piece_bitboards[piece_index] |= rook_to_mask;
-            },
-            _ => panic!("Invalid castling move"),
+    /* Puts a piece captured on the given square into the hand of the capturing color. Promoted pieces go back into
+    / the hand as pawns. */
+    pub fn put_in_hand(&mut self, color: Color, piece: u8, square: u8) {
+        let piece = if self.state.promoted.contains(square) { Piece::PAWN } else { piece };
+        self.state.promoted &= !BitBoard::from_square(square);
+        self.state.hands[color as usize][piece as usize] += 1;
+    }
+
+    // Removes the castling right that belongs to the rook on the given square, if there is one
+    fn remove_castling_rights_of_rook(&mut self, square: u8) {
+        for right in Castling::RIGHTS {
+            let back_rank = if Castling::color(right) == Color::White { 0 } else { 56 };
+            if square == back_rank + self.state.castling_rights.rook_file(right) {
+                self.state.castling_rights.0 &= !right;
+            }
+        }
+    }
+
+    /* Castling works the same in standard chess and Chess960: the king and the rook end up on the g and f files when
+    / castling king side and on the c and d files when castling queen side, no matter where they started.
+    / A castling move is stored as the king moving two squares if the king starts on the e file and the rook in the
+    / corner, like e1g1 in standard chess. Otherwise it's stored as the king capturing its own rook, like b1a1, since
+    / the target square of the king could also be the target of a regular king move in Chess960. */
+    pub fn castling_squares(&self, right: u8) -> CastlingSquares {
+        let color = Castling::color(right);
+        let king_from = (self.piece_bitboards[Piece::KING as usize] & self.color_bitboards[color as usize]).trailing_zeros();
+        let back_rank = if color == Color::White { 0 } else { 56 };
+        let (king_file, rook_file) = if Castling::is_king_side(right) { (6, 5) } else { (2, 3) };
+        CastlingSquares {
+            king_from,
+            king_to: back_rank + king_file,
+            rook_from: back_rank + self.state.castling_rights.rook_file(right),
+            rook_to: back_rank + rook_file,
+        }
+    }
+
+    // Returns the move that castles with the given right, see castling_squares()
+    pub fn castling_move(&self, right: u8) -> (u8, u8) {
+        let squares = self.castling_squares(right);
+        let is_standard = squares.king_from % 8 == 4 && matches!(squares.rook_from % 8, 0 | 7);
+        if is_standard {
+            (squares.king_from, squares.king_to)
+        } else {
+            (squares.king_from, squares.rook_from)
+        }
+    }
+
+    // Returns the castling right used by the move if it's a castling move
+    pub fn castling_right_of(&self, from: u8, to: u8) -> Option<u8> {
+        let (piece, color) = self.piece_at(from)?;
+        if piece != Piece::KING {
+            return None;
+        }
+        Castling::RIGHTS.into_iter().find(|&right| {
+            self.state.castling_rights.0 & right != 0
+                && Castling::color(right) == color
+                && self.castling_move(right) == (from, to)
+        })
+    }
+
+    /* Returns the castling right of a move written as the king capturing its own rook, the way UCI_Chess960 and
+    / Polyglot books write castling moves, even in standard chess. */
+    pub fn castling_right_by_rook(&self, king_square: u8, rook_square: u8) -> Option<u8> {
+        let (piece, color) = self.piece_at(king_square)?;
+        if piece != Piece::KING {
+            return None;
+        }
+        Castling::RIGHTS.into_iter().find(|&right| {
+            self.state.castling_rights.0 & right != 0
+                && Castling::color(right) == color
+                && self.castling_squares(right).rook_from == rook_square
+        })
+    }
+
+    /* Returns the targets of the castling moves of the given color whose paths are free: all squares the king and the
+    / rook move over, including their targets, must be empty apart from the castling king and rook themselves.
+    / Whether the king is in check or passes attacked squares isn't tested here. */
+    pub fn castling_targets(&self, color: Color) -> BitBoard {
+        let mut targets = BitBoard::empty();
+        for right in Castling::RIGHTS {
+            if self.state.castling_rights.0 & right == 0 || Castling::color(right) != color {
+                continue;
+            }
+            let squares = self.castling_squares(right);
+            let path = |a: u8, b: u8| std::cmp::min(a, b)..=std::cmp::max(a, b);
+            let is_free = path(squares.king_from, squares.king_to)
+                .chain(path(squares.rook_from, squares.rook_to))
+                .all(|square| square == squares.king_from || square == squares.rook_from || self.piece_at(square).is_none());
+            if is_free {
+                targets |= BitBoard::from_square(self.castling_move(right).1);
+            }
+        }
+        targets
+    }
+
+    // Moves the king and the rook to their castling squares, they may swap places or stay where they are in Chess960
+    fn make_castling_move(&mut self, right: u8) {
+        let squares = self.castling_squares(right);
+        let color = Castling::color(right) as usize;
+        for (square, piece) in [(squares.king_from, Piece::KING), (squares.rook_from, Piece::ROOK)] {
+            self.color_bitboards[color] ^= BitBoard::from_square(square);
+            self.piece_bitboards[piece as usize] ^= BitBoard::from_square(square);
+        }
+        for (square, piece) in [(squares.king_to, Piece::KING), (squares.rook_to, Piece::ROOK)] {
+            self.color_bitboards[color] |= BitBoard::from_square(square);
+            self.piece_bitboards[piece as usize] |= BitBoard::from_square(square);
         }
 
+        self.state.castling_rights.0 &= match Castling::color(right) {
+            Color::White => !Castling::WHITE_CASTLING,
+            Color::Black => !Castling::BLACK_CASTLING,
+        };
+        self.en_passant_square = None;
         self.state.half_move_counter += 1;
-        // self.halfmove_clock_history.push(self.state.half_move_counter);
-        self.attack_bitboards[*from as usize] = BitBoard::empty();
+        self.state.switch_active_player();
 
+        // Check for draw by 50 move rule
+        if self.state.half_move_counter == 100 {
+            self.state.game_result = GameResult(Results::DRAW);
+        }
     }
 
     /* pub fn unmake_move(&mut self, from: &Square, to: &Square) {
@@ -527,6 +484,75 @@ impl Position {
         self.attack_bitboards[attacker_square as usize] = attacks;
     }
 
+    /* Computes the attack maps of all pieces and the check flag from scratch, matching the maps the engine keeps up
+    / to date during the game: sliders attack up to and including the first blocker, knights and pawns attack all their
+    / target squares and kings attack the squares they can move to, including castling squares when not in check. */
+    pub fn rebuild_attack_maps(&mut self) {
+        self.attack_bitboards = [BitBoard::empty(); 64];
+        let mut pieces = self.all_pieces() & !self.piece_bitboards[Piece::KING as usize];
+        while !pieces.is_empty() {
+            let square = pieces.trailing_zeros();
+            let (piece, color) = self.piece_at(square).unwrap();
+            self.attack_bitboards[square as usize] = self.piece_attacks(piece, color, square);
+            pieces.clear_lsb();
+        }
+
+        // Kings can't give check, so the check flag is known before the king attacks are added
+        let king = self.piece_bitboards[Piece::KING as usize] & self.color_bitboards[self.state.active_player as usize];
+        self.check = !king.is_empty() && self.is_square_attacked_by_color(king.trailing_zeros(), !self.state.active_player);
+
+        let mut kings = self.piece_bitboards[Piece::KING as usize];
+        while !kings.is_empty() {
+            let square = kings.trailing_zeros();
+            let attacks = self.king_attacks(self.piece_color(square), square);
+            self.attack_bitboards[square as usize] = attacks;
+            kings.clear_lsb();
+        }
+    }
+
+    fn piece_attacks(&self, piece: u8, color: Color, square: u8) -> BitBoard {
+        let (directions, sliding): (&[(i8, i8)], bool) = match piece {
+            Piece::ROOK => (&[(1, 0), (-1, 0), (0, 1), (0, -1)], true),
+            Piece::BISHOP => (&[(1, 1), (1, -1), (-1, 1), (-1, -1)], true),
+            Piece::QUEEN => (&[(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)], true),
+            Piece::KNIGHT => (&[(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)], false),
+            _ => match color {
+                Color::White => (&[(-1, 1), (1, 1)], false),
+                Color::Black => (&[(-1, -1), (1, -1)], false),
+            },
+        };
+
+        let mut attacks = BitBoard::empty();
+        for &(dx, dy) in directions {
+            let mut current = square;
+            while let Some(target) = try_square_offset(current, dx, dy) {
+                attacks |= BitBoard::from_square(target);
+                if !sliding || self.all_pieces().contains(target) {
+                    break;
+                }
+                current = target;
+            }
+        }
+        attacks
+    }
+
+    fn king_attacks(&self, color: Color, square: u8) -> BitBoard {
+        let mut attacks = BitBoard::empty();
+        for (dx, dy) in [(1, 1), (1, 0), (1, -1), (0, 1), (0, -1), (-1, 1), (-1, 0), (-1, -1)] {
+            if let Some(target) = try_square_offset(square, dx, dy) {
+                attacks |= BitBoard::from_square(target);
+            }
+        }
+
+        attacks &= !self.color_bitboards[color as usize];
+
+        let in_check = color == self.state.active_player && self.check;
+        if !in_check {
+            attacks |= self.castling_targets(color);
+        }
+        attacks
+    }
+
     /* Returns true if the given square is under attack by the given color.
     / attack_bitboards contains a bitboard for every square that contains information which other squares the piece
     on that square attacks, if any. */ 
@@ -556,13 +582,10 @@ impl Position {
     }
 
     pub fn is_promotion(&self, start: &u8, end: &u8) -> bool {
-        let (piece, color) = self.piece_at(*start).unwrap();
-        if piece == Piece::PAWN {
-            if color == Color::White && end / 8 == 7 || color == Color::Black && end / 8 == 0 {
-                return true;
-            }
-        }
-        false
+        let Some((piece, color)) = self.piece_at(*start) else {
+            return false;
+        };
+        piece == Piece::PAWN && (color == Color::White && end / 8 == 7 || color == Color::Black && end / 8 == 0)
     }
 
     pub fn promote_pawn(&mut self, square: u8, target_piece: u8) {
@@ -585,6 +608,9 @@ impl Position {
         self.color_bitboards[color as usize] ^= mask;
         self.piece_bitboards[piece_index] |= mask;
         self.color_bitboards[color as usize] |= mask;
+        if self.state.variant == Variant::Crazyhouse {
+            self.state.promoted |= mask;
+        }
     }
 
     pub fn colorflip(&mut self) -> Position {
@@ -596,8 +622,11 @@ impl Position {
         for i in 0..6 {
             new_position.piece_bitboards[i] = self.piece_bitboards[i].colorflip();
         }
-        new_position.state.castling_rights = !self.state.castling_rights;
         new_position.state = self.state;
+        new_position.state.castling_rights = !self.state.castling_rights;
+        new_position.state.checks = [self.state.checks[1], self.state.checks[0]];
+        new_position.state.hands = [self.state.hands[1], self.state.hands[0]];
+        new_position.state.promoted = self.state.promoted.colorflip();
         new_position.state.switch_active_player();
         new_position
     }
@@ -612,6 +641,9 @@ impl Position {
     }
 
     pub fn piece_type_at(&self, square: u8) -> Option<u8> {
+        if square >= 64 {
+            return None;
+        }
         if self.piece_bitboards[0].contains(square) {return Some(0)}
         if self.piece_bitboards[1].contains(square) {return Some(1)}
         if self.piece_bitboards[2].contains(square) {return Some(2)}
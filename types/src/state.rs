@@ -1,8 +1,10 @@
-use crate::{Color, Castling, Results};
+use crate::{bitboard::BitBoard, variant::Variant, Color, Castling, Results};
 use std::ops::Not;
 
 /* A state depicts additional information that is necessary to evaluate a position:
-/ Castling rights, en passant square, halfmove clock and the active player. */
+/ Castling rights, en passant square, halfmove clock and the active player, as well as the variant that is played
+/ and, in Three-check, the number of checks each side has given. In Crazyhouse, the state also holds the pieces in
+/ hand and the squares of promoted pieces, which are put in hand as pawns when captured. */
 
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct State {
@@ -12,6 +14,20 @@ pub struct State {
     pub full_move_counter: u16,
     pub active_player: Color,
     pub game_result: GameResult,
+    pub variant: Variant,
+    // Whether castling moves and FENs of the game are written the Chess960 way, see UCI_Chess960
+    pub chess960: bool,
+    // Checks given by white and black, only counted in Three-check
+    pub checks: [u8; 2],
+    // Pieces in hand of white and black by piece type, only used in Crazyhouse
+    pub hands: [[u8; 6]; 2],
+    pub promoted: BitBoard,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl State {
@@ -23,6 +39,11 @@ impl State {
             full_move_counter: 1,
             active_player: Color::White,
             game_result: GameResult::new(),
+            variant: Variant::Standard,
+            chess960: false,
+            checks: [0, 0],
+            hands: [[0; 6]; 2],
+            promoted: BitBoard::empty(),
         }
     }
 
@@ -64,19 +85,34 @@ impl Default for GameResult {
 / bit 5 is white's king side castling rights,
 / bit 6 is white's queen side castling rights,
 / bit 7 is black's king side castling rights and
-/ bit 8 is black's queen side castling rights. */
+/ bit 8 is black's queen side castling rights.
+/ The second field holds the file of the rook for each right in the same order, from white's king side to black's
+/ queen side. In standard chess these are the h and a files, Chess960 start positions can have them anywhere. */
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
-pub struct CastlingRights(pub u8);
+pub struct CastlingRights(pub u8, pub [u8; 4]);
 
 impl CastlingRights {
     fn all() -> Self {
         Self::default()
     }
+
+    // Index of a single right in the rook files
+    fn index(right: u8) -> usize {
+        3 - right.trailing_zeros() as usize
+    }
+
+    pub fn rook_file(&self, right: u8) -> u8 {
+        self.1[Self::index(right)]
+    }
+
+    pub fn set_rook_file(&mut self, right: u8, file: u8) {
+        self.1[Self::index(right)] = file;
+    }
 }
 
 impl Default for CastlingRights {
     fn default() -> Self {
-        Self(Castling::ANY_CASTLING)
+        Self(Castling::ANY_CASTLING, [7, 0, 7, 0])
     }
 }
 
@@ -84,17 +120,18 @@ impl Not for CastlingRights {
     type Output = Self;
 
     fn not(self) -> Self::Output {
-        let white_kingside = self.0 & 0b00010000;
-        let white_queenside = self.0 & 0b00100000;
-        let black_kingside = self.0 & 0b01000000;
-        let black_queenside = self.0 & 0b10000000;
+        let white_kingside = self.0 & Castling::WHITE_KING_SIDE;
+        let white_queenside = self.0 & Castling::WHITE_QUEEN_SIDE;
+        let black_kingside = self.0 & Castling::BLACK_KING_SIDE;
+        let black_queenside = self.0 & Castling::BLACK_QUEEN_SIDE;
 
-        let flipped_white_kingside = black_kingside >> 2;
-        let flipped_white_queenside = black_queenside >> 1;
-        let flipped_black_kingside = white_kingside << 2;
-        let flipped_black_queenside = white_queenside << 1;
+        let flipped_white_kingside = black_kingside << 2;
+        let flipped_white_queenside = black_queenside << 2;
+        let flipped_black_kingside = white_kingside >> 2;
+        let flipped_black_queenside = white_queenside >> 2;
 
         let flipped_rights = flipped_black_kingside | flipped_black_queenside | flipped_white_kingside | flipped_white_queenside;
-        Self(flipped_rights)
+        let [white_kingside_file, white_queenside_file, black_kingside_file, black_queenside_file] = self.1;
+        Self(flipped_rights, [black_kingside_file, black_queenside_file, white_kingside_file, white_queenside_file])
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,79 @@
+use std::{fmt, str::FromStr};
+
+/* Chess variants with their own win conditions and move rules:
+/ King of the Hill is won by bringing the king to one of the four center squares,
+/ Three-check is won by giving check for the third time,
+/ Antichess is won by losing all pieces or having no legal moves. Captures are compulsory and the king is a normal
+/ piece that can be captured, there is no check and no castling,
+/ Crazyhouse is won by checkmate like standard chess, but captured pieces go to the hand of the capturing side and can
+/ be dropped back onto the board as its own. Promoted pieces turn back into pawns when they are captured. */
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    KingOfTheHill,
+    ThreeCheck,
+    Antichess,
+    Crazyhouse,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 5] = [
+        Variant::Standard,
+        Variant::KingOfTheHill,
+        Variant::ThreeCheck,
+        Variant::Antichess,
+        Variant::Crazyhouse,
+    ];
+
+    // Name of the variant as a value of the UCI_Variant option
+    pub fn uci_name(self) -> &'static str {
+        match self {
+            Variant::Standard => "chess",
+            Variant::KingOfTheHill => "kingofthehill",
+            Variant::ThreeCheck => "3check",
+            Variant::Antichess => "antichess",
+            Variant::Crazyhouse => "crazyhouse",
+        }
+    }
+
+    // Name of the variant in the Variant tag of PGN games
+    pub fn pgn_name(self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::KingOfTheHill => "King of the Hill",
+            Variant::ThreeCheck => "Three-check",
+            Variant::Antichess => "Antichess",
+            Variant::Crazyhouse => "Crazyhouse",
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pgn_name())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UnknownVariant(pub String);
+
+impl fmt::Display for UnknownVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown variant '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownVariant {}
+
+// Accepts the UCI and PGN names of a variant in any case
+impl FromStr for Variant {
+    type Err = UnknownVariant;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Variant::ALL.into_iter()
+            .find(|variant| name.eq_ignore_ascii_case(variant.uci_name()) || name.eq_ignore_ascii_case(variant.pgn_name()))
+            .ok_or_else(|| UnknownVariant(name.to_string()))
+    }
+}